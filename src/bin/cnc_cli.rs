@@ -0,0 +1,554 @@
+//! Headless client: drives a controller from the shell or CI without opening a window.
+//! Results and events are written to stdout as JSON lines, errors to stderr.
+
+#![allow(clippy::enum_variant_names)]
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::{json, Value};
+
+use cnc_desktop::cnc_bus::{EEventKind, EStatusEvent, Subscription};
+use cnc_desktop::cnc_connection::{connect, parse_host, parse_port, CncConnectionManager};
+use cnc_desktop::cnc_ctrl::CncCtrl;
+use cnc_desktop::cnc_log::{self, format_timestamp};
+use cnc_desktop::cnc_machine_state::EMachineState;
+use cnc_desktop::cnc_msg::{AxisInfo, CncCoordinates, PIDParams};
+use cnc_desktop::cnc_pid_history::EPidVerifyState;
+use cnc_desktop::cnc_sim::{CncSimulator, SIM_PORT};
+
+const USAGE: &str = "\
+Usage: cnc_cli [options] <command> [arguments]
+
+Options:
+  --host HOST         controller address (default 127.0.0.1)
+  --port PORT         controller port (default 5555)
+  --timeout SECONDS   how long to wait for the handshake and replies (default 10)
+  --move-timeout SECONDS
+                      how long move, jog and home may take (default 600)
+  --sim               start the simulator and connect to it
+  --verbose           copy the log to stderr
+  --help              show this help
+
+Commands:
+  connect                         connect and print the machine info
+  move VALUE...                   move to an absolute position, one value per axis
+  jog AXIS DISTANCE               move one axis relative to its position
+  home                            move all axes to their minimum
+  reset                           acknowledge and reset alarms
+  get-pid                         print the PID params of all axes
+  set-pid AXIS P I D [--note N]   change the PID params of one axis and verify them
+  run FILE                        run a G-code program, printing progress and run time estimates
+  monitor [--count N] [--duration SECONDS]
+                                  print status, alarms and state changes
+  record FILE [--duration SECONDS]
+                                  write all events to FILE until stopped
+
+Axes are given by name or index, values in machine units. The desktop app's machine
+profile is used but never changed. Ctrl-C stops a running job, and ends monitor and
+record normally.
+
+Exit codes: 0 success, 1 command failed, 2 bad arguments, 3 connection failed or lost,
+4 machine in alarm or e-stop.";
+
+/// Process exit codes, so scripts can tell failures apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EExitCode {
+    EOk = 0,
+    EFailed = 1,
+    EUsage = 2,
+    EConnection = 3,
+    EAlarm = 4,
+}
+
+struct Failure {
+    code    : EExitCode,
+    message : String,
+}
+
+impl Failure {
+    fn new(code: EExitCode, message: impl Into<String>) -> Self {
+        Failure{ code, message: message.into() }
+    }
+}
+
+enum ECliCommand {
+    EConnect,
+    EMove(Vec<f32>),
+    EJog(String, f32),
+    EHome,
+    EReset,
+    EGetPid,
+    ESetPid{ axis: String, params: PIDParams, note: String },
+    ERun(String),
+    EMonitor{ count: Option<usize>, duration: Option<Duration> },
+    ERecord{ path: String, duration: Option<Duration> },
+}
+
+/// Set by Ctrl-C, checked on every tick.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+struct Options {
+    host    : String,
+    port    : u16,
+    timeout : Duration,
+    move_timeout : Duration,
+    sim     : bool,
+    verbose : bool,
+    command : ECliCommand,
+}
+
+fn usage_error(message: impl Into<String>) -> Failure {
+    Failure::new(EExitCode::EUsage, format!("{}\n\n{}", message.into(), USAGE))
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str, what: &str) -> Result<T, Failure> {
+    text.parse::<T>().map_err(|_| usage_error(format!("Invalid {} '{}'", what, text)))
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, Failure> {
+    match text.parse::<f32>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0f32 => Ok(Duration::from_secs_f32(seconds)),
+        _ => Err(usage_error(format!("Invalid number of seconds '{}'", text))),
+    }
+}
+
+/// Takes the value following `option`.
+fn option_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, Failure> {
+    args.next().ok_or_else(|| usage_error(format!("{} needs a value", option)))
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, Failure> {
+    let mut host = String::from("127.0.0.1");
+    let mut port = SIM_PORT;
+    let mut timeout = Duration::from_secs(10);
+    let mut move_timeout = Duration::from_secs(600);
+    let mut sim = false;
+    let mut verbose = false;
+    let mut args = args.into_iter();
+    let name = loop {
+        match args.next() {
+            Some(arg) => match arg.as_str() {
+                "--host" => host = parse_host(&option_value(&mut args, "--host")?).map_err(usage_error)?,
+                "--port" => port = parse_port(&option_value(&mut args, "--port")?).map_err(usage_error)?,
+                "--timeout" => timeout = parse_seconds(&option_value(&mut args, "--timeout")?)?,
+                "--move-timeout" => move_timeout = parse_seconds(&option_value(&mut args, "--move-timeout")?)?,
+                "--sim" => sim = true,
+                "--verbose" => verbose = true,
+                "--help" | "-h" => return Err(Failure::new(EExitCode::EOk, USAGE)),
+                option if option.starts_with("--") => return Err(usage_error(format!("Unknown option {}", option))),
+                _ => break arg,
+            },
+            None => return Err(usage_error("No command given")),
+        }
+    };
+
+    // Options of the command may come anywhere after it.
+    let mut positional = Vec::new();
+    let mut note = String::new();
+    let mut count = None;
+    let mut duration = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--note" => note = option_value(&mut args, "--note")?,
+            "--count" => count = Some(parse_number::<usize>(&option_value(&mut args, "--count")?, "count")?),
+            "--duration" => duration = Some(parse_seconds(&option_value(&mut args, "--duration")?)?),
+            // Negative values start with a single dash.
+            option if option.starts_with("--") => return Err(usage_error(format!("Unknown option {}", option))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let expect = |expected: usize| if positional.len() == expected {
+        Ok(())
+    } else {
+        Err(usage_error(format!("{} takes {} arguments, got {}", name, expected, positional.len())))
+    };
+    let command = match name.as_str() {
+        "connect" => { expect(0)?; ECliCommand::EConnect },
+        "move" => {
+            if positional.is_empty() {
+                return Err(usage_error("move needs a value per axis"));
+            }
+            ECliCommand::EMove(positional.iter().map(|value| parse_number::<f32>(value, "position")).collect::<Result<Vec<f32>, Failure>>()?)
+        },
+        "jog" => { expect(2)?; ECliCommand::EJog(positional[0].clone(), parse_number(&positional[1], "distance")?) },
+        "home" => { expect(0)?; ECliCommand::EHome },
+        "reset" => { expect(0)?; ECliCommand::EReset },
+        "get-pid" => { expect(0)?; ECliCommand::EGetPid },
+        "set-pid" => {
+            expect(4)?;
+            let params = PIDParams{
+                prop: parse_number(&positional[1], "P")?,
+                inte: parse_number(&positional[2], "I")?,
+                deri: parse_number(&positional[3], "D")?,
+            };
+            ECliCommand::ESetPid{ axis: positional[0].clone(), params, note }
+        },
+        "run" => { expect(1)?; ECliCommand::ERun(positional[0].clone()) },
+        "monitor" => { expect(0)?; ECliCommand::EMonitor{ count, duration } },
+        "record" => { expect(1)?; ECliCommand::ERecord{ path: positional[0].clone(), duration } },
+        _ => return Err(usage_error(format!("Unknown command {}", name))),
+    };
+    Ok(Options{ host, port, timeout, move_timeout, sim, verbose, command })
+}
+
+/// Index of the axis given by name, case insensitive, or by index.
+fn find_axis(axes: &[AxisInfo], axis: &str) -> Result<usize, Failure> {
+    if let Some(index) = axes.iter().position(|info| info.name.eq_ignore_ascii_case(axis)) {
+        return Ok(index);
+    }
+    match axis.parse::<usize>() {
+        Ok(index) if index < axes.len() => Ok(index),
+        _ => Err(Failure::new(EExitCode::EUsage, format!("The machine has no axis '{}'", axis))),
+    }
+}
+
+fn print_json(value: &Value) {
+    println!("{}", value);
+}
+
+/// Adds the time the event was seen to its JSON.
+fn timestamped(mut value: Value) -> Value {
+    if let Value::Object(ref mut fields) = value {
+        fields.insert(String::from("time"), Value::String(format_timestamp(SystemTime::now())));
+    }
+    value
+}
+
+/// A connected `CncCtrl`, updated by polling like the UI does every frame.
+struct Session {
+    cnc         : CncCtrl,
+    manager     : CncConnectionManager,
+    timeout     : Duration,
+    move_timeout: Duration,
+    verbose     : bool,
+    last_log    : usize,
+}
+
+impl Session {
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn open(options: &Options) -> Result<Session, Failure> {
+        let mut session = Session{
+            cnc         : CncCtrl::new_read_only(),
+            manager     : CncConnectionManager::new(),
+            timeout     : options.timeout,
+            move_timeout: options.move_timeout,
+            verbose     : options.verbose,
+            last_log    : 0,
+        };
+        let stream = connect(&options.host, options.port).map_err(|e| Failure::new(EExitCode::EConnection, e))?;
+        let remote_address = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+        let connection = session.manager.run(stream, session.cnc.connection_axis_count());
+        session.cnc.set_connection(connection, remote_address).map_err(|e| Failure::new(EExitCode::EFailed, e))?;
+
+        let started = Instant::now();
+        while matches!(session.cnc.state(), EMachineState::EConnecting | EMachineState::EHandshaking) {
+            if started.elapsed() > session.timeout {
+                return Err(Failure::new(EExitCode::EConnection, "Controller didn't finish the handshake"));
+            }
+            session.tick()?;
+        }
+        Ok(session)
+    }
+
+    /// Applies what the controller sent. Fails once the controller is gone or on Ctrl-C,
+    /// stopping the job if one is running.
+    fn tick(&mut self) -> Result<(), Failure> {
+        thread::sleep(Session::TICK);
+        self.cnc.update_status();
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            if self.cnc.state().has_active_job() && self.cnc.stop_job().is_ok() {
+                self.wait_stopped();
+            }
+            return Err(Failure::new(EExitCode::EFailed, "Interrupted"));
+        }
+        if self.verbose {
+            let (events, last) = cnc_log::events_since(self.last_log);
+            self.last_log = last;
+            for event in events {
+                eprintln!("{}", event.format());
+            }
+        }
+        match self.cnc.state() {
+            EMachineState::EDisconnected => Err(Failure::new(EExitCode::EConnection, "Controller disconnected")),
+            _ => Ok(()),
+        }
+    }
+
+    fn alarm_failure(&self) -> Failure {
+        let alarms: Vec<String> = self.cnc.alarms.active.iter().map(|alarm| alarm.describe(|axis| self.cnc.axis_name(axis))).collect();
+        match alarms.is_empty() {
+            true => Failure::new(EExitCode::EAlarm, format!("Machine is in {}", self.cnc.state().name())),
+            false => Failure::new(EExitCode::EAlarm, alarms.join("\n")),
+        }
+    }
+
+    /// A command refused by `CncCtrl`, reported as an alarm failure when that is the reason.
+    fn rejected(&self, e: String) -> Failure {
+        match self.cnc.state() {
+            EMachineState::EAlarm | EMachineState::EEStop => {
+                let alarm = self.alarm_failure();
+                Failure::new(EExitCode::EAlarm, format!("{}\n{}", e, alarm.message))
+            },
+            _ => Failure::new(EExitCode::EFailed, e),
+        }
+    }
+
+    /// After a stop on Ctrl-C, waits for the machine to settle before the process exits,
+    /// at most `move_timeout`. `tick` can't be used as it fails on the interrupt.
+    fn wait_stopped(&mut self) {
+        let started = Instant::now();
+        while self.cnc.state() == EMachineState::EJogging && started.elapsed() < self.move_timeout {
+            thread::sleep(Session::TICK);
+            self.cnc.update_status();
+        }
+    }
+
+    /// Waits for a move to end, at most `move_timeout`.
+    fn wait_idle(&mut self) -> Result<(), Failure> {
+        let started = Instant::now();
+        loop {
+            if started.elapsed() > self.move_timeout {
+                return Err(Failure::new(EExitCode::EFailed, format!("Move didn't finish within {:.1} s", self.move_timeout.as_secs_f32())));
+            }
+            self.tick()?;
+            match self.cnc.state() {
+                EMachineState::EIdle => return Ok(()),
+                EMachineState::EAlarm | EMachineState::EEStop => return Err(self.alarm_failure()),
+                _ => {},
+            }
+        }
+    }
+
+    /// Requests the PID params and waits for the controller's answer.
+    fn read_pid_params(&mut self) -> Result<Vec<PIDParams>, Failure> {
+        let events = self.cnc.bus.subscribe(&[EEventKind::EPIDParams], 1);
+        self.cnc.request_pid_params();
+        let started = Instant::now();
+        loop {
+            if let Some(EStatusEvent::EPIDParams(params)) = events.drain().pop() {
+                return Ok(params);
+            }
+            if started.elapsed() > self.timeout {
+                return Err(Failure::new(EExitCode::EConnection, "Controller didn't send its PID params"));
+            }
+            self.tick()?;
+        }
+    }
+
+    fn pid_json(&self, params: &[PIDParams]) -> Value {
+        EStatusEvent::EPIDParams(params.to_vec()).to_json(|axis| self.cnc.axis_name(axis))
+    }
+
+    fn run_command(&mut self, command: &ECliCommand) -> Result<(), Failure> {
+        match command {
+            ECliCommand::EConnect => {
+                print_json(&json!({
+                    "address": self.cnc.remote_address,
+                    "state": self.cnc.state().name(),
+                    "axes": self.cnc.axes.iter().map(|axis| json!({ "axis": axis.name, "min": axis.min, "max": axis.max })).collect::<Vec<Value>>(),
+                }));
+                Ok(())
+            },
+            ECliCommand::EMove(values) => {
+                if values.len() != self.cnc.axis_count() {
+                    return Err(Failure::new(EExitCode::EUsage, format!("move needs {} values, one per axis", self.cnc.axis_count())));
+                }
+                let mut target = CncCoordinates::with_axes(values.len());
+                for (axis, value) in values.iter().enumerate() {
+                    target.set(axis, *value);
+                }
+                self.move_to(target)
+            },
+            ECliCommand::EJog(axis, distance) => {
+                let axis = find_axis(&self.cnc.axes, axis)?;
+                let mut target = self.cnc.current_coords.clone();
+                target.set(axis, target.values[axis] + distance);
+                self.move_to(target)
+            },
+            ECliCommand::EHome => {
+                self.cnc.home().map_err(|e| self.rejected(e))?;
+                self.wait_idle()?;
+                self.print_position();
+                Ok(())
+            },
+            ECliCommand::EReset => {
+                // Nothing to reset is fine for scripts that reset before every run.
+                if matches!(self.cnc.state(), EMachineState::EAlarm | EMachineState::EEStop) {
+                    self.cnc.acknowledge_alarms();
+                    self.cnc.reset().map_err(|e| self.rejected(e))?;
+                }
+                print_json(&json!({ "state": self.cnc.state().name() }));
+                Ok(())
+            },
+            ECliCommand::EGetPid => {
+                let params = self.read_pid_params()?;
+                print_json(&self.pid_json(&params));
+                Ok(())
+            },
+            ECliCommand::ESetPid{ axis, params, note } => {
+                let axis = find_axis(&self.cnc.axes, axis)?;
+                let mut all_params = self.read_pid_params()?;
+                if axis >= all_params.len() {
+                    return Err(Failure::new(EExitCode::EFailed, format!("Controller sent PID params for {} axes", all_params.len())));
+                }
+                all_params[axis] = params.clone();
+                self.cnc.set_pid_params(&all_params, note).map_err(|e| self.rejected(e))?;
+                loop {
+                    self.tick()?;
+                    match self.cnc.pid_verification.state(axis) {
+                        EPidVerifyState::EApplied => break,
+                        EPidVerifyState::EMismatch => return Err(Failure::new(EExitCode::EFailed, "Controller reports different PID params than were sent")),
+                        EPidVerifyState::ENoReply => return Err(Failure::new(EExitCode::EConnection, "Controller didn't confirm the PID params")),
+                        EPidVerifyState::EPending | EPidVerifyState::EUnknown => {},
+                    }
+                }
+                print_json(&self.pid_json(&self.cnc.pid_params));
+                Ok(())
+            },
+            ECliCommand::ERun(path) => self.run_program(path),
+            ECliCommand::EMonitor{ count, duration } => {
+                let events = self.cnc.bus.subscribe(&[EEventKind::EStatus, EEventKind::EPosition, EEventKind::EAlarm, EEventKind::EMachineState], 1000);
+                let mut printed = 0;
+                self.stream_events(&events, *duration, |event| {
+                    print_json(event);
+                    printed += 1;
+                    count.is_none_or(|count| printed < count)
+                })
+            },
+            ECliCommand::ERecord{ path, duration } => {
+                let file = File::create(path).map_err(|e| Failure::new(EExitCode::EFailed, format!("Can't create {}: {}", path, e)))?;
+                let mut writer = BufWriter::new(file);
+                let events = self.cnc.bus.subscribe(&[EEventKind::EPosition, EEventKind::EStatus, EEventKind::EPIDParams, EEventKind::EAxisConfig,
+                    EEventKind::EMachineInfo, EEventKind::EAlarm, EEventKind::EMachineState], 10000);
+                let mut recorded = 0;
+                let mut error = None;
+                let result = self.stream_events(&events, *duration, |event| {
+                    match writeln!(writer, "{}", event) {
+                        Ok(()) => {
+                            recorded += 1;
+                            true
+                        },
+                        Err(e) => {
+                            error = Some(e);
+                            false
+                        },
+                    }
+                });
+                if let Some(e) = error.or_else(|| writer.flush().err()) {
+                    return Err(Failure::new(EExitCode::EFailed, format!("Can't write {}: {}", path, e)));
+                }
+                eprintln!("Recorded {} events to {}", recorded, path);
+                result
+            },
+        }
+    }
+
+    fn move_to(&mut self, target: CncCoordinates) -> Result<(), Failure> {
+        self.cnc.set_target_coords(target).map_err(|e| self.rejected(e))?;
+        self.wait_idle()?;
+        self.print_position();
+        Ok(())
+    }
+
+    fn print_position(&self) {
+        print_json(&EStatusEvent::EPosition(self.cnc.current_coords.values.clone()).to_json(|axis| self.cnc.axis_name(axis)));
+    }
+
+    fn run_program(&mut self, path: &str) -> Result<(), Failure> {
+        self.cnc.load_program(path).map_err(|e| self.rejected(e))?;
+        self.cnc.start_job().map_err(|e| self.rejected(e))?;
+        let total = self.cnc.toolpath.as_ref().map_or(0, |toolpath| toolpath.segments.len());
+        let mut reported = None;
+        loop {
+            let completed = self.cnc.toolpath.as_ref().map_or(0, |toolpath| self.cnc.job.completed_segments(toolpath));
+            if reported != Some(completed) {
+                print_json(&timestamped(self.progress(completed, total)));
+                reported = Some(completed);
+            }
+            self.tick()?;
+            match self.cnc.state() {
+                EMachineState::EIdle => break,
+                EMachineState::EAlarm | EMachineState::EEStop => return Err(self.alarm_failure()),
+                _ => {},
+            }
+        }
+        if reported != Some(total) {
+            print_json(&timestamped(self.progress(total, total)));
+        }
+        Ok(())
+    }
+
+    /// Progress with the time taken so far and the estimate for the same progress, in seconds.
+    fn progress(&self, completed: usize, total: usize) -> Value {
+        let estimated = self.cnc.job_stats.as_ref().map_or(0f32, |stats| stats.estimated_until(completed));
+        let estimated_total = self.cnc.job_stats.as_ref().map_or(0f32, |stats| stats.estimated_duration);
+        json!({ "event": "progress", "completed": completed, "total": total, "elapsed": self.cnc.job.elapsed().as_secs_f32(),
+            "estimated": estimated, "estimated_total": estimated_total })
+    }
+
+    /// Passes events as JSON to `consume` until it returns false, `duration` is over, Ctrl-C
+    /// is pressed or the controller disconnects.
+    fn stream_events(&mut self, events: &Subscription, duration: Option<Duration>, mut consume: impl FnMut(&Value) -> bool) -> Result<(), Failure> {
+        let started = Instant::now();
+        loop {
+            for event in events.drain() {
+                if !consume(&timestamped(event.to_json(|axis| self.cnc.axis_name(axis)))) {
+                    return Ok(());
+                }
+            }
+            if events.dropped() > 0 {
+                return Err(Failure::new(EExitCode::EFailed, format!("Fell behind, {} events were lost", events.dropped())));
+            }
+            if duration.is_some_and(|duration| started.elapsed() >= duration) || INTERRUPTED.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let _ = io::stdout().flush();
+            match self.tick() {
+                Err(_) if INTERRUPTED.load(Ordering::SeqCst) => return Ok(()),
+                result => result?,
+            }
+        }
+    }
+
+    fn close(mut self) {
+        self.cnc.quit();
+        self.manager.shutdown();
+    }
+}
+
+fn run(options: Options) -> Result<(), Failure> {
+    if options.sim {
+        CncSimulator::new(AxisInfo::default_axes()).spawn(options.port).map_err(|e| Failure::new(EExitCode::EFailed, e))?;
+    }
+    let mut session = Session::open(&options)?;
+    let result = session.run_command(&options.command);
+    session.close();
+    result
+}
+
+fn main() {
+    cnc_log::init();
+    if let Err(e) = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst)) {
+        eprintln!("Can't handle Ctrl-C: {}", e);
+    }
+    let result = parse_args(std::env::args().skip(1).collect()).and_then(run);
+    cnc_log::flush();
+    let code = match result {
+        Ok(()) => EExitCode::EOk,
+        Err(failure) => {
+            match failure.code {
+                EExitCode::EOk => println!("{}", failure.message),
+                _ => eprintln!("{}", failure.message),
+            }
+            failure.code
+        },
+    };
+    process::exit(code as i32);
+}
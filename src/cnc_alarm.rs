@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cnc_msg::{AlarmAxisDetail, AlarmReport, EAlarmCode};
+use crate::log_error;
+
+const HISTORY_PATH: &str = "./data/alarm_history.json";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EAlarmSource {
+    /// Reported by the controller.
+    EController,
+    /// Raised by this application, e.g. by a monitor watching the status.
+    EDesktop,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Alarm {
+    /// Numeric `EAlarmCode`, so the history survives codes unknown to this version.
+    pub code            : u16,
+    pub axes            : Vec<AlarmAxisDetail>,
+    pub source          : EAlarmSource,
+    pub raised_at       : SystemTime,
+    pub acknowledged    : bool,
+    /// When the alarm was reset, `None` while it is active.
+    pub cleared_at      : Option<SystemTime>,
+}
+
+impl Default for Alarm {
+    /// Fills in fields missing from history saved by older versions.
+    fn default() -> Self {
+        Alarm{ raised_at: UNIX_EPOCH, ..Alarm::new(EAlarmCode::EUnknown(0), Vec::new(), EAlarmSource::EController) }
+    }
+}
+
+impl Alarm {
+    pub fn new(code: EAlarmCode, axes: Vec<AlarmAxisDetail>, source: EAlarmSource) -> Self {
+        Alarm{
+            code        : code.code(),
+            axes,
+            source,
+            raised_at   : SystemTime::now(),
+            acknowledged: false,
+            cleared_at  : None,
+        }
+    }
+
+    pub fn from_report(report: AlarmReport) -> Self {
+        Alarm::new(EAlarmCode::from_code(report.code), report.axes, EAlarmSource::EController)
+    }
+
+    pub fn alarm_code(&self) -> EAlarmCode {
+        EAlarmCode::from_code(self.code)
+    }
+
+    /// Same fault on the same axes, whatever values were measured.
+    pub fn same_fault(&self, other: &Alarm) -> bool {
+        self.code == other.code && self.source == other.source
+            && self.axes.iter().map(|detail| detail.axis).eq(other.axes.iter().map(|detail| detail.axis))
+    }
+
+    /// One line description with the affected axes, named by `axis_name`.
+    pub fn describe(&self, axis_name: impl Fn(usize) -> String) -> String {
+        let mut text = format!("A{:03} {}", self.code, self.alarm_code().description());
+        if !self.axes.is_empty() {
+            let axes: Vec<String> = self.axes.iter()
+                .map(|detail| format!("{} {:.3}", axis_name(detail.axis as usize), detail.value))
+                .collect();
+            text.push_str(&format!(" ({})", axes.join(", ")));
+        }
+        if self.source == EAlarmSource::EDesktop {
+            text.push_str(" [desktop]");
+        }
+        text
+    }
+}
+
+/// Latched alarms and the ones cleared before, most recent last.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AlarmLog {
+    pub active  : Vec<Alarm>,
+    pub history : Vec<Alarm>,
+    /// Set when the history file exists but couldn't be read, so it is never saved over.
+    #[serde(skip)]
+    unreadable  : bool,
+}
+
+impl AlarmLog {
+    pub const MAX_HISTORY: usize = 200;
+
+    /// Loads the history saved by a previous run. Alarms active at exit are kept
+    /// as history only, the controller reports them again if they still apply. A file
+    /// that can't be read is left alone.
+    pub fn load() -> Self {
+        if !Path::new(HISTORY_PATH).exists() {
+            return AlarmLog::default();
+        }
+        let result = fs::read(HISTORY_PATH)
+            .map_err(|e| format!("Failed to read {}: {:?}", HISTORY_PATH, e))
+            .and_then(|payload| serde_json::from_slice::<Vec<Alarm>>(&payload).map_err(|e| format!("Failed to deserialize {}: {}", HISTORY_PATH, e)));
+        match result {
+            Ok(history) => AlarmLog{ active: Vec::new(), history, unreadable: false },
+            Err(e) => {
+                log_error!("{}, alarms won't be saved until it is fixed or removed", e);
+                AlarmLog{ unreadable: true, ..AlarmLog::default() }
+            },
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if self.unreadable {
+            return Err(format!("Not saving over {}, it couldn't be loaded", HISTORY_PATH));
+        }
+        if let Some(dir) = Path::new(HISTORY_PATH).parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {:?}", dir, e))?;
+        }
+        let payload = serde_json::to_vec_pretty(&self.history).map_err(|e| format!("Failed to serialize alarm history: {:?}", e))?;
+        fs::write(HISTORY_PATH, payload).map_err(|e| format!("Failed to write {}: {:?}", HISTORY_PATH, e))
+    }
+
+    /// Latches `alarm`. Returns false if the same fault is already active.
+    pub fn raise(&mut self, alarm: Alarm) -> bool {
+        if self.active.iter().any(|active| active.same_fault(&alarm)) {
+            return false;
+        }
+        self.history.push(alarm.clone());
+        if self.history.len() > AlarmLog::MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.active.push(alarm);
+        true
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    pub fn all_acknowledged(&self) -> bool {
+        self.active.iter().all(|alarm| alarm.acknowledged)
+    }
+
+    pub fn acknowledge(&mut self) {
+        for alarm in self.active.iter_mut() {
+            alarm.acknowledged = true;
+        }
+        self.sync_history();
+    }
+
+    /// Unlatches all active alarms.
+    pub fn clear(&mut self) {
+        let now = SystemTime::now();
+        for alarm in self.active.iter_mut() {
+            alarm.cleared_at = Some(now);
+        }
+        self.sync_history();
+        self.active.clear();
+    }
+
+    /// Copies the state of the active alarms to their history entries.
+    fn sync_history(&mut self) {
+        for alarm in self.active.iter() {
+            if let Some(entry) = self.history.iter_mut().rev().find(|entry| entry.raised_at == alarm.raised_at && entry.code == alarm.code) {
+                *entry = alarm.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarm(code: EAlarmCode, axes: &[(u8, f32)], source: EAlarmSource) -> Alarm {
+        Alarm::new(code, axes.iter().map(|(axis, value)| AlarmAxisDetail{ axis: *axis, value: *value }).collect(), source)
+    }
+
+    #[test]
+    fn repeated_faults_latch_once() {
+        use EAlarmSource::*;
+        let mut log = AlarmLog::default();
+        let first = alarm(EAlarmCode::EFollowingError, &[(0, 1.5f32)], EController);
+        let cases = vec![
+            (alarm(EAlarmCode::EFollowingError, &[(0, 1.5f32)], EController), false),
+            (alarm(EAlarmCode::EFollowingError, &[(0, 2.7f32)], EController), false),
+            (alarm(EAlarmCode::EFollowingError, &[(0, 1.5f32)], EDesktop), true),
+            (alarm(EAlarmCode::EFollowingError, &[(1, 1.5f32)], EController), true),
+            (alarm(EAlarmCode::ELimitSwitch, &[(0, 1.5f32)], EController), true),
+        ];
+        assert!(log.raise(first));
+        for (index, (next, latched)) in cases.into_iter().enumerate() {
+            assert_eq!(log.raise(next), latched, "case {}", index);
+        }
+        assert_eq!(log.active.len(), 4);
+        assert_eq!(log.history.len(), 4);
+    }
+
+    #[test]
+    fn clearing_keeps_the_history() {
+        let mut log = AlarmLog::default();
+        log.raise(alarm(EAlarmCode::EWatchdogReset, &[], EAlarmSource::EController));
+        assert!(!log.all_acknowledged());
+        log.acknowledge();
+        assert!(log.all_acknowledged());
+        log.clear();
+        assert!(!log.is_active());
+        assert!(log.history[0].acknowledged && log.history[0].cleared_at.is_some());
+        assert!(log.raise(alarm(EAlarmCode::EWatchdogReset, &[], EAlarmSource::EController)));
+
+        for _ in 0..AlarmLog::MAX_HISTORY {
+            log.clear();
+            log.raise(alarm(EAlarmCode::EEncoderFailure, &[], EAlarmSource::EController));
+        }
+        assert_eq!(log.history.len(), AlarmLog::MAX_HISTORY);
+    }
+
+    #[test]
+    fn history_entries_missing_fields_take_defaults() {
+        let history: Vec<Alarm> = serde_json::from_str(r#"[{"code": 2, "source": "EDesktop", "future_field": true}]"#).unwrap();
+        assert_eq!(history[0].alarm_code(), EAlarmCode::ELimitSwitch);
+        assert_eq!(history[0].source, EAlarmSource::EDesktop);
+        assert_eq!(history[0].raised_at, UNIX_EPOCH);
+        assert!(history[0].axes.is_empty());
+    }
+}
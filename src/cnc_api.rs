@@ -0,0 +1,522 @@
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::cnc_bus::{EEventKind, Subscription};
+use crate::cnc_ctrl::CncCtrl;
+use crate::cnc_log::format_timestamp;
+use crate::cnc_msg::{CncCoordinates, PIDParams};
+use crate::{log_error, log_info, log_warn};
+
+/// Address the API listens on unless told otherwise. Only local programs can connect.
+pub const API_DEFAULT_ADDRESS: &str = "127.0.0.1:5560";
+const AUDIT_PATH: &str = "./data/api_audit.log";
+/// Clients must send this token in an `auth` request before anything else.
+const TOKEN_PATH: &str = "./data/api_token";
+/// Start of the first line a browser sends, which is never a valid request.
+const HTTP_METHODS: [&str; 9] = ["GET ", "HEAD ", "POST ", "PUT ", "DELETE ", "CONNECT ", "OPTIONS ", "TRACE ", "PATCH "];
+/// Lines queued for a client before it is dropped as too slow.
+const CLIENT_QUEUE: usize = 1024;
+/// Events a subscribed client may fall behind by before it loses the oldest.
+const CLIENT_EVENTS: usize = 1000;
+/// How often client and accept threads look for shutdown and replies.
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "cmd")]
+pub enum EApiCommand {
+    /// Has to be the first request of a client, with the token from `data/api_token`.
+    #[serde(rename = "auth")]
+    EAuth{ token: String },
+    /// State, position, job progress and active alarms.
+    #[serde(rename = "status")]
+    EStatus,
+    /// Absolute move, one value per axis in machine units.
+    #[serde(rename = "move")]
+    EMove{ position: Vec<f32> },
+    /// Relative move of one axis, given by name or index.
+    #[serde(rename = "jog")]
+    EJog{ axis: Value, distance: f32 },
+    #[serde(rename = "home")]
+    EHome,
+    /// Stops the running job.
+    #[serde(rename = "stop")]
+    EStop,
+    #[serde(rename = "estop")]
+    EEStop,
+    /// Acknowledges the active alarms and resets.
+    #[serde(rename = "reset")]
+    EReset,
+    /// The PID params last read from the controller.
+    #[serde(rename = "get_pid")]
+    EGetPid,
+    #[serde(rename = "set_pid")]
+    ESetPid{ axes: Vec<PIDParams>, #[serde(default)] note: String },
+    #[serde(rename = "load")]
+    ELoad{ path: String },
+    #[serde(rename = "run")]
+    ERun,
+    #[serde(rename = "pause")]
+    EPause,
+    #[serde(rename = "resume")]
+    EResume,
+    /// Streams events of the given kinds as lines with an `event` field, all kinds if empty.
+    #[serde(rename = "subscribe")]
+    ESubscribe{ #[serde(default)] events: Vec<String> },
+    #[serde(rename = "unsubscribe")]
+    EUnsubscribe,
+}
+
+impl EApiCommand {
+    /// Commands that only read are left out of the audit log.
+    fn changes_machine(&self) -> bool {
+        !matches!(self, EApiCommand::EAuth{ .. } | EApiCommand::EStatus | EApiCommand::EGetPid | EApiCommand::ESubscribe{ .. } | EApiCommand::EUnsubscribe)
+    }
+}
+
+fn event_kind(name: &str) -> Result<EEventKind, String> {
+    match name {
+        "position" => Ok(EEventKind::EPosition),
+        "status" => Ok(EEventKind::EStatus),
+        "pid_params" => Ok(EEventKind::EPIDParams),
+        "axis_config" => Ok(EEventKind::EAxisConfig),
+        "machine_info" => Ok(EEventKind::EMachineInfo),
+        "alarm" => Ok(EEventKind::EAlarm),
+        "state" => Ok(EEventKind::EMachineState),
+        _ => Err(format!("Unknown event '{}'", name)),
+    }
+}
+
+const ALL_EVENTS: [EEventKind; 7] = [EEventKind::EPosition, EEventKind::EStatus, EEventKind::EPIDParams, EEventKind::EAxisConfig,
+    EEventKind::EMachineInfo, EEventKind::EAlarm, EEventKind::EMachineState];
+
+/// Reads a request line like `{"id": 1, "cmd": "move", "position": [10, 20, 5]}`. The
+/// optional `id` is returned to be echoed in the response, also when the command is invalid.
+pub fn parse_request(line: &str) -> (Value, Result<EApiCommand, String>) {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return (Value::Null, Err(format!("Invalid JSON: {}", e))),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    (id, serde_json::from_value(request).map_err(|e| format!("Invalid request: {}", e)))
+}
+
+/// A browser tricked into posting to the API sends HTTP headers, its body would come later.
+fn is_http_request(line: &str) -> bool {
+    HTTP_METHODS.iter().any(|method| line.starts_with(method))
+}
+
+/// Compares every byte so the time taken doesn't tell how much of the token was right.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Reads the token clients have to send, or writes a new random one if there is none.
+fn load_token() -> Result<String, String> {
+    match fs::read_to_string(TOKEN_PATH) {
+        Ok(token) if !token.trim().is_empty() => return Ok(String::from(token.trim())),
+        Ok(_) => return Err(format!("{} is empty, delete it to get a new token", TOKEN_PATH)),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(format!("Can't read {}: {}", TOKEN_PATH, e)),
+        Err(_) => {},
+    }
+    // Each `RandomState` is seeded by the OS, that's enough to be unguessable from a browser.
+    let token: String = (0..2).map(|_| format!("{:016x}", RandomState::new().build_hasher().finish())).collect();
+    Path::new(TOKEN_PATH).parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| write_private(TOKEN_PATH, &token))
+        .map_err(|e| format!("Can't write {}: {}", TOKEN_PATH, e))?;
+    log_info!("New API token written to {}", TOKEN_PATH);
+    Ok(token)
+}
+
+#[cfg(unix)]
+fn write_private(path: &str, contents: &str) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &str, contents: &str) -> io::Result<()> {
+    OpenOptions::new().write(true).create_new(true).open(path)?.write_all(contents.as_bytes())
+}
+
+/// Index of the axis given by name, case insensitive, or by index.
+fn find_axis(cnc: &CncCtrl, axis: &Value) -> Result<usize, String> {
+    let index = match axis {
+        Value::String(name) => cnc.axes.iter().position(|info| info.name.eq_ignore_ascii_case(name)),
+        Value::Number(index) => index.as_u64().map(|index| index as usize).filter(|index| *index < cnc.axis_count()),
+        _ => None,
+    };
+    index.ok_or_else(|| format!("The machine has no axis {}", axis))
+}
+
+fn status_json(cnc: &CncCtrl) -> Value {
+    let axis_name = |axis: usize| cnc.axis_name(axis);
+    let (completed, total) = match cnc.toolpath {
+        Some(ref toolpath) => (cnc.job.completed_segments(toolpath), toolpath.segments.len()),
+        None => (0, 0),
+    };
+    json!({
+        "state": cnc.state().name(),
+        "position": cnc.current_coords.values,
+        "target": cnc.get_target_coords().values,
+        "axes": (0..cnc.axis_count()).map(axis_name).collect::<Vec<String>>(),
+        "program": cnc.program.as_ref().map(|program| program.name.clone()),
+        "job": { "completed": completed, "total": total },
+        "alarms": cnc.alarms.active.iter().map(|alarm| alarm.describe(axis_name)).collect::<Vec<String>>(),
+    })
+}
+
+/// Runs one command against the controller. Subscriptions are handled by the server.
+fn execute(cnc: &mut CncCtrl, command: &EApiCommand) -> Result<Value, String> {
+    match command {
+        EApiCommand::EStatus => Ok(status_json(cnc)),
+        EApiCommand::EMove{ position } => {
+            if position.len() != cnc.axis_count() {
+                return Err(format!("move needs {} values, one per axis", cnc.axis_count()));
+            }
+            let mut target = CncCoordinates::with_axes(position.len());
+            for (axis, value) in position.iter().enumerate() {
+                target.set(axis, *value);
+            }
+            cnc.set_target_coords(target).map(|()| Value::Null)
+        },
+        EApiCommand::EJog{ axis, distance } => {
+            let axis = find_axis(cnc, axis)?;
+            let mut target = cnc.current_coords.clone();
+            target.set(axis, target.values[axis] + distance);
+            cnc.set_target_coords(target).map(|()| Value::Null)
+        },
+        EApiCommand::EHome => cnc.home().map(|()| Value::Null),
+        EApiCommand::EStop => cnc.stop_job().map(|()| Value::Null),
+        EApiCommand::EEStop => cnc.emergency_stop().map(|()| Value::Null),
+        EApiCommand::EReset => {
+            cnc.acknowledge_alarms();
+            cnc.reset().map(|()| Value::Null)
+        },
+        EApiCommand::EGetPid => Ok(json!(cnc.pid_params)),
+        EApiCommand::ESetPid{ axes, note } => cnc.set_pid_params(axes, note).map(|()| Value::Null),
+        EApiCommand::ELoad{ path } => cnc.load_program(path).map(|()| Value::Null),
+        EApiCommand::ERun => cnc.start_job().map(|()| Value::Null),
+        EApiCommand::EPause => cnc.pause_job().map(|()| Value::Null),
+        EApiCommand::EResume => cnc.resume_job().map(|()| Value::Null),
+        EApiCommand::EAuth{ .. } | EApiCommand::ESubscribe{ .. } | EApiCommand::EUnsubscribe => Ok(Value::Null),
+    }
+}
+
+/// Appends one line per request that may change the machine, whether it was accepted or not.
+struct AuditLog {
+    file    : Option<BufWriter<File>>,
+}
+
+impl AuditLog {
+    fn open() -> Self {
+        let file = Path::new(AUDIT_PATH).parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| OpenOptions::new().create(true).append(true).open(AUDIT_PATH));
+        match file {
+            Ok(file) => AuditLog{ file: Some(BufWriter::new(file)) },
+            Err(e) => {
+                log_error!("Can't open {}, API commands won't be audited: {}", AUDIT_PATH, e);
+                AuditLog{ file: None }
+            },
+        }
+    }
+
+    fn record(&mut self, client: &SocketAddr, line: &str, result: &Result<Value, String>) {
+        let outcome = match result {
+            Ok(_) => String::from("ok"),
+            Err(e) => format!("rejected: {}", e),
+        };
+        if let Some(ref mut file) = self.file {
+            let written = writeln!(file, "{} {} {} -> {}", format_timestamp(SystemTime::now()), client, line, outcome)
+                .and_then(|()| file.flush());
+            if let Err(e) = written {
+                log_error!("Can't write {}: {}", AUDIT_PATH, e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+struct ApiClient {
+    address : SocketAddr,
+    lines   : SyncSender<String>,
+    events  : Option<Subscription>,
+    /// Set once the client sent the right token.
+    authorized: bool,
+    /// Set by the client thread when it ends.
+    closed  : Arc<AtomicBool>,
+}
+
+/// A request line read by a client thread.
+struct ClientLine {
+    client  : SocketAddr,
+    line    : String,
+}
+
+/// Local JSON lines API over TCP. Client threads only read and write sockets, commands
+/// run on the thread calling `update`, which owns the `CncCtrl`.
+pub struct ApiServer {
+    clients : Vec<ApiClient>,
+    accepted: Receiver<ApiClient>,
+    requests: Receiver<ClientLine>,
+    audit   : AuditLog,
+    token   : String,
+    stop    : Arc<AtomicBool>,
+}
+
+impl ApiServer {
+    pub fn start(address: &str) -> Result<ApiServer, String> {
+        let token = load_token()?;
+        let listener = TcpListener::bind(address).map_err(|e| format!("API can't listen on {}: {}", address, e))?;
+        let address = listener.local_addr().map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        if !address.ip().is_loopback() {
+            log_warn!("API listens on {}, reachable from other machines", address);
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (accepted_tx, accepted) = mpsc::channel();
+        let (requests_tx, requests) = mpsc::channel();
+        let accept_stop = Arc::clone(&stop);
+        thread::spawn(move || ApiServer::accept(listener, accepted_tx, requests_tx, accept_stop));
+        log_info!("API listening on {}", address);
+        Ok(ApiServer{
+            clients : Vec::new(),
+            accepted,
+            requests,
+            audit   : AuditLog::open(),
+            token,
+            stop,
+        })
+    }
+
+    fn accept(listener: TcpListener, accepted: Sender<ApiClient>, requests: Sender<ClientLine>, stop: Arc<AtomicBool>) {
+        while !stop.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    let (lines_tx, lines_rx) = mpsc::sync_channel(CLIENT_QUEUE);
+                    let closed = Arc::new(AtomicBool::new(false));
+                    if accepted.send(ApiClient{ address, lines: lines_tx, events: None, authorized: false, closed: Arc::clone(&closed) }).is_err() {
+                        break;
+                    }
+                    log_info!("API client {} connected", address);
+                    let requests = requests.clone();
+                    let stop = Arc::clone(&stop);
+                    thread::spawn(move || {
+                        if let Err(e) = ApiServer::serve(stream, address, lines_rx, requests, stop) {
+                            log_warn!("API client {}: {}", address, e);
+                        }
+                        closed.store(true, Ordering::SeqCst);
+                        log_info!("API client {} disconnected", address);
+                    });
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_PERIOD),
+                Err(e) => {
+                    log_error!("API failed to accept: {}", e);
+                    thread::sleep(POLL_PERIOD);
+                },
+            }
+        }
+    }
+
+    /// Forwards request lines and writes queued lines until either side goes away. HTTP
+    /// requests are dropped before anything is forwarded.
+    fn serve(stream: TcpStream, address: SocketAddr, lines: Receiver<String>, requests: Sender<ClientLine>, stop: Arc<AtomicBool>) -> Result<(), String> {
+        stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(POLL_PERIOD)).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while !stop.load(Ordering::SeqCst) {
+            match reader.read_line(&mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {
+                    let request = line.trim();
+                    if is_http_request(request) {
+                        return Err(String::from("Sent an HTTP request, dropped"));
+                    }
+                    if !request.is_empty() && requests.send(ClientLine{ client: address, line: String::from(request) }).is_err() {
+                        return Ok(());
+                    }
+                    line.clear();
+                },
+                // A partial line stays in `line` until the rest arrives.
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(e.to_string()),
+            }
+            loop {
+                match lines.try_recv() {
+                    Ok(reply) => writeln!(writer, "{}", reply).map_err(|e| e.to_string())?,
+                    Err(TryRecvError::Empty) => break,
+                    // Dropped by the server.
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Takes new clients, runs their requests and sends their events. Call it every frame
+    /// after `CncCtrl::update_status`.
+    pub fn update(&mut self, cnc: &mut CncCtrl) {
+        while let Ok(client) = self.accepted.try_recv() {
+            self.clients.push(client);
+        }
+        while let Ok(request) = self.requests.try_recv() {
+            self.handle(cnc, request);
+        }
+        for client in self.clients.iter_mut() {
+            if let Some(ref events) = client.events {
+                for event in events.drain() {
+                    let line = event.to_json(|axis| cnc.axis_name(axis)).to_string();
+                    if ApiServer::queue(client.address, &client.lines, line).is_err() {
+                        client.events = None;
+                        break;
+                    }
+                }
+            }
+        }
+        self.clients.retain(|client| !client.closed.load(Ordering::SeqCst));
+    }
+
+    fn queue(address: SocketAddr, lines: &SyncSender<String>, line: String) -> Result<(), ()> {
+        match lines.try_send(line) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                log_warn!("API client {} is too slow, dropping its events", address);
+                Err(())
+            },
+            Err(TrySendError::Disconnected(_)) => Err(()),
+        }
+    }
+
+    fn handle(&mut self, cnc: &mut CncCtrl, request: ClientLine) {
+        let client = match self.clients.iter().position(|client| client.address == request.client) {
+            Some(client) => client,
+            None => return,
+        };
+        let (id, command) = parse_request(&request.line);
+        let result = match command {
+            Ok(EApiCommand::EAuth{ ref token }) => {
+                self.clients[client].authorized = same_token(token, &self.token);
+                match self.clients[client].authorized {
+                    true => Ok(Value::Null),
+                    false => Err(String::from("Wrong token")),
+                }
+            },
+            Ok(_) if !self.clients[client].authorized => Err(format!("Send auth with the token from {} first", TOKEN_PATH)),
+            Ok(EApiCommand::ESubscribe{ ref events }) => self.subscribe(cnc, client, events),
+            Ok(EApiCommand::EUnsubscribe) => {
+                self.clients[client].events = None;
+                Ok(Value::Null)
+            },
+            Ok(ref command) => execute(cnc, command),
+            Err(ref e) => Err(e.clone()),
+        };
+        // Whatever sent a line that isn't a request isn't a client of the API.
+        let drop_reason = match command {
+            Err(_) => Some("sent an invalid request"),
+            Ok(_) if !self.clients[client].authorized => Some("isn't authorized"),
+            Ok(_) => None,
+        };
+        // Requests that can't be read might have been meant to change something.
+        if command.map_or(true, |command| command.changes_machine()) {
+            self.audit.record(&request.client, &request.line, &result);
+        }
+        let response = match result {
+            Ok(result) => json!({ "id": id, "ok": true, "result": result }),
+            Err(e) => json!({ "id": id, "ok": false, "error": e }),
+        };
+        let _ = ApiServer::queue(request.client, &self.clients[client].lines, response.to_string());
+        // The response is still written, dropping the client only closes its queue.
+        if let Some(reason) = drop_reason {
+            log_warn!("API client {} {}, dropping it", request.client, reason);
+            self.clients.remove(client);
+        }
+    }
+
+    fn subscribe(&mut self, cnc: &CncCtrl, client: usize, events: &[String]) -> Result<Value, String> {
+        let kinds = match events.is_empty() {
+            true => ALL_EVENTS.to_vec(),
+            false => events.iter().map(|name| event_kind(name)).collect::<Result<Vec<EEventKind>, String>>()?,
+        };
+        self.clients[client].events = Some(cnc.bus.subscribe(&kinds, CLIENT_EVENTS));
+        Ok(Value::Null)
+    }
+
+    /// Disconnects all clients and stops listening.
+    pub fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.clients.clear();
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_parse_with_and_without_id() {
+        let (id, command) = parse_request(r#"{"id": 7, "cmd": "move", "position": [1, 2.5, 3]}"#);
+        assert_eq!(id, json!(7));
+        assert_eq!(command, Ok(EApiCommand::EMove{ position: vec![1f32, 2.5f32, 3f32] }));
+
+        let (id, command) = parse_request(r#"{"cmd": "subscribe"}"#);
+        assert_eq!(id, Value::Null);
+        assert_eq!(command, Ok(EApiCommand::ESubscribe{ events: Vec::new() }));
+    }
+
+    #[test]
+    fn bad_requests_are_rejected_with_their_id() {
+        let (id, command) = parse_request(r#"{"id": "a", "cmd": "explode"}"#);
+        assert_eq!(id, json!("a"));
+        assert!(command.is_err());
+        assert!(parse_request(r#"{"cmd": "jog", "axis": "X"}"#).1.is_err());
+        assert!(parse_request("move 1 2 3").1.is_err());
+    }
+
+    #[test]
+    fn only_commands_that_change_the_machine_are_audited() {
+        assert!(EApiCommand::EHome.changes_machine());
+        assert!(EApiCommand::ELoad{ path: String::from("a.gcode") }.changes_machine());
+        assert!(!EApiCommand::EStatus.changes_machine());
+        assert!(!EApiCommand::ESubscribe{ events: Vec::new() }.changes_machine());
+        assert!(!EApiCommand::EAuth{ token: String::from("secret") }.changes_machine());
+    }
+
+    #[test]
+    fn http_requests_are_recognized() {
+        assert!(is_http_request("POST / HTTP/1.1"));
+        assert!(is_http_request("GET /status HTTP/1.1"));
+        assert!(!is_http_request(r#"{"cmd": "status"}"#));
+        assert!(!is_http_request("POSTS"));
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(same_token("0123abcd", "0123abcd"));
+        assert!(!same_token("0123abce", "0123abcd"));
+        assert!(!same_token("0123abc", "0123abcd"));
+        assert!(!same_token("", "0123abcd"));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::cnc_alarm::Alarm;
+use crate::cnc_machine_state::EMachineState;
+use crate::cnc_msg::{AxisConfig, CncStatus, MachineInfo, PIDParams};
+
+/// Something that happened on the machine, as published on the `StatusBus`.
+#[derive(Clone, Debug)]
+pub enum EStatusEvent {
+    /// Position from controllers that only report coordinates.
+    EPosition(Vec<f32>),
+    /// Every status sample, not coalesced.
+    EStatus(CncStatus),
+    EPIDParams(Vec<PIDParams>),
+    EAxisConfig(Vec<AxisConfig>),
+    EMachineInfo(MachineInfo),
+    /// Raised by the controller or the application.
+    EAlarm(Alarm),
+    /// The machine state changed, including connects and disconnects.
+    EMachineState(EMachineState),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EEventKind {
+    EPosition,
+    EStatus,
+    EPIDParams,
+    EAxisConfig,
+    EMachineInfo,
+    EAlarm,
+    EMachineState,
+}
+
+impl EStatusEvent {
+    pub fn kind(&self) -> EEventKind {
+        match self {
+            EStatusEvent::EPosition(_) => EEventKind::EPosition,
+            EStatusEvent::EStatus(_) => EEventKind::EStatus,
+            EStatusEvent::EPIDParams(_) => EEventKind::EPIDParams,
+            EStatusEvent::EAxisConfig(_) => EEventKind::EAxisConfig,
+            EStatusEvent::EMachineInfo(_) => EEventKind::EMachineInfo,
+            EStatusEvent::EAlarm(_) => EEventKind::EAlarm,
+            EStatusEvent::EMachineState(_) => EEventKind::EMachineState,
+        }
+    }
+
+    /// One line for event lists, with `axis_name` naming the axes of alarms.
+    pub fn describe(&self, axis_name: impl Fn(usize) -> String) -> String {
+        let values = |values: &mut dyn Iterator<Item = f32>| values.map(|value| format!("{:.3}", value)).collect::<Vec<String>>().join(" ");
+        match self {
+            EStatusEvent::EPosition(position) => format!("Position {}", values(&mut position.iter().cloned())),
+            EStatusEvent::EStatus(status) => format!("Status {}", values(&mut status.axis_status.iter().map(|axis| axis.position))),
+            EStatusEvent::EPIDParams(params) => format!("PID params for {} axes", params.len()),
+            EStatusEvent::EAxisConfig(configs) => format!("Axis config for {} axes", configs.len()),
+            EStatusEvent::EMachineInfo(info) => format!("Machine info, protocol {} with {} axes", info.protocol_version, info.axes.len()),
+            EStatusEvent::EAlarm(alarm) => format!("Alarm {}", alarm.describe(axis_name)),
+            EStatusEvent::EMachineState(state) => format!("State {}", state.name()),
+        }
+    }
+
+    /// One JSON object per event for scripts, with an `event` field naming the kind and
+    /// per axis values in `axes`, named by `axis_name`.
+    pub fn to_json(&self, axis_name: impl Fn(usize) -> String) -> Value {
+        match self {
+            EStatusEvent::EPosition(position) => json!({
+                "event": "position",
+                "axes": position.iter().enumerate().map(|(axis, value)| json!({ "axis": axis_name(axis), "position": value })).collect::<Vec<Value>>(),
+            }),
+            EStatusEvent::EStatus(status) => json!({
+                "event": "status",
+                "cycle_time": status.cycle_time,
+                "axes": named_axes(&status.axis_status, &axis_name),
+            }),
+            EStatusEvent::EPIDParams(params) => json!({ "event": "pid_params", "axes": named_axes(params, &axis_name) }),
+            EStatusEvent::EAxisConfig(configs) => json!({ "event": "axis_config", "axes": named_axes(configs, &axis_name) }),
+            EStatusEvent::EMachineInfo(info) => json!({
+                "event": "machine_info",
+                "protocol_version": info.protocol_version,
+                "axes": info.axes.iter().map(|axis| json!({ "axis": axis.name, "min": axis.min, "max": axis.max })).collect::<Vec<Value>>(),
+            }),
+            EStatusEvent::EAlarm(alarm) => json!({
+                "event": "alarm",
+                "code": alarm.code,
+                "text": alarm.describe(&axis_name),
+                "axes": alarm.axes.iter().map(|detail| json!({ "axis": axis_name(detail.axis as usize), "value": detail.value })).collect::<Vec<Value>>(),
+            }),
+            EStatusEvent::EMachineState(state) => json!({ "event": "state", "state": state.name() }),
+        }
+    }
+}
+
+struct SubscriberQueue {
+    events  : VecDeque<EStatusEvent>,
+    capacity: usize,
+    /// Events dropped because the subscriber fell behind.
+    dropped : u64,
+}
+
+struct Subscriber {
+    kinds   : Vec<EEventKind>,
+    queue   : Arc<Mutex<SubscriberQueue>>,
+}
+
+/// Fans events out to any number of subscribers, each with its own bounded queue,
+/// so a slow consumer only loses its own oldest events. Clones share the subscribers.
+#[derive(Clone)]
+pub struct StatusBus {
+    subscribers : Arc<Mutex<Vec<Subscriber>>>,
+}
+
+/// Receiving end of a subscription. Dropping it unsubscribes.
+pub struct Subscription {
+    queue   : Arc<Mutex<SubscriberQueue>>,
+}
+
+impl Default for StatusBus {
+    fn default() -> Self {
+        StatusBus::new()
+    }
+}
+
+impl StatusBus {
+    pub fn new() -> Self {
+        StatusBus{
+            subscribers : Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Receives the `kinds` published from now on, keeping at most `capacity` unread.
+    pub fn subscribe(&self, kinds: &[EEventKind], capacity: usize) -> Subscription {
+        let queue = Arc::new(Mutex::new(SubscriberQueue{
+            events  : VecDeque::with_capacity(capacity.min(1024)),
+            capacity: capacity.max(1),
+            dropped : 0,
+        }));
+        let subscriber = Subscriber{ kinds: kinds.to_vec(), queue: Arc::clone(&queue) };
+        lock(&self.subscribers).push(subscriber);
+        Subscription{ queue }
+    }
+
+    pub fn publish(&self, event: EStatusEvent) {
+        let kind = event.kind();
+        let mut subscribers = lock(&self.subscribers);
+        // Only the bus holds the queue of a dropped subscription.
+        subscribers.retain(|subscriber| Arc::strong_count(&subscriber.queue) > 1);
+        for subscriber in subscribers.iter().filter(|subscriber| subscriber.kinds.contains(&kind)) {
+            let mut queue = lock(&subscriber.queue);
+            if queue.events.len() == queue.capacity {
+                queue.events.pop_front();
+                queue.dropped += 1;
+            }
+            queue.events.push_back(event.clone());
+        }
+    }
+}
+
+impl Subscription {
+    /// Takes all queued events, oldest first.
+    pub fn drain(&self) -> Vec<EStatusEvent> {
+        lock(&self.queue).events.drain(..).collect()
+    }
+
+    /// Events lost so far because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        lock(&self.queue).dropped
+    }
+}
+
+/// Serializes each item with its axis name added as `axis`.
+fn named_axes<T: Serialize>(items: &[T], axis_name: &impl Fn(usize) -> String) -> Vec<Value> {
+    items.iter().enumerate().map(|(axis, item)| {
+        let mut value = serde_json::to_value(item).unwrap_or(Value::Null);
+        if let Value::Object(ref mut fields) = value {
+            fields.insert(String::from("axis"), Value::String(axis_name(axis)));
+        }
+        value
+    }).collect()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(events: Vec<EStatusEvent>) -> Vec<f32> {
+        events.iter().map(|event| match event {
+            EStatusEvent::EPosition(position) => position[0],
+            other => panic!("unexpected event {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn subscribers_only_get_their_kinds() {
+        let bus = StatusBus::new();
+        let all = bus.subscribe(&[EEventKind::EPosition, EEventKind::EMachineState], 10);
+        let states = bus.subscribe(&[EEventKind::EMachineState], 10);
+
+        bus.publish(EStatusEvent::EPosition(vec![1f32]));
+        bus.publish(EStatusEvent::EMachineState(EMachineState::EIdle));
+
+        assert_eq!(all.drain().iter().map(EStatusEvent::kind).collect::<Vec<EEventKind>>(), vec![EEventKind::EPosition, EEventKind::EMachineState]);
+        assert_eq!(states.drain().iter().map(EStatusEvent::kind).collect::<Vec<EEventKind>>(), vec![EEventKind::EMachineState]);
+        assert!(all.drain().is_empty());
+    }
+
+    #[test]
+    fn full_queue_drops_only_its_own_oldest_events() {
+        let bus = StatusBus::new();
+        let slow = bus.subscribe(&[EEventKind::EPosition], 2);
+        let fast = bus.subscribe(&[EEventKind::EPosition], 10);
+
+        for i in 0..4 {
+            bus.publish(EStatusEvent::EPosition(vec![i as f32]));
+        }
+
+        assert_eq!(positions(slow.drain()), vec![2f32, 3f32]);
+        assert_eq!(slow.dropped(), 2);
+        assert_eq!(positions(fast.drain()), vec![0f32, 1f32, 2f32, 3f32]);
+        assert_eq!(fast.dropped(), 0);
+    }
+
+    #[test]
+    fn json_names_the_axes() {
+        let event = EStatusEvent::EPosition(vec![1f32, 2f32]);
+        let json = event.to_json(|axis| ["X", "Y"][axis].to_string());
+        assert_eq!(json["event"], "position");
+        assert_eq!(json["axes"][1]["axis"], "Y");
+        assert_eq!(json["axes"][1]["position"], 2f64);
+    }
+
+    #[test]
+    fn dropping_a_subscription_unsubscribes() {
+        let bus = StatusBus::new();
+        let kept = bus.subscribe(&[EEventKind::EPosition], 10);
+        drop(bus.subscribe(&[EEventKind::EPosition], 10));
+
+        bus.clone().publish(EStatusEvent::EPosition(vec![1f32]));
+
+        assert_eq!(lock(&bus.subscribers).len(), 1);
+        assert_eq!(positions(kept.drain()), vec![1f32]);
+    }
+}
@@ -1,42 +1,85 @@
 
+use std::mem;
 use std::sync::mpsc::{self, TryRecvError};
-use std::net::TcpStream;
-use std::{io::prelude::*, net::Shutdown, time::Duration};
+use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::{io::prelude::*, net::Shutdown, time::{Duration, Instant}};
 use std::io;
-use crate::thread_pool::ThreadPool;
+use serde::{Deserialize, Serialize};
+use crate::thread_pool::{JobHandle, ThreadPool};
 
-use crate::cnc_msg::{ECncCtrlMessage, ECncStatusMessage, CncCoordinates, CncStatus, PIDParams};
+use crate::cnc_msg::{is_truncated, ECncCtrlMessage, ECncStatusMessage};
+use crate::{log_debug, log_error, log_trace, log_warn};
 
 pub struct CncConnection<T, U> {
-    o_tx            : Option<mpsc::Sender<T>>,
+    o_tx            : Option<mpsc::SyncSender<T>>,
     o_rx            : Option<mpsc::Receiver<U>>,
+    /// Messages superseded by the next one of the same kind, kept outside the queue
+    /// so a full queue never drops the newest.
+    o_latest_tx     : Option<Arc<Mutex<Vec<T>>>>,
+    o_latest_rx     : Option<Arc<Mutex<Vec<U>>>>,
+    /// Job serving the other end, stopped when this end is closed or dropped.
+    o_job           : Option<JobHandle>,
+}
+
+impl<T, U> Default for CncConnection<T, U> {
+    fn default() -> Self {
+        CncConnection::new()
+    }
 }
 
 impl<T, U> CncConnection<T, U> {
+    /// Messages each direction holds before `send` fails, bounding the latency of a
+    /// receiver that falls behind.
+    pub const QUEUE_CAPACITY: usize = 64;
+
     pub fn new() -> Self {
         CncConnection{
             o_tx : None,
             o_rx : None,
+            o_latest_tx : None,
+            o_latest_rx : None,
+            o_job : None,
         }
     }
     pub fn new_connected_pair() -> (Self, CncConnection<U, T>) {
-        let (tx_1, rx_1) = mpsc::channel();
-        let (tx_2, rx_2) = mpsc::channel();
+        let (tx_1, rx_1) = mpsc::sync_channel(Self::QUEUE_CAPACITY);
+        let (tx_2, rx_2) = mpsc::sync_channel(Self::QUEUE_CAPACITY);
+        let latest_1 = Arc::new(Mutex::new(Vec::new()));
+        let latest_2 = Arc::new(Mutex::new(Vec::new()));
         (
             CncConnection{
                 o_tx : Some(tx_1),
                 o_rx : Some(rx_2),
+                o_latest_tx : Some(latest_1.clone()),
+                o_latest_rx : Some(latest_2.clone()),
+                o_job : None,
             },
             CncConnection{
                 o_tx : Some(tx_2),
                 o_rx : Some(rx_1),
+                o_latest_tx : Some(latest_2),
+                o_latest_rx : Some(latest_1),
+                o_job : None,
             }
         )
     }
 
+    /// Stops the job serving the other end and disconnects both channels.
+    pub fn close(&mut self) {
+        if let Some(job) = self.o_job.take() {
+            job.cancel();
+        }
+        self.o_tx = None;
+        self.o_rx = None;
+        self.o_latest_tx = None;
+        self.o_latest_rx = None;
+    }
+
+    /// Queues `msg` without blocking, failing if the queue is full.
     pub fn send(&self, msg: T) -> Result<(), String> {
         if let Some(ref tx) = self.o_tx {
-            match tx.send( msg ) {
+            match tx.try_send( msg ) {
                 Ok( () ) => {
                     Ok(())
                 }, 
@@ -45,10 +88,34 @@ impl<T, U> CncConnection<T, U> {
                 }
             }
         } else {
-            Err(format!("Can't send: Connection not set up!"))
+            Err(String::from("Can't send: Connection not set up!"))
         }
     }
 
+    /// Queues `msg`, waiting for room if the queue is full.
+    pub fn send_wait(&self, msg: T) -> Result<(), String> {
+        match self.o_tx {
+            Some(ref tx) => tx.send(msg).map_err(|e| format!("Failed to send a message: {:?}", e)),
+            None => Err(String::from("Can't send: Connection not set up!")),
+        }
+    }
+
+    /// Replaces the pending message of the same kind as `msg`, or adds it. The receiver
+    /// gets it once the queue is empty. Fails if the other end is gone.
+    pub fn send_latest(&self, msg: T) -> Result<(), String> {
+        match self.o_latest_tx {
+            Some(ref latest) if Arc::strong_count(latest) > 1 => {
+                let mut latest = latest.lock().map_err(|_| String::from("Can't send: Connection poisoned!"))?;
+                latest.retain(|pending| mem::discriminant(pending) != mem::discriminant(&msg));
+                latest.push(msg);
+                Ok(())
+            },
+            Some(_) => Err(String::from("Can't send: Connection closed!")),
+            None => Err(String::from("Can't send: Connection not set up!")),
+        }
+    }
+
+    /// Takes the next queued message, then the ones sent with `send_latest`.
     pub fn receive(&self) -> Result<Option<U>, TryRecvError> {
         if let Some(ref rx) = self.o_rx {
             match rx.try_recv() {
@@ -56,8 +123,13 @@ impl<T, U> CncConnection<T, U> {
                     Ok(Some(res))
                 },
                 Err(e) => {
-                    // println!("Failed to receive: {:?}", e);
-                    Err(e)
+                    let latest = self.o_latest_rx.as_ref()
+                        .and_then(|latest| latest.lock().ok())
+                        .and_then(|mut latest| if latest.is_empty() { None } else { Some(latest.remove(0)) });
+                    match latest {
+                        Some(res) => Ok(Some(res)),
+                        None => Err(e),
+                    }
                 },
             }
         } else {
@@ -67,140 +139,488 @@ impl<T, U> CncConnection<T, U> {
     }
 }
 
+impl<T, U> Drop for CncConnection<T, U> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Checks a host name, IPv4 or IPv6 address without resolving it. IPv6 may be given
+/// in brackets. Returns the host in the form `ToSocketAddrs` expects.
+pub fn parse_host(host: &str) -> Result<String, String> {
+    let host = host.trim();
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if host.is_empty() {
+        return Err(String::from("Enter a host name or address"));
+    }
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(String::from(host));
+    }
+    if host.len() > 253 {
+        return Err(String::from("Host name is longer than 253 characters"));
+    }
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid host name '{}'", host));
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') || label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("Invalid host name '{}'", host));
+        }
+    }
+    Ok(String::from(host))
+}
+
+pub fn parse_port(port: &str) -> Result<u16, String> {
+    match port.trim().parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(String::from("Port must be a number from 1 to 65535")),
+    }
+}
+
+/// Resolves the host, which may block on DNS. IPv4 addresses are tried first.
+pub fn resolve_host(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let mut addresses: Vec<SocketAddr> = (host, port).to_socket_addrs()
+        .map_err(|e| format!("Can't resolve {}: {}", host, e))?
+        .collect();
+    if addresses.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    addresses.sort_by_key(|address| address.is_ipv6());
+    Ok(addresses)
+}
+
+/// How long connecting to one address may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connects to the first address of the host that accepts, trying IPv4 first.
+/// Fails with the error of the last address tried.
+pub fn connect(host: &str, port: u16) -> Result<TcpStream, String> {
+    let addresses = resolve_host(host, port)?;
+    let mut error = format!("{} has no addresses", host);
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                log_warn!("Failed to connect to {:?} with error {:?}", address, e);
+                error = format!("Failed to connect to {}: {}", address, e);
+            }
+        }
+    }
+    Err(error)
+}
+
+/// UDP port controllers listen on for discovery probes.
+pub const DISCOVERY_PORT: u16 = 5556;
+/// Probe broadcast by the app. The last byte is the discovery protocol version.
+const DISCOVERY_PROBE: &[u8] = b"CNC?\x01";
+/// Prefix of a reply, followed by a bincode encoded `DiscoveredController`.
+const DISCOVERY_REPLY: &[u8] = b"CNC!\x01";
+/// How long replies are collected after sending the probe.
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// A controller that answered a discovery probe.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DiscoveredController {
+    pub name            : String,
+    pub firmware_version: String,
+    /// Address the controller accepts connections on. Left empty by controllers that
+    /// don't know their own address, the reply's source address is used then.
+    pub ip              : String,
+    pub port            : u16,
+}
+
+impl DiscoveredController {
+    pub fn label(&self) -> String {
+        format!("{} ({}) at {}:{}", self.name, self.firmware_version, self.ip, self.port)
+    }
+}
+
+/// Broadcasts a probe on the local network and loopback, collecting replies until `timeout`.
+pub fn discover(timeout: Duration) -> Result<Vec<DiscoveredController>, String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(|e| format!("Can't open discovery socket: {}", e))?;
+    socket.set_broadcast(true).map_err(|e| format!("Can't enable broadcast: {}", e))?;
+    let mut sent = false;
+    for target in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST].iter() {
+        match socket.send_to(DISCOVERY_PROBE, (*target, DISCOVERY_PORT)) {
+            Ok(_) => sent = true,
+            Err(e) => log_warn!("Failed to send discovery probe to {}: {}", target, e),
+        }
+    }
+    if !sent {
+        return Err(String::from("Failed to send the discovery probe"));
+    }
+
+    let mut controllers: Vec<DiscoveredController> = Vec::new();
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; 512];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_millis(0) {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining)).map_err(|e| format!("Can't set discovery timeout: {}", e))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((len, source)) => {
+                let payload = match buffer[..len].strip_prefix(DISCOVERY_REPLY) {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+                match bincode::deserialize::<DiscoveredController>(payload) {
+                    Ok(mut controller) => {
+                        if controller.ip.parse::<IpAddr>().map(|ip| ip.is_unspecified()).unwrap_or(true) {
+                            controller.ip = source.ip().to_string();
+                        }
+                        if !controllers.iter().any(|c| c.ip == controller.ip && c.port == controller.port) {
+                            controllers.push(controller);
+                        }
+                    },
+                    Err(e) => log_warn!("Invalid discovery reply from {}: {:?}", source, e),
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(format!("Discovery failed: {}", e)),
+        }
+    }
+    Ok(controllers)
+}
+
+/// Waits for one probe on `socket` and answers it with `controller`. Used by the simulator,
+/// firmware implements the same exchange.
+pub fn answer_discovery(socket: &UdpSocket, controller: &DiscoveredController) -> Result<(), String> {
+    let mut buffer = [0u8; 64];
+    let (len, source) = socket.recv_from(&mut buffer).map_err(|e| format!("Discovery receive failed: {}", e))?;
+    if &buffer[..len] != DISCOVERY_PROBE {
+        return Ok(());
+    }
+    let mut reply = Vec::from(DISCOVERY_REPLY);
+    reply.append(&mut bincode::serialize(controller).map_err(|e| format!("Failed to serialize discovery reply: {:?}", e))?);
+    socket.send_to(&reply, source).map_err(|e| format!("Failed to answer {}: {}", source, e))?;
+    Ok(())
+}
+
 pub struct CncConnectionManager {
     pool:   ThreadPool,
 }
 
+impl Default for CncConnectionManager {
+    fn default() -> Self {
+        CncConnectionManager::new()
+    }
+}
+
 impl CncConnectionManager {
+    const READ_TIMEOUT: Duration = Duration::from_millis(5);
+    /// Largest partial message kept between reads; anything longer is garbage.
+    const MAX_PARTIAL: usize = 64 * 1024;
+
     pub fn new() -> Self {
         CncConnectionManager{
             pool    : ThreadPool::new(2),
         }
     }
 
-    pub fn run(&mut self, stream: TcpStream) -> CncConnection<ECncCtrlMessage, ECncStatusMessage> {
-        let (other_end, own_end) = CncConnection::new_connected_pair();
-        self.pool.execute( move || {
-            CncConnectionManager::run_tcp(stream, own_end);
-        });
+    /// Starts serving `stream`, decoding per axis data for `axis_count` axes until the
+    /// controller's handshake says otherwise. Serving stops when the returned end is
+    /// closed or dropped, freeing the worker.
+    pub fn run(&mut self, stream: TcpStream, axis_count: usize) -> CncConnection<ECncCtrlMessage, ECncStatusMessage> {
+        let (mut other_end, own_end) = CncConnection::new_connected_pair();
+        match self.pool.spawn( move |job| {
+            CncConnectionManager::run_tcp(stream, own_end, axis_count, job);
+        }) {
+            Ok(job) => other_end.o_job = Some(job),
+            Err(e) => {
+                // The job and its end of the channels are gone, so `other_end` reads as disconnected.
+                log_error!("Can't serve the connection: {}", e);
+            },
+        }
 
         other_end
     }
 
+    /// Runs discovery in the background, the result arrives on the returned channel.
+    pub fn discover(&mut self) -> mpsc::Receiver<Result<Vec<DiscoveredController>, String>> {
+        let (tx, rx) = mpsc::channel();
+        let job_tx = tx.clone();
+        if let Err(e) = self.pool.execute( move || {
+            let _ = job_tx.send(discover(DISCOVERY_TIMEOUT));
+        }) {
+            let _ = tx.send(Err(e));
+        }
+        rx
+    }
+
+    /// Stops all connections and waits for the background jobs to end.
+    pub fn shutdown(&mut self) {
+        log_debug!("Stopping {} connections", self.pool.active_jobs());
+        self.pool.shutdown();
+    }
+
     fn send_msg_tcp(stream: &mut TcpStream, msg: ECncCtrlMessage) {
         match msg.bin_serialize() {
             Ok(payload) => {
                 match stream.write(payload.as_slice()) {
                     Ok(bytes_written) => {
                         if bytes_written!=payload.len() {
-                            println!("Failed to send all bytes: sent {} of {}", bytes_written, payload.len());
+                            log_error!("Failed to send all bytes: sent {} of {}", bytes_written, payload.len());
                         } else {
-                            println!("Sent {:?}", payload);
+                            log_trace!("Sent {:?}", payload);
+                        }
+                        if let Err(e) = stream.flush() {
+                            log_error!("Error flushing: {:?}", e);
                         }
-                        stream.flush().unwrap();
                     },
                     Err(e) => {
-                        println!("Error sending: {:?}", e);
+                        log_error!("Error sending: {:?}", e);
                     }
                 }
             },
             Err(e) => {
-                println!("Failed to serialize the message: {:?}", e);
+                log_error!("Failed to serialize the message: {:?}", e);
             }
         }
     }
 
-    fn run_tcp(mut stream: TcpStream, cnc: CncConnection<ECncStatusMessage, ECncCtrlMessage>) {
+    /// Serves one controller until it disconnects, the other end quits or goes away, or `job`
+    /// is cancelled. Commands are only written between reads, so the read timeout bounds
+    /// both their latency and how long a cancel takes to be noticed.
+    fn run_tcp(mut stream: TcpStream, cnc: CncConnection<ECncStatusMessage, ECncCtrlMessage>, mut axis_count: usize, job: JobHandle) {
         let mut running = true;
-        stream.set_read_timeout( Some(Duration::from_millis(100)) ).unwrap();
+        if let Err(e) = stream.set_read_timeout( Some(CncConnectionManager::READ_TIMEOUT) ) {
+            log_error!("Can't set the read timeout: {:?}", e);
+            let _ = cnc.send_wait(ECncStatusMessage::EDisconnected);
+            return;
+        }
         let mut ab_recv_buffer: [u8; 512] = [0; 512];
+        // Bytes read but not decoded yet, the start of a message split across reads.
+        let mut received: Vec<u8> = Vec::new();
         while running {
-            match cnc.receive() {
-                Ok(Some(ECncCtrlMessage::EQuit)) => {
-                    stream.shutdown(Shutdown::Both).unwrap();
-                    running = false;
-                },
-                Ok(msg) => {
-                    if let Some(msg) = msg {
+            if job.is_cancelled() {
+                log_debug!("Connection cancelled");
+                let _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+            // Everything the application queued goes out before the next read.
+            loop {
+                match cnc.receive() {
+                    Ok(Some(ECncCtrlMessage::EQuit)) => {
+                        // Fails if the controller has already closed its side.
+                        let _ = stream.shutdown(Shutdown::Both);
+                        running = false;
+                        break;
+                    },
+                    Ok(Some(msg)) => {
                         CncConnectionManager::send_msg_tcp(&mut stream, msg);
-                        // CncConnectionManager::send_msg_tcp(&mut stream, msg);
-                    }
-                },
-                Err(e) => {
-                    if e==mpsc::TryRecvError::Disconnected {
-                        println!("Failed to receive: {:?}", e);
+                    },
+                    Ok(None) | Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        log_debug!("Connection closed by the application");
+                        let _ = stream.shutdown(Shutdown::Both);
                         running = false;
-                    } 
-                },
+                        break;
+                    },
+                }
+            }
+            if !running {
+                break;
             }
     
             match stream.read( &mut ab_recv_buffer ) {
+                Ok( 0 ) => {
+                    log_warn!("Controller closed the connection");
+                    let _ = cnc.send_wait(ECncStatusMessage::EDisconnected);
+                    running = false;
+                },
                 Ok( res ) => {
-                    if res>0 {
-                        // println!("Received {} bytes", res);
-                        let status_type = ab_recv_buffer[0];
-                        if status_type==0 {
-                            match bincode::deserialize::<CncCoordinates>(&ab_recv_buffer[1..]) {
-                                Ok(res) => {
-                                    let temp_coords = res.clone();
-                                    match cnc.send(ECncStatusMessage::ECurrentPosition(res)) {
-                                        Ok( () ) => {
-                                            println!("Received coordinates: {:?}", temp_coords);
-                                        },
-                                        Err(e) => {
-                                            println!("Error sending a received message {:?}", e);
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    println!("Error deserializeing {:?}", *e);
-                                },
-                            }
-                        } else if status_type==1 {
-                            match bincode::deserialize::<CncStatus>(&ab_recv_buffer[1..]) {
-                                Ok(res) => {
-                                    let temp_status = res.clone();
-                                    match cnc.send(ECncStatusMessage::EStatus(res)) {
-                                        Ok( () ) => {
-                                            println!("Received status: {:?}", temp_status);
-                                        },
-                                        Err(e) => {
-                                            println!("Error sending a received message {:?}", e);
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    println!("Error deserializeing {:?}", *e);
-                                },
-                            }
-                        } else if status_type==2 {
-                            match bincode::deserialize::<[PIDParams; 3]>(&ab_recv_buffer[1..]) {
-                                Ok(res) => {
-                                    let temp = res.clone();
-                                    match cnc.send(ECncStatusMessage::EPIDParams(res)) {
-                                        Ok( () ) => {
-                                            println!("Received PID params: {:?}", temp);
-                                        },
-                                        Err(e) => {
-                                            println!("Error sending a received message {:?}", e);
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    println!("Error deserializeing {:?}", *e);
-                                },
-                            }
-                        } else {
-                            println!("Unknown status received: {}", status_type);
+                    received.extend_from_slice(&ab_recv_buffer[..res]);
+                    let mut buffer = received.as_slice();
+                    while !buffer.is_empty() {
+                        let mut rest = buffer;
+                        match ECncStatusMessage::bin_deserialize(&mut rest, axis_count) {
+                            Ok(status) => {
+                                buffer = rest;
+                                if let ECncStatusMessage::EMachineInfo(ref info) = status {
+                                    axis_count = info.axes.len();
+                                }
+                                log_trace!("Received {:?}", status);
+                                // Position updates are superseded by the next one, so only the
+                                // latest is kept rather than delaying everything behind them.
+                                let _ = match status {
+                                    ECncStatusMessage::ECurrentPosition(_) | ECncStatusMessage::EStatus(_) => cnc.send_latest(status),
+                                    _ => cnc.send_wait(status),
+                                };
+                            },
+                            // Kept until the rest of the message arrives.
+                            Err(ref e) if is_truncated(&**e) && buffer.len() < CncConnectionManager::MAX_PARTIAL => break,
+                            Err(e) => {
+                                // Without framing there is no telling where the next message starts.
+                                log_error!("Error deserializeing {:?}, dropping {} bytes", e, buffer.len());
+                                buffer = &[];
+                            },
                         }
                     }
+                    let consumed = received.len() - buffer.len();
+                    received.drain(..consumed);
                 },
                 Err(e) => {
-                    if e.kind()!=io::ErrorKind::WouldBlock {
-                        println!("Error receiving {:?}", e);
+                    if e.kind()!=io::ErrorKind::WouldBlock && e.kind()!=io::ErrorKind::TimedOut {
+                        log_error!("Error receiving {:?}", e);
+                        let _ = cnc.send_wait(ECncStatusMessage::EDisconnected);
+                        running = false;
                     }
                 },
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Connects to a fresh loopback listener, returning both sides.
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_read_timeout(Some(TIMEOUT)).unwrap();
+        (client, server)
+    }
+
+    /// Reads until the peer closes, returning false if it doesn't within `TIMEOUT`.
+    fn closed_by_peer(stream: &mut TcpStream) -> bool {
+        let mut buffer = [0u8; 64];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => return true,
+                Ok(_) => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + TIMEOUT;
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    #[test]
+    fn send_fails_when_the_queue_is_full() {
+        let (app_end, job_end) = CncConnection::<ECncCtrlMessage, ECncStatusMessage>::new_connected_pair();
+        for sequence in 0..CncConnection::<ECncCtrlMessage, ECncStatusMessage>::QUEUE_CAPACITY {
+            app_end.send(ECncCtrlMessage::EPing(sequence as u32)).unwrap();
+        }
+        assert!(app_end.send(ECncCtrlMessage::EPing(0)).is_err());
+        assert!(matches!(job_end.receive(), Ok(Some(ECncCtrlMessage::EPing(0)))));
+        assert!(app_end.send(ECncCtrlMessage::EPing(0)).is_ok());
+    }
+
+    #[test]
+    fn latest_messages_replace_their_kind_and_follow_the_queue() {
+        let (app_end, job_end) = CncConnection::<ECncCtrlMessage, ECncStatusMessage>::new_connected_pair();
+        job_end.send_latest(ECncStatusMessage::EPong(1)).unwrap();
+        job_end.send_latest(ECncStatusMessage::EDisconnected).unwrap();
+        job_end.send_latest(ECncStatusMessage::EPong(2)).unwrap();
+        job_end.send(ECncStatusMessage::EPong(3)).unwrap();
+        assert!(matches!(app_end.receive(), Ok(Some(ECncStatusMessage::EPong(3)))));
+        assert!(matches!(app_end.receive(), Ok(Some(ECncStatusMessage::EDisconnected))));
+        assert!(matches!(app_end.receive(), Ok(Some(ECncStatusMessage::EPong(2)))));
+        assert!(matches!(app_end.receive(), Err(TryRecvError::Empty)));
+        drop(app_end);
+        assert!(job_end.send_latest(ECncStatusMessage::EPong(4)).is_err());
+    }
+
+    #[test]
+    fn dropping_the_connection_frees_the_worker() {
+        let mut manager = CncConnectionManager::new();
+        let (client, mut server) = socket_pair();
+        let connection = manager.run(client, 3);
+        assert_eq!(manager.pool.active_jobs(), 1);
+
+        drop(connection);
+        assert!(closed_by_peer(&mut server));
+        assert!(wait_until(|| manager.pool.active_jobs() == 0));
+    }
+
+    #[test]
+    fn reconnecting_more_often_than_there_are_workers() {
+        let mut manager = CncConnectionManager::new();
+        let mut connection = CncConnection::new();
+        // Kept open so only closing the application end ends a job.
+        let mut servers = Vec::new();
+        for _ in 0..5 {
+            let (client, mut server) = socket_pair();
+            connection.close();
+            connection = manager.run(client, 3);
+            connection.send(ECncCtrlMessage::EPing(7)).unwrap();
+            let mut buffer = [0u8; 64];
+            let len = server.read(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], ECncCtrlMessage::EPing(7).bin_serialize().unwrap().as_slice());
+            servers.push(server);
+        }
+        assert!(wait_until(|| manager.pool.active_jobs() == 1));
+    }
+
+    #[test]
+    fn every_message_in_a_read_is_received() {
+        let mut manager = CncConnectionManager::new();
+        let (client, mut server) = socket_pair();
+        let connection = manager.run(client, 3);
+        let mut payload = ECncStatusMessage::EPong(1).bin_serialize().unwrap();
+        payload.extend(ECncStatusMessage::EPong(2).bin_serialize().unwrap());
+        let third = ECncStatusMessage::EPong(3).bin_serialize().unwrap();
+        payload.extend_from_slice(&third[..2]);
+        server.write_all(&payload).unwrap();
+        assert!(wait_until(|| matches!(connection.receive(), Ok(Some(ECncStatusMessage::EPong(1))))));
+        assert!(matches!(connection.receive(), Ok(Some(ECncStatusMessage::EPong(2)))));
+
+        // The rest of a split message completes it.
+        std::thread::sleep(Duration::from_millis(50));
+        server.write_all(&third[2..]).unwrap();
+        assert!(wait_until(|| matches!(connection.receive(), Ok(Some(ECncStatusMessage::EPong(3))))));
+    }
+
+    #[test]
+    fn quit_closes_the_socket() {
+        let mut manager = CncConnectionManager::new();
+        let (client, mut server) = socket_pair();
+        let connection = manager.run(client, 3);
+        connection.send(ECncCtrlMessage::EQuit).unwrap();
+        assert!(closed_by_peer(&mut server));
+        assert!(wait_until(|| manager.pool.active_jobs() == 0));
+    }
+
+    #[test]
+    fn controller_disconnect_is_reported() {
+        let mut manager = CncConnectionManager::new();
+        let (client, server) = socket_pair();
+        let connection = manager.run(client, 3);
+        drop(server);
+        assert!(wait_until(|| matches!(connection.receive(), Ok(Some(ECncStatusMessage::EDisconnected)))));
+        assert!(wait_until(|| manager.pool.active_jobs() == 0));
+    }
+
+    #[test]
+    fn shutdown_stops_open_connections() {
+        let mut manager = CncConnectionManager::new();
+        let (client, mut server) = socket_pair();
+        let connection = manager.run(client, 3);
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            manager.shutdown();
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(TIMEOUT).is_ok());
+        assert!(closed_by_peer(&mut server));
+        // The application end only notices when it next talks to the gone job.
+        assert!(connection.send(ECncCtrlMessage::EPing(1)).is_err());
+    }
+}
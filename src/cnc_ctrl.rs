@@ -1,106 +1,985 @@
 
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use crate::cnc_msg::{CncCoordinates, ECncCtrlMessage, ECncStatusMessage, PIDParams};
+use crate::cnc_msg::{AlarmAxisDetail, AxisConfig, EAlarmCode, AxisInfo, CncCoordinates, CncStatus, ECncCtrlMessage, ECncStatusMessage, PIDParams};
 use crate::cnc_connection::CncConnection;
+use crate::cnc_pid_history::{EPidSource, PidVerification};
+use crate::cnc_profile::{MachineProfile, PidPreset};
+use crate::cnc_gcode::GCodeProgram;
+use crate::cnc_units::EUnits;
+use crate::cnc_toolpath::Toolpath;
+use crate::cnc_job::{reached, EJobState, JobRunner, ResumePlan};
+use crate::cnc_machine_state::{check_command, ECommand, EMachineState};
+use crate::cnc_bus::{EStatusEvent, StatusBus};
+use crate::cnc_link::LinkStats;
+use crate::cnc_alarm::{Alarm, AlarmLog, EAlarmSource};
+use crate::cnc_following_error::{EFollowingErrorLevel, FollowingErrorLimits, FollowingErrorMonitor};
+use crate::cnc_job_sim::{format_duration, JobSimulation};
+use crate::cnc_job_stats::JobStatistics;
+use crate::cnc_macro::{DryRunReport, EMacroState, Macro, MacroDef, MacroRun};
+use crate::{log_debug, log_error, log_info, log_trace, log_warn};
 
-enum ECncCtrlState {
-    EOffline,
-    EConnected,
-}
-
+/// Controllers that don't answer the handshake are assumed ready after this.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Most messages applied per `update_status` call.
+const STATUS_DRAIN_LIMIT: usize = CncConnection::<ECncCtrlMessage, ECncStatusMessage>::QUEUE_CAPACITY;
 
 pub struct CncCtrl
 {
-    e_cnc_ctrl_state    : ECncCtrlState,
+    machine_state       : EMachineState,
+    state_since         : Instant,
+    /// Latest rejected command, shown to the user for a while.
+    pub last_rejection  : Option<(String, Instant)>,
+    /// Axes of the connected machine, from the controller handshake or the profile.
+    pub axes            : Vec<AxisInfo>,
+    pub axes_from_handshake : bool,
     pub target_coords   : CncCoordinates,
     pub current_coords  : CncCoordinates,
-    pub pid_params      : [PIDParams; 3],
+    pub pid_params      : Vec<PIDParams>,
+    pub profile         : MachineProfile,
+    pub pid_verification: PidVerification,
+    pub axis_config     : Vec<AxisConfig>,
+    /// Axis config version reported by the controller, `None` for PID-only firmware.
+    pub axis_config_version : Option<u16>,
+    /// Units used for display and input; positions themselves are always kept in mm.
+    pub display_units   : EUnits,
+    pub program         : Option<GCodeProgram>,
+    /// Moves of `program`, starting from where the machine was when it was loaded.
+    pub toolpath        : Option<Toolpath>,
+    /// Statistics and run time estimate of `toolpath`.
+    pub job_stats       : Option<JobStatistics>,
+    pub job             : JobRunner,
+    /// Line the last job stopped at before finishing, to offer resuming there.
+    pub interrupted_line: Option<usize>,
+    /// Resume waiting for the user to confirm it.
+    pub pending_resume  : Option<ResumePlan>,
+    /// Playback of the loaded job against the model, kept after it ends for its report.
+    pub simulation      : Option<JobSimulation>,
+    /// The running macro, or the last one until another starts.
+    pub macro_run       : Option<MacroRun>,
+    pub link            : LinkStats,
+    /// Address of the connected controller.
+    pub remote_address  : Option<String>,
+    /// Latched alarms and their history.
+    pub alarms          : AlarmLog,
+    pub following_error : FollowingErrorMonitor,
+    /// Everything received from the controller and the state changes derived from it.
+    pub bus             : StatusBus,
+    /// False when the profile and alarm history belong to another process and are only read.
+    persist             : bool,
     connection          : CncConnection<ECncCtrlMessage, ECncStatusMessage>
 }
 
+impl Default for CncCtrl {
+    fn default() -> Self {
+        CncCtrl::new()
+    }
+}
+
 impl CncCtrl {
     pub fn new() -> CncCtrl {
+        let profile = MachineProfile::load_or_new("default");
+        let axis_count = profile.axes.len();
         CncCtrl{
-            e_cnc_ctrl_state: ECncCtrlState::EOffline,
-            target_coords   : CncCoordinates::new(),
-            current_coords  : CncCoordinates::new(),
-            pid_params      : [PIDParams::new(), PIDParams::new(), PIDParams::new()],
+            machine_state   : EMachineState::EDisconnected,
+            state_since     : Instant::now(),
+            last_rejection  : None,
+            axes            : profile.axes.clone(),
+            axes_from_handshake : false,
+            target_coords   : CncCoordinates::with_axes(axis_count),
+            current_coords  : CncCoordinates::with_axes(axis_count),
+            pid_params      : vec![PIDParams::new(); axis_count],
+            profile,
+            pid_verification: PidVerification::new(axis_count),
+            axis_config     : vec![AxisConfig::new(); axis_count],
+            axis_config_version : None,
+            display_units   : EUnits::EMillimeters,
+            program         : None,
+            toolpath        : None,
+            job_stats       : None,
+            job             : JobRunner::new(),
+            interrupted_line: None,
+            pending_resume  : None,
+            simulation      : None,
+            macro_run       : None,
+            link            : LinkStats::new(),
+            remote_address  : None,
+            alarms          : AlarmLog::load(),
+            following_error : FollowingErrorMonitor::new(axis_count),
+            bus             : StatusBus::new(),
+            persist         : true,
             connection      : CncConnection::new(),
         }
     }
 
+    /// Uses the desktop app's profile but never writes it or the alarm history.
+    pub fn new_read_only() -> CncCtrl {
+        let mut cnc = CncCtrl::new();
+        cnc.persist = false;
+        cnc
+    }
+
+    pub fn axis_count(&self) -> usize {
+        self.axes.len()
+    }
+
+    /// Switches to a different axis layout, resizing all per axis state.
+    pub fn set_axes(&mut self, axes: Vec<AxisInfo>) {
+        let axis_count = axes.len();
+        if axes != self.axes {
+            // The loaded program was resolved against the old axis letters.
+            self.job.stop();
+            self.interrupted_line = None;
+            self.pending_resume = None;
+            self.toolpath = None;
+            self.job_stats = None;
+            self.simulation = None;
+            self.program = None;
+        }
+        self.axes = axes;
+        self.target_coords.resize(axis_count);
+        self.current_coords.resize(axis_count);
+        self.pid_params.resize(axis_count, PIDParams::new());
+        self.axis_config.resize(axis_count, AxisConfig::new());
+        self.pid_verification = PidVerification::new(axis_count);
+        self.following_error.resize(axis_count);
+    }
+
+    pub fn set_display_units(&mut self, units: EUnits) {
+        self.display_units = units;
+    }
+
+    /// Converts a machine value of `axis` (mm or degrees) to display units.
+    pub fn to_display(&self, axis: usize, value: f32) -> f32 {
+        match self.axes.get(axis) {
+            Some(info) => self.display_units.to_units(info.kind, value),
+            None => value,
+        }
+    }
+
+    /// Converts a value typed in display units to machine units.
+    pub fn display_to_machine(&self, axis: usize, value: f32) -> f32 {
+        match self.axes.get(axis) {
+            Some(info) => self.display_units.to_mm(info.kind, value),
+            None => value,
+        }
+    }
+
+    pub fn format_axis_value(&self, axis: usize, value: f32) -> String {
+        match self.axes.get(axis) {
+            Some(info) => self.display_units.format(info.kind, value),
+            None => format!("{:.3}", value),
+        }
+    }
+
+    pub fn state(&self) -> EMachineState {
+        self.machine_state
+    }
+
+    pub fn allows(&self, command: ECommand) -> bool {
+        self.check_command(command).is_ok()
+    }
+
+    /// The machine state decides, except that a running macro keeps the motion to itself.
+    /// Its own commands pass as `update_macro` takes the run out while it executes.
+    fn check_command(&self, command: ECommand) -> Result<(), String> {
+        if command.moves_machine() && self.is_macro_running() {
+            return Err(format!("Can't {} while a macro is running", command.name()));
+        }
+        check_command(self.machine_state, command)
+    }
+
+    /// Checks `command` against the machine state, recording the reason if it is rejected.
+    fn check(&mut self, command: ECommand) -> Result<(), String> {
+        self.check_command(command).map_err(|e| {
+            log_warn!("{}", e);
+            self.last_rejection = Some((e.clone(), Instant::now()));
+            e
+        })
+    }
+
+    fn set_state(&mut self, state: EMachineState) {
+        if state != self.machine_state {
+            log_info!("Machine state {} -> {}", self.machine_state.name(), state.name());
+            self.machine_state = state;
+            self.state_since = Instant::now();
+            self.bus.publish(EStatusEvent::EMachineState(state));
+        }
+    }
+
+    /// Loads a G-code program. If it selects units with G20/G21 the display follows.
+    pub fn load_program(&mut self, path: &str) -> Result<(), String> {
+        self.check(ECommand::ELoadProgram)?;
+        let program = GCodeProgram::load(path)?;
+        if let Some(units) = program.units() {
+            self.set_display_units(units);
+        }
+        let toolpath = Toolpath::build(&program, &self.axes, &self.current_coords.values)?;
+        let stats = JobStatistics::compute(&toolpath, &self.profile.motion_limits);
+        log_info!("Loaded {} with {} blocks, {} moves, estimated {}", program.name, program.lines.len(), stats.moves,
+            format_duration(stats.estimated_duration));
+        self.program = Some(program);
+        self.toolpath = Some(toolpath);
+        self.job_stats = Some(stats);
+        self.simulation = None;
+        self.interrupted_line = None;
+        self.pending_resume = None;
+        self.job.stop();
+        Ok(())
+    }
+
+    /// Plays the loaded job back against the model at `speed` times real time, or
+    /// instantly for `None`. The machine doesn't move.
+    pub fn simulate_job(&mut self, speed: Option<f32>) -> Result<(), String> {
+        self.check(ECommand::ESimulateJob)?;
+        let (toolpath, stats) = match (&self.toolpath, &self.job_stats) {
+            (Some(toolpath), Some(stats)) => (toolpath, stats),
+            _ => return Err(String::from("No program loaded")),
+        };
+        let simulation = JobSimulation::new(toolpath, &self.axes, stats, speed);
+        let report = &simulation.report;
+        log_info!("Simulating job: {}, {:.1} mm rapid, {:.1} mm feed, {} soft-limit violations", format_duration(stats.estimated_duration),
+            stats.rapid_length, stats.cut_length, report.violations.len());
+        for violation in report.violations.iter() {
+            log_warn!("Line {}: {} {:.3} is outside the soft limits", violation.line_number, self.axis_name(violation.axis), violation.value);
+        }
+        self.simulation = Some(simulation);
+        Ok(())
+    }
+
+    pub fn stop_simulation(&mut self) {
+        self.simulation = None;
+    }
+
+    pub fn is_simulating(&self) -> bool {
+        matches!(self.simulation, Some(ref simulation) if !simulation.is_finished())
+    }
+
+    pub fn start_job(&mut self) -> Result<(), String> {
+        self.check(ECommand::EStartJob)?;
+        if self.toolpath.is_none() {
+            return Err(String::from("No program loaded"));
+        }
+        self.simulation = None;
+        self.interrupted_line = None;
+        self.pending_resume = None;
+        self.job.start(0);
+        self.set_state(EMachineState::ERunning);
+        self.update_job();
+        Ok(())
+    }
+
+    /// Plans resuming the job at `line_number` from the current position. It only
+    /// starts once `confirm_resume` is called.
+    pub fn prepare_resume(&mut self, line_number: usize) -> Result<(), String> {
+        self.check(ECommand::EStartJob)?;
+        let plan = self.plan_resume(line_number)?;
+        log_info!("Resuming from line {} with {}, confirm to start", plan.line_number, plan.modal.describe());
+        self.pending_resume = Some(plan);
+        Ok(())
+    }
+
+    /// Starts the prepared resume. It is planned again from the current position: if the
+    /// moves differ from the ones shown, the new plan has to be confirmed instead. Modal
+    /// state isn't sent, so a plan with the spindle on needs `spindle_started`.
+    pub fn confirm_resume(&mut self, spindle_started: bool) -> Result<(), String> {
+        self.check(ECommand::EStartJob)?;
+        let pending = self.pending_resume.take().ok_or_else(|| String::from("No resume prepared"))?;
+        let plan = self.plan_resume(pending.line_number)?;
+        if !plan.same_approach(&pending) {
+            self.pending_resume = Some(plan);
+            return Err(String::from("The machine moved since the resume was planned, check the new moves and confirm again"));
+        }
+        if plan.needs_spindle() && !spindle_started {
+            let e = format!("Line {} cuts with the spindle on, start it by hand and confirm that it runs", plan.line_number);
+            self.pending_resume = Some(plan);
+            return Err(e);
+        }
+        log_info!("Resuming job from line {}", plan.line_number);
+        self.simulation = None;
+        self.interrupted_line = None;
+        self.job.resume_from(&plan);
+        self.set_state(EMachineState::ERunning);
+        self.update_job();
+        Ok(())
+    }
+
+    pub fn cancel_resume(&mut self) {
+        self.pending_resume = None;
+    }
+
+    fn plan_resume(&self, line_number: usize) -> Result<ResumePlan, String> {
+        match (&self.program, &self.toolpath) {
+            (Some(program), Some(toolpath)) => ResumePlan::new(program, toolpath, &self.axes, line_number, &self.current_coords.values),
+            _ => Err(String::from("No program loaded")),
+        }
+    }
+
+    /// Stops the job, remembering where so it can be resumed.
+    fn interrupt_job(&mut self) {
+        if self.job.is_active() {
+            if let Some(segment) = self.toolpath.as_ref().and_then(|toolpath| toolpath.segments.get(self.job.current_segment)) {
+                log_warn!("Job interrupted at line {}", segment.line_number);
+                self.interrupted_line = Some(segment.line_number);
+            }
+        }
+        self.job.stop();
+    }
+
+    pub fn pause_job(&mut self) -> Result<(), String> {
+        self.check(ECommand::EPauseJob)?;
+        self.job.pause();
+        self.set_state(EMachineState::EHolding);
+        Ok(())
+    }
+
+    pub fn resume_job(&mut self) -> Result<(), String> {
+        self.check(ECommand::EResumeJob)?;
+        self.job.resume();
+        self.set_state(EMachineState::ERunning);
+        self.update_job();
+        Ok(())
+    }
+
+    /// Stops the job and holds the axes where they are. The machine counts as moving
+    /// until it has settled there.
+    pub fn stop_job(&mut self) -> Result<(), String> {
+        self.check(ECommand::EStopJob)?;
+        self.interrupt_job();
+        self.hold_position();
+        self.set_state(EMachineState::EJogging);
+        Ok(())
+    }
+
+    /// Moves all axes to their minimum, the machine's home position. The protocol has
+    /// no homing cycle, so this doesn't seek switches.
+    pub fn home(&mut self) -> Result<(), String> {
+        self.check(ECommand::EHome)?;
+        let mut coords = CncCoordinates::with_axes(self.axis_count());
+        for (axis, info) in self.axes.iter().enumerate() {
+            coords.set(axis, info.min);
+        }
+        self.send_target(coords);
+        self.set_state(EMachineState::EHoming);
+        Ok(())
+    }
+
+    /// Stops the job and holds the current position until reset. This is no substitute
+    /// for the hardware emergency stop.
+    pub fn emergency_stop(&mut self) -> Result<(), String> {
+        self.check(ECommand::EEStop)?;
+        self.interrupt_job();
+        self.send_target(self.current_coords.clone());
+        self.set_state(EMachineState::EEStop);
+        Ok(())
+    }
+
+    /// Leaves the alarm or e-stop state. Active alarms must be acknowledged first and
+    /// are cleared on the controller too.
+    pub fn reset(&mut self) -> Result<(), String> {
+        self.check(ECommand::EReset)?;
+        if self.alarms.is_active() {
+            if !self.alarms.all_acknowledged() {
+                let e = String::from("Acknowledge the alarms before resetting");
+                self.last_rejection = Some((e.clone(), Instant::now()));
+                return Err(e);
+            }
+            self.send(ECncCtrlMessage::EResetAlarm)?;
+            log_info!("Alarms reset");
+            self.alarms.clear();
+            self.save_alarms();
+        }
+        self.target_coords = self.current_coords.clone();
+        // A fault that persists after the reset raises the alarm again.
+        self.following_error.reset();
+        self.set_state(EMachineState::EIdle);
+        Ok(())
+    }
+
+    /// Latches `alarm` and stops the machine until it is acknowledged and reset.
+    pub fn raise_alarm(&mut self, alarm: Alarm) {
+        let description = alarm.describe(|axis| self.axis_name(axis));
+        if !self.alarms.raise(alarm.clone()) {
+            return;
+        }
+        self.bus.publish(EStatusEvent::EAlarm(alarm));
+        log_error!("Alarm: {}", description);
+        self.save_alarms();
+        if self.machine_state.has_active_job() {
+            self.interrupt_job();
+        }
+        // The handshake finishes into the alarm state.
+        if !matches!(self.machine_state, EMachineState::EDisconnected | EMachineState::EConnecting | EMachineState::EHandshaking) {
+            self.set_state(EMachineState::EAlarm);
+        }
+    }
+
+    pub fn acknowledge_alarms(&mut self) {
+        if self.alarms.is_active() && !self.alarms.all_acknowledged() {
+            log_info!("Alarms acknowledged");
+            self.alarms.acknowledge();
+            self.save_alarms();
+        }
+    }
+
+    pub fn axis_name(&self, axis: usize) -> String {
+        match self.axes.get(axis) {
+            Some(info) => info.name.clone(),
+            None => format!("#{}", axis),
+        }
+    }
+
+    fn save_alarms(&self) {
+        if !self.persist {
+            return;
+        }
+        if let Err(e) = self.alarms.save() {
+            log_error!("{}", e);
+        }
+    }
+
+    /// Leaves the handshake, into the alarm state if the controller reported one.
+    fn finish_handshake(&mut self) {
+        if self.alarms.is_active() {
+            self.set_state(EMachineState::EAlarm);
+        } else {
+            self.set_state(EMachineState::EIdle);
+        }
+    }
+
+    /// Sends the next segment end once the machine has reached the previous one.
+    fn update_job(&mut self) {
+        if !self.machine_state.has_active_job() {
+            return;
+        }
+        if self.job.state == EJobState::EFinished {
+            log_info!("Job finished in {}", format_duration(self.job.elapsed().as_secs_f32()));
+            self.set_state(EMachineState::EIdle);
+            return;
+        }
+        let next_target = match self.toolpath {
+            Some(ref toolpath) => self.job.update(toolpath, &self.current_coords.values),
+            None => None,
+        };
+        if let Some(target) = next_target {
+            let mut coords = CncCoordinates::with_axes(self.axis_count());
+            for (axis, value) in target.iter().enumerate() {
+                coords.set(axis, *value);
+            }
+            self.send_target(coords);
+        }
+    }
+
+    /// Ends a jog or homing move once the machine is at the target.
+    fn update_motion(&mut self) {
+        if !matches!(self.machine_state, EMachineState::EJogging | EMachineState::EHoming) {
+            return;
+        }
+        if reached(&self.target_coords.values, &self.current_coords.values) {
+            self.set_state(EMachineState::EIdle);
+        }
+    }
+
+    /// Stores the axis layout in the profile. Ignored while the controller dictates it.
+    pub fn set_profile_axes(&mut self, axes: Vec<AxisInfo>) -> Result<(), String> {
+        self.check(ECommand::ESetAxes)?;
+        AxisInfo::validate_axes(&axes)?;
+        self.profile.axes = axes.clone();
+        self.save_profile();
+        if !self.axes_from_handshake {
+            self.set_axes(axes);
+        }
+        Ok(())
+    }
+
+    /// Applies everything the controller sent since the last call. Position and status
+    /// updates are coalesced to the latest one, keeping the worst following error seen in
+    /// between so short spikes still reach the monitor.
     pub fn update_status(&mut self) {
-        match self.connection.receive() {
-            Ok(msg) => {
-                if let Some(status) = msg {
+        let mut latest_position: Option<CncCoordinates> = None;
+        let mut latest_status: Option<CncStatus> = None;
+        let mut worst_errors: Vec<f32> = Vec::new();
+        // Bounded so a flooding controller can't stall the frame, the rest waits in the queue.
+        for _ in 0..STATUS_DRAIN_LIMIT {
+            match self.connection.receive() {
+                Ok(Some(status)) => {
+                    if !matches!(status, ECncStatusMessage::EDisconnected) {
+                        self.link.on_rx();
+                    }
                     match status {
-                        ECncStatusMessage::ECurrentPosition( current ) => {
-                            println!("Received coordinates: {:?}", current.clone());
-                            // self.current_coords = current;
-                            self.set_current_coords(current.x, current.y, current.z);
+                        ECncStatusMessage::ECurrentPosition(current) => {
+                            self.bus.publish(EStatusEvent::EPosition(current.values.clone()));
+                            latest_position = Some(current);
                         },
                         ECncStatusMessage::EStatus(status) => {
-                            self.set_current_coords(status.axis_status[0].position, status.axis_status[1].position, status.axis_status[2].position);
-                        },
-                        ECncStatusMessage::EPIDParams(params) => {
-                            self.update_pid_params(params);    
-                        },
-                        ECncStatusMessage::EDisconnected => {
-        
+                            worst_errors.resize(status.axis_status.len(), 0f32);
+                            for (worst, axis) in worst_errors.iter_mut().zip(status.axis_status.iter()) {
+                                let error = axis.target_position - axis.position;
+                                if error.abs() > worst.abs() {
+                                    *worst = error;
+                                }
+                            }
+                            self.bus.publish(EStatusEvent::EStatus(status.clone()));
+                            latest_status = Some(status);
                         },
+                        status => self.handle_status(status),
+                    }
+                },
+                Ok(None) => break,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(e) => {
+                    if self.is_connected() {
+                        log_error!("Failed to receive: {:?}", e);
+                        self.disconnected();
                     }
+                    break;
+                },
+            }
+        }
+
+        if let Some(current) = latest_position {
+            log_trace!("Received coordinates: {:?}", current);
+            self.set_current_coords(&current.values);
+        }
+        if let Some(status) = latest_status {
+            self.link.cycle_time = Some(status.cycle_time);
+            let positions: Vec<f32> = status.axis_status.iter().map(|axis| axis.position).collect();
+            self.set_current_coords(&positions);
+            self.monitor_following_error(&worst_errors);
+        }
+
+        self.update_handshake();
+        if self.pid_verification.needs_request() {
+            self.request_pid_params();
+        }
+        if self.is_connected() {
+            if let Some(sequence) = self.link.next_ping() {
+                self.send_msg(ECncCtrlMessage::EPing(sequence));
+            }
+        }
+        self.link.update();
+        self.update_motion();
+        self.update_job();
+        self.update_macro();
+        if let Some(ref mut simulation) = self.simulation {
+            simulation.update();
+        }
+    }
+
+    /// Applies a message that isn't coalesced.
+    fn handle_status(&mut self, status: ECncStatusMessage) {
+        match status {
+            ECncStatusMessage::EPIDParams(params) => {
+                self.bus.publish(EStatusEvent::EPIDParams(params.clone()));
+                self.update_pid_params(params);    
+                // Firmware without the handshake answers the PID request first.
+                if self.machine_state == EMachineState::EHandshaking {
+                    self.finish_handshake();
                 }
-            }, 
-            Err(e) => {
-                if e==mpsc::TryRecvError::Disconnected {
-                    println!("Failed to receive: {:?}", e);
+            },
+            ECncStatusMessage::EDisconnected => {
+                log_warn!("Disconnected from {}", self.remote_address.as_deref().unwrap_or("controller"));
+                self.disconnected();
+            },
+            ECncStatusMessage::EAxisConfig(configs) => {
+                self.bus.publish(EStatusEvent::EAxisConfig(configs.clone()));
+                self.update_axis_config(configs);
+            },
+            ECncStatusMessage::EMachineInfo(info) => {
+                self.bus.publish(EStatusEvent::EMachineInfo(info.clone()));
+                log_info!("Controller reports {} axes, protocol v{}", info.axes.len(), info.protocol_version);
+                self.axes_from_handshake = true;
+                if info.axes != self.axes {
+                    self.set_axes(info.axes);
                 }
+                if self.machine_state == EMachineState::EHandshaking {
+                    self.finish_handshake();
+                }
+            },
+            ECncStatusMessage::EPong(sequence) => {
+                self.link.on_pong(sequence);
             },
+            ECncStatusMessage::EAlarm(report) => {
+                self.raise_alarm(Alarm::from_report(report));
+            },
+            ECncStatusMessage::ECurrentPosition(_) | ECncStatusMessage::EStatus(_) => {},
+        }
+    }
+
+    pub fn following_error_limits(&self) -> Vec<FollowingErrorLimits> {
+        (0..self.axis_count())
+            .map(|axis| self.profile.following_error_limits.get(axis).cloned().unwrap_or_else(FollowingErrorLimits::new))
+            .collect()
+    }
+
+    /// Stores the thresholds in the profile.
+    pub fn set_following_error_limits(&mut self, limits: Vec<FollowingErrorLimits>) -> Result<(), String> {
+        for (axis, axis_limits) in limits.iter().enumerate() {
+            axis_limits.validate().map_err(|e| format!("Axis {}: {}", self.axis_name(axis), e))?;
+        }
+        self.profile.following_error_limits = limits;
+        self.save_profile();
+        Ok(())
+    }
+
+    /// Holds the machine and raises an alarm when an axis exceeds its fault threshold.
+    fn monitor_following_error(&mut self, axis_errors: &[f32]) {
+        let limits = self.following_error_limits();
+        let raised = self.following_error.update(axis_errors, &limits);
+        let mut faulted = Vec::new();
+        for (axis, level) in raised {
+            match level {
+                EFollowingErrorLevel::EWarning => {
+                    log_warn!("Following error on {} is {:.3}, above the warning threshold {}", self.axis_name(axis), axis_errors[axis].abs(), limits[axis].warning);
+                },
+                EFollowingErrorLevel::EFault => {
+                    faulted.push(AlarmAxisDetail{ axis: axis as u8, value: axis_errors[axis].abs() });
+                },
+                EFollowingErrorLevel::EOk => {},
+            }
+        }
+        if faulted.is_empty() || !self.is_connected() {
+            return;
+        }
+        self.hold_position();
+        self.raise_alarm(Alarm::new(EAlarmCode::EFollowingError, faulted, EAlarmSource::EDesktop));
+    }
+
+    /// Stops the axes at the last reported position. Firmware without feed hold ignores it,
+    /// the target at the current position stops it too.
+    fn hold_position(&mut self) {
+        self.send_msg(ECncCtrlMessage::EFeedHold);
+        self.send_target(self.current_coords.clone());
+    }
+
+    /// Sends the handshake requests for a new connection, and stops waiting for an
+    /// answer after `HANDSHAKE_TIMEOUT`.
+    fn update_handshake(&mut self) {
+        match self.machine_state {
+            EMachineState::EConnecting => {
+                // Firmware that knows the handshake reports its axes, otherwise the profile applies.
+                self.send_msg(ECncCtrlMessage::ERequestMachineInfo);
+                // Firmware that knows the extended config answers this, older firmware ignores it.
+                self.send_msg(ECncCtrlMessage::ERequestAxisConfig);
+                self.request_pid_params();
+                self.set_state(EMachineState::EHandshaking);
+            },
+            EMachineState::EHandshaking if self.state_since.elapsed() > HANDSHAKE_TIMEOUT => {
+                log_warn!("Controller didn't answer the handshake, using the profile");
+                self.use_profile_axes();
+                self.finish_handshake();
+            },
+            _ => {},
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.interrupt_job();
+        self.set_state(EMachineState::EDisconnected);
+        self.use_profile_axes();
+    }
+
+    /// Goes back to the profile's axes once the controller that reported others is gone.
+    fn use_profile_axes(&mut self) {
+        self.axes_from_handshake = false;
+        if self.axes != self.profile.axes {
+            log_info!("Using the profile's axes {}", AxisInfo::format_axes(&self.profile.axes));
+            self.set_axes(self.profile.axes.clone());
         }
     }
 
-    pub fn set_current_coords(&mut self, x: f32, y: f32, z: f32) {
-        self.current_coords.x = x;
-        self.current_coords.y = y;
-        self.current_coords.z = z;
+    /// Axes a new connection decodes replies for until the controller reports its own.
+    pub fn connection_axis_count(&self) -> usize {
+        self.profile.axes.len()
     }
 
-    pub fn set_target_coords(&mut self, target_pos: CncCoordinates) {
+    pub fn is_connected(&self) -> bool {
+        self.machine_state.is_connected()
+    }
+
+    pub fn set_current_coords(&mut self, positions: &[f32]) {
+        for (axis, position) in positions.iter().enumerate() {
+            self.current_coords.set(axis, *position);
+        }
+    }
+
+    /// Moves the machine to `target_pos` on user request.
+    pub fn set_target_coords(&mut self, target_pos: CncCoordinates) -> Result<(), String> {
+        self.check(ECommand::EJog)?;
+        self.send_target(target_pos);
+        self.set_state(EMachineState::EJogging);
+        Ok(())
+    }
+
+    fn send_target(&mut self, target_pos: CncCoordinates) {
         self.target_coords = target_pos;
 
-        match self.connection.send(ECncCtrlMessage::ETargetPosition(self.target_coords.clone())) {
+        match self.send(ECncCtrlMessage::ETargetPosition(self.target_coords.clone())) {
             Ok( () ) => {
-                println!("Message sent");
+                log_trace!("Message sent");
             }, 
             Err(e) => {
-                println!("Failed to send a message: {:?}", e);
+                log_error!("Failed to send a message: {:?}", e);
             }
         }
     }
     
-    pub fn set_pid_params(&mut self, x_axis: &PIDParams, y_axis: &PIDParams, z_axis: &PIDParams) {
-        match self.connection.send(ECncCtrlMessage::EPIDParams([x_axis.clone(), y_axis.clone(), z_axis.clone()])) {
+    pub fn set_pid_params(&mut self, params: &[PIDParams], note: &str) -> Result<(), String> {
+        self.check(ECommand::EWriteConfig)?;
+        if params.len() != self.axis_count() {
+            return Err(format!("Can't send PID params for {} axes to a {} axis machine", params.len(), self.axis_count()));
+        }
+        match self.send(ECncCtrlMessage::EPIDParams(params.to_vec())) {
             Ok(()) => {
-                println!("Message sent with PID params");
+                log_debug!("Message sent with PID params");
+                self.profile.pid_history.record(EPidSource::ESent, params, note);
+                self.save_profile();
+                self.pid_verification.start(params);
+                self.request_pid_params();
+                Ok(())
             },
             Err(e) => {
-                println!("Failed to send a message with PID params: {:?}", e);
+                Err(format!("Failed to send a message with PID params: {:?}", e))
             }
         }
     }
     
-    pub fn update_pid_params(&mut self, pid_params: [PIDParams; 3]) {
+    pub fn update_pid_params(&mut self, pid_params: Vec<PIDParams>) {
+        if pid_params.len() != self.axis_count() {
+            log_warn!("Ignoring PID params for {} axes on a {} axis machine", pid_params.len(), self.axis_count());
+            self.pid_verification.on_received(&pid_params);
+            return;
+        }
+        if self.profile.pid_history.record(EPidSource::EReceived, &pid_params, "").is_some() {
+            self.save_profile();
+        }
+        self.pid_verification.on_received(&pid_params);
         self.pid_params = pid_params;
     }
 
+    /// Sends the gains as plain PID params, which every firmware understands, and the
+    /// full config only if the controller has reported that it supports it.
+    pub fn set_axis_config(&mut self, configs: &[AxisConfig], note: &str) -> Result<(), String> {
+        self.check(ECommand::EWriteConfig)?;
+        if configs.len() != self.axis_count() {
+            return Err(format!("Expected config for {} axes, got {}", self.axis_count(), configs.len()));
+        }
+        for (axis, config) in self.axes.iter().zip(configs.iter()) {
+            if let Err(e) = config.validate() {
+                return Err(format!("Axis {}: {}", axis.name, e));
+            }
+        }
+
+        if let Some(version) = self.axis_config_version.filter(|version| *version != AxisConfig::VERSION) {
+            return Err(format!("Controller uses axis config v{}, this build can only send v{}", version, AxisConfig::VERSION));
+        }
+
+        let pid_params: Vec<PIDParams> = configs.iter().map(|config| config.pid.clone()).collect();
+        self.set_pid_params(&pid_params, note)?;
+
+        if self.axis_config_version.is_none() {
+            return Ok(());
+        }
+        match self.send(ECncCtrlMessage::EAxisConfig(configs.to_vec())) {
+            Ok(()) => {
+                log_debug!("Message sent with axis config");
+                self.send_msg(ECncCtrlMessage::ERequestAxisConfig);
+                Ok(())
+            },
+            Err(e) => {
+                Err(format!("Failed to send a message with axis config: {:?}", e))
+            }
+        }
+    }
+
+    pub fn update_axis_config(&mut self, configs: Vec<AxisConfig>) {
+        if configs.len() != self.axis_count() {
+            log_warn!("Ignoring axis config for {} axes on a {} axis machine", configs.len(), self.axis_count());
+            return;
+        }
+        if let Some(config) = configs.first() {
+            self.axis_config_version = Some(config.version);
+        }
+        self.axis_config = configs;
+    }
+
+    /// Sends to the controller, counting the message for the link statistics.
+    fn send(&mut self, msg: ECncCtrlMessage) -> Result<(), String> {
+        self.connection.send(msg)?;
+        self.link.on_tx();
+        Ok(())
+    }
+
+    fn send_msg(&mut self, msg: ECncCtrlMessage) {
+        if let Err(e) = self.send(msg) {
+            log_error!("{}", e);
+        }
+    }
+
+    /// Asks the controller to report the PID params it is currently using.
+    pub fn request_pid_params(&mut self) {
+        match self.send(ECncCtrlMessage::ERequestPIDParams) {
+            Ok(()) => {
+                self.pid_verification.on_requested();
+            },
+            Err(e) => {
+                log_error!("Failed to request PID params: {:?}", e);
+            }
+        }
+    }
+
+    /// Re-sends the parameter set recorded as `version` in the history.
+    pub fn rollback_pid_params(&mut self, version: u32) -> Result<(), String> {
+        let params = match self.profile.pid_history.get(version) {
+            Some(entry) => entry.params.clone(),
+            None => {
+                return Err(format!("Can't roll back: PID history has no version {}", version));
+            }
+        };
+        self.set_pid_params(&params, &format!("rollback to v{}", version))
+    }
+
+    pub fn set_pid_note(&mut self, version: u32, note: &str) {
+        if self.profile.pid_history.set_note(version, note) {
+            self.save_profile();
+        }
+    }
+
+    pub fn save_pid_preset(&mut self, name: &str, params: Vec<PIDParams>) {
+        self.profile.save_preset(PidPreset{ name: String::from(name), params });
+        self.save_profile();
+    }
+
+    pub fn remove_pid_preset(&mut self, name: &str) {
+        self.profile.remove_preset(name);
+        self.save_profile();
+    }
+
+    /// Adds a preset file to the profile, refusing one made for another axis count.
+    pub fn import_pid_preset(&mut self, path: &str) -> Result<(), String> {
+        let preset = PidPreset::import(path)?;
+        if preset.params.len() != self.axis_count() {
+            return Err(format!("Preset {} has params for {} axes, the machine has {}", preset.name, preset.params.len(), self.axis_count()));
+        }
+        self.profile.save_preset(preset);
+        self.save_profile();
+        Ok(())
+    }
+
+    /// Checks the macro and stores it in the profile.
+    pub fn save_macro(&mut self, definition: MacroDef) -> Result<(), String> {
+        Macro::compile(&definition, &self.axes)?;
+        self.profile.save_macro(definition);
+        self.save_profile();
+        Ok(())
+    }
+
+    pub fn remove_macro(&mut self, name: &str) {
+        self.profile.remove_macro(name);
+        self.save_profile();
+    }
+
+    fn compile_macro(&self, name: &str) -> Result<Macro, String> {
+        let definition = self.profile.macros.iter().find(|m| m.name == name)
+            .ok_or_else(|| format!("No macro named {}", name))?;
+        Macro::compile(definition, &self.axes)
+    }
+
+    pub fn is_macro_running(&self) -> bool {
+        matches!(self.macro_run, Some(ref run) if run.state == EMacroState::ERunning)
+    }
+
+    pub fn run_macro(&mut self, name: &str) -> Result<(), String> {
+        self.check(ECommand::ERunMacro)?;
+        let program = self.compile_macro(name)?;
+        log_info!("Running macro {}", name);
+        self.macro_run = Some(MacroRun::new(program, self.current_coords.values.clone(), false));
+        self.update_macro();
+        Ok(())
+    }
+
+    /// Runs the macro against a model of the machine starting at the current position.
+    pub fn dry_run_macro(&self, name: &str) -> Result<DryRunReport, String> {
+        let program = self.compile_macro(name)?;
+        Ok(DryRunReport::run(program, &self.axes, self.current_coords.values.clone()))
+    }
+
+    /// Stops the macro after its current statement; a move already sent is not cancelled.
+    pub fn stop_macro(&mut self) {
+        if let Some(ref mut run) = self.macro_run {
+            if run.state == EMacroState::ERunning {
+                run.stop();
+                log_info!("Stopped macro {} at line {}", run.name(), run.line());
+            }
+        }
+    }
+
+    fn update_macro(&mut self) {
+        let mut run = match self.macro_run.take() {
+            Some(run) if run.state == EMacroState::ERunning => run,
+            other => {
+                self.macro_run = other;
+                return;
+            },
+        };
+        let printed = run.output.len();
+        run.update(self);
+        for line in run.output[printed..].iter() {
+            log_info!("{}: {}", run.name(), line);
+        }
+        match run.state {
+            EMacroState::EFinished => log_info!("Macro {} finished", run.name()),
+            EMacroState::EFailed(ref e) => log_error!("Macro {} failed at {}", run.name(), e),
+            _ => {},
+        }
+        self.macro_run = Some(run);
+    }
+
+    pub fn load_profile(&mut self, name: &str) -> Result<(), String> {
+        self.check(ECommand::ESetAxes)?;
+        MachineProfile::check_name(name)?;
+        self.profile = MachineProfile::load_or_new(name);
+        if !self.axes_from_handshake {
+            self.set_axes(self.profile.axes.clone());
+        }
+        // A program kept across the switch is estimated with the new machine's limits.
+        if let Some(ref toolpath) = self.toolpath {
+            self.job_stats = Some(JobStatistics::compute(toolpath, &self.profile.motion_limits));
+            self.simulation = None;
+        }
+        Ok(())
+    }
+
+    fn save_profile(&self) {
+        if !self.persist {
+            return;
+        }
+        if let Err(e) = self.profile.save() {
+            log_error!("{}", e);
+        }
+    }
+
     pub fn get_target_coords(&self) -> CncCoordinates {
         self.target_coords.clone()
     }
     
-    pub fn set_connection(&mut self, connection: CncConnection<ECncCtrlMessage, ECncStatusMessage>) {
+    /// Takes over a new controller connection and starts the handshake.
+    pub fn set_connection(&mut self, connection: CncConnection<ECncCtrlMessage, ECncStatusMessage>, remote_address: String) -> Result<(), String> {
+        self.check(ECommand::EConnect)?;
+        // Frees the worker serving the previous controller.
+        let _ = self.connection.send(ECncCtrlMessage::EQuit);
+        self.connection.close();
         self.connection = connection;
-        self.e_cnc_ctrl_state = ECncCtrlState::EConnected;
+        self.remote_address = Some(remote_address);
+        self.use_profile_axes();
+        self.link = LinkStats::new();
+        self.following_error.reset();
+        self.pid_verification = PidVerification::new(self.axis_count());
+        self.axis_config_version = None;
+        self.set_state(EMachineState::EConnecting);
+        self.update_handshake();
+        Ok(())
     }
     pub fn quit(&mut self) {
         match self.connection.send(ECncCtrlMessage::EQuit) {
@@ -108,9 +987,54 @@ impl CncCtrl {
 
             },
             Err(e) => {
-                println!("Cant send quit message: {}", e);
+                log_warn!("Cant send quit message: {}", e);
             }
         }
-        self.e_cnc_ctrl_state = ECncCtrlState::EOffline;
+        self.connection.close();
+        self.set_state(EMachineState::EDisconnected);
+        self.use_profile_axes();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc_gcode::GCodeProgram;
+
+    /// A controller without the handshake, answering the PID request, running `program`.
+    fn running_job(program: &str) -> (CncCtrl, CncConnection<ECncStatusMessage, ECncCtrlMessage>) {
+        let mut cnc = CncCtrl::new_read_only();
+        cnc.set_axes(AxisInfo::default_axes());
+        let (app_end, controller) = CncConnection::new_connected_pair();
+        cnc.set_connection(app_end, String::from("test")).unwrap();
+        controller.send(ECncStatusMessage::EPIDParams(vec![PIDParams::new(); 3])).unwrap();
+        cnc.update_status();
+        assert_eq!(cnc.state(), EMachineState::EIdle);
+
+        let program = GCodeProgram::parse("test", program).unwrap();
+        cnc.toolpath = Some(Toolpath::build(&program, &cnc.axes, &[0f32, 0f32, 0f32]).unwrap());
+        cnc.program = Some(program);
+        cnc.start_job().unwrap();
+        (cnc, controller)
+    }
+
+    #[test]
+    fn stopping_a_job_holds_the_current_position() {
+        let (mut cnc, controller) = running_job("G1 X100 F600");
+        controller.send_latest(ECncStatusMessage::ECurrentPosition(CncCoordinates{ values: vec![40f32, 0f32, 0f32] })).unwrap();
+        cnc.update_status();
+        while let Ok(Some(_)) = controller.receive() {}
+
+        cnc.stop_job().unwrap();
+        assert!(matches!(controller.receive(), Ok(Some(ECncCtrlMessage::EFeedHold))));
+        match controller.receive() {
+            Ok(Some(ECncCtrlMessage::ETargetPosition(target))) => assert_eq!(target.values, vec![40f32, 0f32, 0f32]),
+            other => panic!("Expected the current position as target, got {:?}", other),
+        }
+        // Still moving until the machine is seen at the held position.
+        assert!(!cnc.allows(ECommand::EStartJob));
+        controller.send_latest(ECncStatusMessage::ECurrentPosition(CncCoordinates{ values: vec![40f32, 0f32, 0f32] })).unwrap();
+        cnc.update_status();
+        assert_eq!(cnc.state(), EMachineState::EIdle);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+/// Following error thresholds of one axis, in machine units.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct FollowingErrorLimits {
+    /// Above this the axis is flagged but keeps moving.
+    pub warning : f32,
+    /// Above this the machine is held and an alarm raised.
+    pub fault   : f32,
+}
+
+impl Default for FollowingErrorLimits {
+    fn default() -> Self {
+        FollowingErrorLimits::new()
+    }
+}
+
+impl FollowingErrorLimits {
+    pub fn new() -> Self {
+        FollowingErrorLimits{
+            warning : 4f32,
+            fault   : 10f32,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.warning.is_finite() || self.warning <= 0f32 {
+            return Err(format!("Warning threshold must be above 0, got {}", self.warning));
+        }
+        if !self.fault.is_finite() || self.fault <= self.warning {
+            return Err(format!("Fault threshold {} must be above the warning threshold {}", self.fault, self.warning));
+        }
+        Ok(())
+    }
+
+    pub fn level(&self, error: f32) -> EFollowingErrorLevel {
+        if error >= self.fault {
+            EFollowingErrorLevel::EFault
+        } else if error >= self.warning {
+            EFollowingErrorLevel::EWarning
+        } else {
+            EFollowingErrorLevel::EOk
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EFollowingErrorLevel {
+    EOk,
+    EWarning,
+    EFault,
+}
+
+/// Following error of one axis over the last `FollowingErrorMonitor::WINDOW` samples.
+#[derive(Clone, Debug)]
+pub struct AxisFollowingError {
+    /// Absolute error of the latest status.
+    pub current : f32,
+    /// Largest error since the last `reset`.
+    pub peak    : f32,
+    pub level   : EFollowingErrorLevel,
+    recent      : VecDeque<f32>,
+}
+
+impl AxisFollowingError {
+    fn new() -> Self {
+        AxisFollowingError{
+            current : 0f32,
+            peak    : 0f32,
+            level   : EFollowingErrorLevel::EOk,
+            recent  : VecDeque::with_capacity(FollowingErrorMonitor::WINDOW),
+        }
+    }
+
+    /// Largest error in the recent window.
+    pub fn recent_max(&self) -> f32 {
+        self.recent.iter().cloned().fold(0f32, f32::max)
+    }
+}
+
+/// Compares each axis' reported position with its setpoint against the profile thresholds.
+pub struct FollowingErrorMonitor {
+    pub axes    : Vec<AxisFollowingError>,
+}
+
+impl FollowingErrorMonitor {
+    /// Status samples kept per axis, about two seconds at the usual status rate.
+    pub const WINDOW: usize = 100;
+
+    pub fn new(axis_count: usize) -> Self {
+        FollowingErrorMonitor{
+            axes    : vec![AxisFollowingError::new(); axis_count],
+        }
+    }
+
+    pub fn resize(&mut self, axis_count: usize) {
+        self.axes.resize(axis_count, AxisFollowingError::new());
+    }
+
+    pub fn reset(&mut self) {
+        let axis_count = self.axes.len();
+        *self = FollowingErrorMonitor::new(axis_count);
+    }
+
+    /// Worst level over all axes.
+    pub fn level(&self) -> EFollowingErrorLevel {
+        self.axes.iter().map(|axis| axis.level).max().unwrap_or(EFollowingErrorLevel::EOk)
+    }
+
+    /// Records one status worth of errors. Returns the axes whose level rose, with the new level.
+    pub fn update(&mut self, errors: &[f32], limits: &[FollowingErrorLimits]) -> Vec<(usize, EFollowingErrorLevel)> {
+        let mut raised = Vec::new();
+        for (index, (axis, error)) in self.axes.iter_mut().zip(errors.iter()).enumerate() {
+            let error = error.abs();
+            axis.current = error;
+            axis.peak = axis.peak.max(error);
+            if axis.recent.len() == FollowingErrorMonitor::WINDOW {
+                axis.recent.pop_front();
+            }
+            axis.recent.push_back(error);
+
+            let level = limits.get(index).cloned().unwrap_or_else(FollowingErrorLimits::new).level(error);
+            if level > axis.level {
+                raised.push((index, level));
+            }
+            axis.level = level;
+        }
+        raised
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use EFollowingErrorLevel::*;
+
+    #[test]
+    fn levels_are_reported_when_they_rise() {
+        let limits = [FollowingErrorLimits{ warning: 1f32, fault: 2f32 }, FollowingErrorLimits::new()];
+        let mut monitor = FollowingErrorMonitor::new(2);
+        // Errors per status and the axes whose level rose.
+        let cases = [
+            ([0.5f32, 0f32], Vec::new(), EOk),
+            ([-1.5f32, 0f32], vec![(0, EWarning)], EWarning),
+            ([1.2f32, 4f32], vec![(1, EWarning)], EWarning),
+            ([2f32, 3f32], vec![(0, EFault)], EFault),
+            ([0f32, 0f32], Vec::new(), EOk),
+            ([3f32, 12f32], vec![(0, EFault), (1, EFault)], EFault),
+        ];
+        for (index, (errors, raised, level)) in cases.iter().enumerate() {
+            assert_eq!(&monitor.update(errors, &limits), raised, "case {}", index);
+            assert_eq!(monitor.level(), *level, "case {}", index);
+        }
+        assert_eq!(monitor.axes[1].peak, 12f32);
+        assert_eq!(monitor.axes[0].recent_max(), 3f32);
+        monitor.reset();
+        assert_eq!(monitor.axes[1].peak, 0f32);
+    }
+
+    #[test]
+    fn axes_without_limits_use_the_defaults() {
+        let mut monitor = FollowingErrorMonitor::new(1);
+        assert_eq!(monitor.update(&[FollowingErrorLimits::new().fault], &[]), vec![(0, EFault)]);
+    }
+
+    #[test]
+    fn limits_must_be_ordered_and_positive() {
+        let cases = [(4f32, 10f32, true), (0f32, 10f32, false), (4f32, 4f32, false), (f32::NAN, 10f32, false), (4f32, f32::INFINITY, false)];
+        for (warning, fault, valid) in cases {
+            assert_eq!(FollowingErrorLimits{ warning, fault }.validate().is_ok(), valid, "{} {}", warning, fault);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs;
+
+use crate::cnc_units::EUnits;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeWord {
+    pub letter  : char,
+    pub value   : f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct GCodeLine {
+    /// 1-based line number in the source file.
+    pub line_number : usize,
+    pub text        : String,
+    pub words       : Vec<GCodeWord>,
+}
+
+impl GCodeLine {
+    /// True if the line contains e.g. `G20` for `has_code('G', 20.0)`. Codes with a
+    /// decimal part such as `G91.1` are compared to one decimal place.
+    pub fn has_code(&self, letter: char, code: f32) -> bool {
+        self.words.iter().any(|word| word.letter == letter && (word.value * 10f32).round() == (code * 10f32).round())
+    }
+}
+
+pub struct GCodeProgram {
+    pub name    : String,
+    pub lines   : Vec<GCodeLine>,
+}
+
+impl GCodeProgram {
+    pub fn load(path: &str) -> Result<GCodeProgram, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {:?}", path, e))?;
+        GCodeProgram::parse(path, &text)
+    }
+
+    /// Splits the program into words, dropping comments, block numbers and empty lines.
+    pub fn parse(name: &str, text: &str) -> Result<GCodeProgram, String> {
+        let mut lines = Vec::new();
+        for (i, raw_line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let words = parse_words(raw_line).map_err(|e| format!("{}:{}: {}", name, line_number, e))?;
+            if !words.is_empty() {
+                lines.push(GCodeLine{
+                    line_number,
+                    text    : String::from(raw_line.trim()),
+                    words,
+                });
+            }
+        }
+        Ok(GCodeProgram{
+            name    : String::from(name),
+            lines,
+        })
+    }
+
+    /// Units selected by the first G20/G21 in the program, if any.
+    pub fn units(&self) -> Option<EUnits> {
+        for line in &self.lines {
+            if line.has_code('G', 20f32) {
+                return Some(EUnits::EInches);
+            }
+            if line.has_code('G', 21f32) {
+                return Some(EUnits::EMillimeters);
+            }
+        }
+        None
+    }
+}
+
+fn parse_words(line: &str) -> Result<Vec<GCodeWord>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ';' || c == '%' {
+            break;
+        }
+        if c == '(' {
+            for c in chars.by_ref() {
+                if c == ')' {
+                    break;
+                }
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+        if !c.is_ascii_alphabetic() {
+            return Err(format!("Unexpected '{}'", c));
+        }
+
+        let letter = c.to_ascii_uppercase();
+        let mut number = String::new();
+        while let Some(&next) = chars.peek() {
+            if next.is_ascii_digit() || next == '.' || next == '-' || next == '+' {
+                number.push(next);
+                chars.next();
+            } else if next == ' ' && number.is_empty() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let value = number.parse::<f32>().map_err(|_| format!("Missing or bad number after '{}'", letter))?;
+        if letter != 'N' {
+            words.push(GCodeWord{ letter, value });
+        }
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(letter: char, value: f32) -> GCodeWord {
+        GCodeWord{ letter, value }
+    }
+
+    #[test]
+    fn lines_split_into_words() {
+        let cases: [(&str, Vec<GCodeWord>); 6] = [
+            ("G1 X10 Y-2.5 F600", vec![word('G', 1f32), word('X', 10f32), word('Y', -2.5f32), word('F', 600f32)]),
+            ("g0x1y2", vec![word('G', 0f32), word('X', 1f32), word('Y', 2f32)]),
+            ("N10 G91.1 (comment X5) Z+.5", vec![word('G', 91.1f32), word('Z', 0.5f32)]),
+            ("X 3 ; trailing X4", vec![word('X', 3f32)]),
+            ("%", vec![]),
+            ("(only a comment)", vec![]),
+        ];
+        for (line, words) in cases {
+            assert_eq!(parse_words(line).unwrap(), words, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn bad_lines_report_their_number() {
+        for line in ["G1 X", "G1 #1", "X1.2.3"] {
+            assert!(parse_words(line).is_err(), "{:?}", line);
+        }
+        let error = GCodeProgram::parse("job", "G0 X0\n\nG1 X?").err().unwrap();
+        assert!(error.starts_with("job:3:"), "{}", error);
+    }
+
+    #[test]
+    fn blank_lines_are_dropped_and_units_found() {
+        let program = GCodeProgram::parse("job", "( setup )\nG90\n\nG20 G0 X1\nG21").unwrap();
+        assert_eq!(program.lines.iter().map(|line| line.line_number).collect::<Vec<_>>(), vec![2, 4, 5]);
+        assert!(program.lines[1].has_code('G', 20f32));
+        assert_eq!(program.units(), Some(EUnits::EInches));
+    }
+}
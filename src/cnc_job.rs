@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::cnc_gcode::GCodeProgram;
+use crate::cnc_msg::{AxisInfo, EAxisKind};
+use crate::cnc_toolpath::{apply_modal_words, modal_state_before, ESpindle, ModalState, Toolpath};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EJobState {
+    EIdle,
+    ERunning,
+    EPaused,
+    EFinished,
+}
+
+/// Streams a toolpath to the controller one segment end at a time. The controller
+/// only understands target positions, so a segment counts as done once the
+/// reported position is within `ARRIVAL_TOLERANCE` of its end.
+pub struct JobRunner {
+    pub state           : EJobState,
+    /// Index of the segment being executed, or the next one to send while paused.
+    pub current_segment : usize,
+    /// True once the current segment's end has been sent as the target.
+    sent                : bool,
+    /// Targets to reach before the current segment, when resuming part way through.
+    approach            : VecDeque<Vec<f32>>,
+    started_at          : Option<Instant>,
+    paused_at           : Option<Instant>,
+    finished_at         : Option<Instant>,
+    /// Time spent paused, left out of `elapsed`.
+    paused_for          : Duration,
+}
+
+impl Default for JobRunner {
+    fn default() -> Self {
+        JobRunner::new()
+    }
+}
+
+impl JobRunner {
+    /// Distance in mm (or degrees) at which a target counts as reached.
+    pub const ARRIVAL_TOLERANCE: f32 = 0.05f32;
+
+    pub fn new() -> Self {
+        JobRunner{
+            state           : EJobState::EIdle,
+            current_segment : 0,
+            sent            : false,
+            approach        : VecDeque::new(),
+            started_at      : None,
+            paused_at       : None,
+            finished_at     : None,
+            paused_for      : Duration::ZERO,
+        }
+    }
+
+    pub fn start(&mut self, first_segment: usize) {
+        self.state = EJobState::ERunning;
+        self.current_segment = first_segment;
+        self.sent = false;
+        self.approach.clear();
+        self.started_at = Some(Instant::now());
+        self.paused_at = None;
+        self.finished_at = None;
+        self.paused_for = Duration::ZERO;
+    }
+
+    /// Starts at the plan's first segment after moving along its approach.
+    pub fn resume_from(&mut self, plan: &ResumePlan) {
+        self.start(plan.first_segment);
+        self.approach = plan.approach.iter().map(|(_, target)| target.clone()).collect();
+    }
+
+    pub fn pause(&mut self) {
+        if self.state == EJobState::ERunning {
+            self.state = EJobState::EPaused;
+            self.paused_at = Some(Instant::now());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.state == EJobState::EPaused {
+            self.state = EJobState::ERunning;
+            if let Some(paused_at) = self.paused_at.take() {
+                self.paused_for += paused_at.elapsed();
+            }
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = EJobState::EIdle;
+        self.current_segment = 0;
+        self.sent = false;
+        self.approach.clear();
+        self.started_at = None;
+    }
+
+    /// Time spent running since the start, not counting pauses.
+    pub fn elapsed(&self) -> Duration {
+        let started_at = match self.started_at {
+            Some(started_at) => started_at,
+            None => return Duration::ZERO,
+        };
+        let end = self.finished_at.or(self.paused_at).unwrap_or_else(Instant::now);
+        end.duration_since(started_at).saturating_sub(self.paused_for)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state == EJobState::ERunning || self.state == EJobState::EPaused
+    }
+
+    /// Number of segments fully executed.
+    pub fn completed_segments(&self, toolpath: &Toolpath) -> usize {
+        match self.state {
+            EJobState::EFinished => toolpath.segments.len(),
+            EJobState::EIdle => 0,
+            _ => self.current_segment,
+        }
+    }
+
+    /// Advances the job given the reported position. Returns the next target to send,
+    /// if any. A paused job finishes the segment it already sent, then holds.
+    pub fn update(&mut self, toolpath: &Toolpath, position: &[f32]) -> Option<Vec<f32>> {
+        if !self.is_active() {
+            return None;
+        }
+        if self.sent {
+            let target = match self.approach.front() {
+                Some(target) => target,
+                None => &toolpath.segments.get(self.current_segment)?.end,
+            };
+            if !reached(target, position) {
+                return None;
+            }
+            if self.approach.pop_front().is_none() {
+                self.current_segment += 1;
+            }
+            self.sent = false;
+        }
+        if let Some(target) = self.approach.front() {
+            if self.state == EJobState::EPaused {
+                return None;
+            }
+            self.sent = true;
+            return Some(target.clone());
+        }
+        if self.current_segment >= toolpath.segments.len() {
+            self.state = EJobState::EFinished;
+            self.finished_at = Some(Instant::now());
+            return None;
+        }
+        if self.state == EJobState::EPaused {
+            return None;
+        }
+        self.sent = true;
+        Some(toolpath.segments[self.current_segment].end.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EApproachMove {
+    /// Up to the safe height where the machine is.
+    ERetract,
+    /// Across at the safe height to above the resume point.
+    ETraverse,
+    /// Down to the resume point.
+    EPlunge,
+}
+
+impl EApproachMove {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EApproachMove::ERetract => "retract",
+            EApproachMove::ETraverse => "traverse",
+            EApproachMove::EPlunge => "plunge",
+        }
+    }
+}
+
+/// How to pick a job up again part way through, shown to the user before it starts.
+#[derive(Clone, Debug)]
+pub struct ResumePlan {
+    /// Source line of `first_segment`, which may be after the requested line.
+    pub line_number     : usize,
+    pub first_segment   : usize,
+    /// Modal state in effect at `line_number`.
+    pub modal           : ModalState,
+    /// Moves from the current position to the start of `first_segment`.
+    pub approach        : Vec<(EApproachMove, Vec<f32>)>,
+}
+
+impl ResumePlan {
+    /// Plans resuming at `line_number` from `position`. A linear third axis is taken as Z:
+    /// it retracts to the highest point of the job or of the machine's position, the
+    /// other axes move across and Z plunges last.
+    pub fn new(program: &GCodeProgram, toolpath: &Toolpath, axes: &[AxisInfo], line_number: usize, position: &[f32]) -> Result<ResumePlan, String> {
+        let first_segment = toolpath.first_segment_from_line(line_number)
+            .ok_or_else(|| format!("No moves on line {} or after it", line_number))?;
+        let segment = &toolpath.segments[first_segment];
+        // The resume block's own words, an M3 or F say, are in effect for its move too.
+        let mut modal = modal_state_before(program, axes, &toolpath.start_position, segment.block_index);
+        apply_modal_words(&mut modal, &program.lines[segment.block_index])?;
+
+        let mut approach: Vec<(EApproachMove, Vec<f32>)> = Vec::new();
+        let mut last = position.to_vec();
+        let mut add = |kind: EApproachMove, target: Vec<f32>| {
+            if !reached(&target, &last) {
+                approach.push((kind, target.clone()));
+                last = target;
+            }
+        };
+        match axes.get(2) {
+            Some(z_axis) if z_axis.kind == EAxisKind::ELinear && position.len() > 2 => {
+                let job_top = toolpath.segments.iter().flat_map(|s| [s.start[2], s.end[2]]).fold(f32::MIN, f32::max);
+                let safe_z = position[2].max(job_top).min(z_axis.max);
+                let mut retract = position.to_vec();
+                retract[2] = safe_z;
+                add(EApproachMove::ERetract, retract);
+                let mut traverse = segment.start.clone();
+                traverse[2] = safe_z;
+                add(EApproachMove::ETraverse, traverse);
+                add(EApproachMove::EPlunge, segment.start.clone());
+            },
+            _ => add(EApproachMove::ETraverse, segment.start.clone()),
+        }
+        Ok(ResumePlan{ line_number: segment.line_number, first_segment, modal, approach })
+    }
+
+    /// Whether `other` starts at the same segment with the same moves, within the
+    /// arrival tolerance.
+    pub fn same_approach(&self, other: &ResumePlan) -> bool {
+        self.first_segment == other.first_segment && self.approach.len() == other.approach.len()
+            && self.approach.iter().zip(other.approach.iter())
+                .all(|((kind, target), (other_kind, other_target))| kind == other_kind && reached(target, other_target))
+    }
+
+    /// Only the moves are resumed, the spindle has to be started by hand if the job
+    /// has it on at the resume line.
+    pub fn needs_spindle(&self) -> bool {
+        self.modal.spindle != ESpindle::EOff
+    }
+}
+
+/// Whether every axis of `position` is within `ARRIVAL_TOLERANCE` of `target`.
+pub fn reached(target: &[f32], position: &[f32]) -> bool {
+    target.iter().zip(position.iter()).all(|(t, p)| (t - p).abs() <= JobRunner::ARRIVAL_TOLERANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> (GCodeProgram, Toolpath) {
+        let program = GCodeProgram::parse("test", "G20 G91\nG0 X1 Z0.5\nM3 S9000\nG1 Z-0.6 F10\nG1 X1\nG90 G1 Y1").unwrap();
+        let toolpath = Toolpath::build(&program, &AxisInfo::default_axes(), &[0f32, 0f32, 20f32]).unwrap();
+        (program, toolpath)
+    }
+
+    #[test]
+    fn resume_rebuilds_the_modal_state() {
+        let (program, toolpath) = program();
+        let plan = ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 5, &[0f32, 0f32, 0f32]).unwrap();
+        assert_eq!(plan.line_number, 5);
+        assert_eq!(plan.first_segment, 2);
+        assert_eq!(plan.modal.describe(), "G1 G20 G91 G54 F10 M3 S9000 T0");
+        // Not yet applied: G90 comes with line 6.
+        assert!(!modal_state_before(&program, &AxisInfo::default_axes(), &toolpath.start_position, 4).absolute);
+        assert!(ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 7, &[0f32, 0f32, 0f32]).is_err());
+    }
+
+    #[test]
+    fn resume_takes_the_modal_words_of_its_own_line() {
+        let program = GCodeProgram::parse("test", "G0 X1\nM3 S9000 G1 X10 F300\nM5 G0 X0").unwrap();
+        let toolpath = Toolpath::build(&program, &AxisInfo::default_axes(), &[0f32, 0f32, 0f32]).unwrap();
+        let plan = ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 2, &[1f32, 0f32, 0f32]).unwrap();
+        assert_eq!(plan.line_number, 2);
+        assert!(plan.needs_spindle());
+        assert_eq!(plan.modal.describe(), "G1 G21 G90 G54 F300 M3 S9000 T0");
+        let plan = ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 3, &[10f32, 0f32, 0f32]).unwrap();
+        assert!(!plan.needs_spindle());
+    }
+
+    #[test]
+    fn resume_retracts_traverses_and_plunges() {
+        let (program, toolpath) = program();
+        let start = toolpath.segments[2].start.clone();
+        // Z0.5 inch up from 20 mm is the highest the job goes.
+        let safe_z = toolpath.segments[0].end[2];
+        let plan = ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 5, &[100f32, 50f32, 10f32]).unwrap();
+        let kinds: Vec<EApproachMove> = plan.approach.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, vec![EApproachMove::ERetract, EApproachMove::ETraverse, EApproachMove::EPlunge]);
+        assert_eq!(plan.approach[0].1, vec![100f32, 50f32, safe_z]);
+        assert_eq!(plan.approach[1].1, vec![start[0], start[1], safe_z]);
+        assert_eq!(plan.approach[2].1, start);
+        assert!(plan.needs_spindle());
+        let moved = ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 5, &[100f32, 60f32, 10f32]).unwrap();
+        assert!(!moved.same_approach(&plan));
+        let still = ResumePlan::new(&program, &toolpath, &AxisInfo::default_axes(), 5, &[100f32, 50.0001f32, 10f32]).unwrap();
+        assert!(still.same_approach(&plan));
+
+        let mut job = JobRunner::new();
+        job.resume_from(&plan);
+        let mut position = vec![100f32, 50f32, 10f32];
+        let mut targets = Vec::new();
+        while let Some(target) = job.update(&toolpath, &position) {
+            targets.push(target.clone());
+            position = target;
+        }
+        assert_eq!(targets.len(), 3 + toolpath.segments.len() - 2);
+        assert_eq!(job.state, EJobState::EFinished);
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Instant;
+
+use crate::cnc_job_stats::JobStatistics;
+use crate::cnc_msg::AxisInfo;
+use crate::cnc_toolpath::Toolpath;
+
+/// A segment end outside the axis limits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitViolation {
+    pub line_number : usize,
+    pub axis        : usize,
+    pub value       : f32,
+}
+
+/// What running a job through the model found, before anything is animated.
+#[derive(Clone, Debug)]
+pub struct SimulationReport {
+    /// Duration, extents and distances as estimated when the program was loaded.
+    pub stats           : JobStatistics,
+    pub violations      : Vec<LimitViolation>,
+}
+
+impl SimulationReport {
+    pub fn analyze(toolpath: &Toolpath, axes: &[AxisInfo], stats: &JobStatistics) -> SimulationReport {
+        let mut violations = Vec::new();
+        for segment in toolpath.segments.iter() {
+            for (axis, (value, info)) in segment.end.iter().zip(axes.iter()).enumerate() {
+                if *value < info.min || *value > info.max {
+                    violations.push(LimitViolation{ line_number: segment.line_number, axis, value: *value });
+                }
+            }
+        }
+        SimulationReport{ stats: stats.clone(), violations }
+    }
+}
+
+/// Formats seconds as `m:ss` or `h:mm:ss`.
+pub fn format_duration(seconds: f32) -> String {
+    let total = seconds.max(0f32).round() as u64;
+    if total >= 3600 {
+        format!("{}:{:02}:{:02}", total / 3600, total / 60 % 60, total % 60)
+    } else {
+        format!("{}:{:02}", total / 60, total % 60)
+    }
+}
+
+/// A loaded job played back against the model instead of the controller.
+pub struct JobSimulation {
+    pub report      : SimulationReport,
+    /// Simulated seconds since the start, up to the estimated duration.
+    pub elapsed     : f32,
+    /// Simulated seconds per real second, `None` to finish at once.
+    pub speed       : Option<f32>,
+    last_update     : Instant,
+}
+
+impl JobSimulation {
+    pub fn new(toolpath: &Toolpath, axes: &[AxisInfo], stats: &JobStatistics, speed: Option<f32>) -> Self {
+        let mut simulation = JobSimulation{
+            report      : SimulationReport::analyze(toolpath, axes, stats),
+            elapsed     : 0f32,
+            speed,
+            last_update : Instant::now(),
+        };
+        simulation.update();
+        simulation
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.report.stats.estimated_duration
+    }
+
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let step = match self.speed {
+            Some(speed) => now.duration_since(self.last_update).as_secs_f32() * speed,
+            None => self.duration(),
+        };
+        self.elapsed = (self.elapsed + step).min(self.duration());
+        self.last_update = now;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration()
+    }
+
+    /// Number of segments the model has fully executed.
+    pub fn completed_segments(&self) -> usize {
+        self.report.stats.segments_done_by(self.elapsed)
+    }
+
+    /// Where the model is now, `None` for a job without moves.
+    pub fn position(&self, toolpath: &Toolpath) -> Option<Vec<f32>> {
+        let index = self.completed_segments();
+        let segment = match toolpath.segments.get(index) {
+            Some(segment) => segment,
+            None => return toolpath.segments.last().map(|segment| segment.end.clone()),
+        };
+        let start_time = self.report.stats.estimated_until(index);
+        let duration = self.report.stats.estimated_until(index + 1) - start_time;
+        let t = if duration > 0f32 { (self.elapsed - start_time) / duration } else { 1f32 };
+        Some(segment.start.iter().zip(segment.end.iter()).map(|(a, b)| a + (b - a) * t).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc_gcode::GCodeProgram;
+    use crate::cnc_job_stats::MotionLimits;
+
+    fn toolpath(text: &str) -> Toolpath {
+        let program = GCodeProgram::parse("test", text).unwrap();
+        Toolpath::build(&program, &AxisInfo::default_axes(), &[0f32, 0f32, 0f32]).unwrap()
+    }
+
+    #[test]
+    fn report_splits_rapids_and_feeds() {
+        let limits = MotionLimits{ rapid_feed: 3000f32, acceleration: 0f32 };
+        let toolpath = toolpath("G0 X30 Y40\nG1 Z-1 F600\nG1 X60 Y80 F1200\nG0 Z5");
+        let report = SimulationReport::analyze(&toolpath, &AxisInfo::default_axes(), &JobStatistics::compute(&toolpath, &limits));
+        assert!((report.stats.rapid_length - 56f32).abs() < 1e-3);
+        assert!((report.stats.cut_length - 51f32).abs() < 1e-3);
+        // 56 mm at 3000 mm/min, 1 mm at 600 mm/min and 50 mm at 1200 mm/min.
+        assert!((report.stats.estimated_duration - (1.12f32 + 0.1f32 + 2.5f32)).abs() < 1e-3);
+        assert_eq!(report.stats.min, vec![0f32, 0f32, -1f32]);
+        assert_eq!(report.stats.max, vec![60f32, 80f32, 5f32]);
+        assert_eq!(report.violations, vec![LimitViolation{ line_number: 2, axis: 2, value: -1f32 },
+            LimitViolation{ line_number: 3, axis: 2, value: -1f32 }]);
+    }
+
+    #[test]
+    fn playback_interpolates_the_current_segment() {
+        let toolpath = toolpath("G1 X10 F600\nG1 X20");
+        let stats = JobStatistics::compute(&toolpath, &MotionLimits{ rapid_feed: 3000f32, acceleration: 0f32 });
+        let mut simulation = JobSimulation::new(&toolpath, &AxisInfo::default_axes(), &stats, Some(0f32));
+        simulation.elapsed = 1.5f32;
+        assert_eq!(simulation.completed_segments(), 1);
+        assert_eq!(simulation.position(&toolpath), Some(vec![15f32, 0f32, 0f32]));
+
+        let instant = JobSimulation::new(&toolpath, &AxisInfo::default_axes(), &stats, None);
+        assert!(instant.is_finished());
+        assert_eq!(instant.completed_segments(), 2);
+        assert_eq!(instant.position(&toolpath), Some(vec![20f32, 0f32, 0f32]));
+    }
+
+    #[test]
+    fn durations_read_as_clock_time() {
+        assert_eq!(format_duration(59.6f32), "1:00");
+        assert_eq!(format_duration(3725f32), "1:02:05");
+    }
+}
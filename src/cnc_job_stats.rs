@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cnc_toolpath::{EMoveKind, Toolpath, ToolpathSegment};
+
+/// Machine speeds the run time estimate is based on, stored in the machine profile.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct MotionLimits {
+    /// Speed of rapids and of feed moves without a feed rate, in mm/min.
+    pub rapid_feed      : f32,
+    /// In mm/s², the same for every axis.
+    pub acceleration    : f32,
+}
+
+impl Default for MotionLimits {
+    fn default() -> Self {
+        MotionLimits::new()
+    }
+}
+
+impl MotionLimits {
+    /// Rapids match the simulator's top speed.
+    pub fn new() -> Self {
+        MotionLimits{
+            rapid_feed      : 3000f32,
+            acceleration    : 500f32,
+        }
+    }
+
+    /// Seconds `segment` takes starting and ending at rest, which is how the job runner
+    /// streams it: the next target is only sent once the previous one is reached.
+    pub fn segment_duration(&self, segment: &ToolpathSegment) -> f32 {
+        let feed = match segment.kind {
+            EMoveKind::EFeed if segment.feed > 0f32 => segment.feed.min(self.rapid_feed),
+            _ => self.rapid_feed,
+        };
+        let speed = feed / 60f32;
+        let length = segment.length();
+        if length <= 0f32 || speed <= 0f32 {
+            return 0f32;
+        }
+        if self.acceleration <= 0f32 {
+            return length / speed;
+        }
+        if length >= speed * speed / self.acceleration {
+            // Trapezoid: cruising at `speed`, plus half the ramp time on each end.
+            length / speed + speed / self.acceleration
+        } else {
+            // Triangle: the segment ends before reaching `speed`.
+            2f32 * (length / self.acceleration).sqrt()
+        }
+    }
+}
+
+/// Figures for a loaded program, for quoting and for tracking a run against the estimate.
+#[derive(Clone, Debug)]
+pub struct JobStatistics {
+    /// Per axis extents of all moves, empty for a program without moves.
+    pub min                 : Vec<f32>,
+    pub max                 : Vec<f32>,
+    pub cut_length          : f32,
+    pub rapid_length        : f32,
+    /// Blocks with motion; arcs count once although they are split into segments.
+    pub moves               : usize,
+    pub tool_changes        : usize,
+    /// Seconds for the whole job.
+    pub estimated_duration  : f32,
+    /// Estimated seconds from the start to the end of each segment.
+    segment_ends            : Vec<f32>,
+}
+
+impl JobStatistics {
+    pub fn compute(toolpath: &Toolpath, limits: &MotionLimits) -> JobStatistics {
+        let mut stats = JobStatistics{
+            min                 : Vec::new(),
+            max                 : Vec::new(),
+            cut_length          : 0f32,
+            rapid_length        : 0f32,
+            moves               : 0,
+            tool_changes        : toolpath.tool_changes,
+            estimated_duration  : 0f32,
+            segment_ends        : Vec::with_capacity(toolpath.segments.len()),
+        };
+        if let Some(first) = toolpath.segments.first() {
+            stats.min = first.start.clone();
+            stats.max = first.start.clone();
+        }
+        let mut last_block = None;
+        for segment in toolpath.segments.iter() {
+            if last_block != Some(segment.block_index) {
+                stats.moves += 1;
+                last_block = Some(segment.block_index);
+            }
+            match segment.kind {
+                EMoveKind::ERapid => stats.rapid_length += segment.length(),
+                EMoveKind::EFeed => stats.cut_length += segment.length(),
+            }
+            for (axis, value) in segment.end.iter().enumerate() {
+                stats.min[axis] = stats.min[axis].min(*value);
+                stats.max[axis] = stats.max[axis].max(*value);
+            }
+            stats.estimated_duration += limits.segment_duration(segment);
+            stats.segment_ends.push(stats.estimated_duration);
+        }
+        stats
+    }
+
+    /// Estimated seconds to complete the first `segments` segments.
+    pub fn estimated_until(&self, segments: usize) -> f32 {
+        match segments {
+            0 => 0f32,
+            _ => self.segment_ends.get(segments - 1).copied().unwrap_or(self.estimated_duration),
+        }
+    }
+
+    /// Segments the estimate has completed `seconds` into the job.
+    pub fn segments_done_by(&self, seconds: f32) -> usize {
+        if seconds >= self.estimated_duration {
+            return self.segment_ends.len();
+        }
+        self.segment_ends.partition_point(|end| *end <= seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc_gcode::GCodeProgram;
+    use crate::cnc_msg::AxisInfo;
+
+    fn toolpath(text: &str) -> Toolpath {
+        let program = GCodeProgram::parse("test", text).unwrap();
+        Toolpath::build(&program, &AxisInfo::default_axes(), &[0f32, 0f32, 0f32]).unwrap()
+    }
+
+    #[test]
+    fn short_moves_never_reach_the_feed() {
+        let limits = MotionLimits{ rapid_feed: 6000f32, acceleration: 100f32 };
+        // 100 mm/s needs 100 mm to ramp up and down, 400 mm leaves 300 mm at full speed.
+        let stats = JobStatistics::compute(&toolpath("G0 X400"), &limits);
+        assert!((stats.estimated_duration - 5f32).abs() < 1e-3);
+        // 25 mm is spent accelerating and decelerating: 2 * sqrt(25 / 100).
+        let stats = JobStatistics::compute(&toolpath("G0 X25"), &limits);
+        assert!((stats.estimated_duration - 1f32).abs() < 1e-3);
+    }
+
+    #[test]
+    fn statistics_count_blocks_lengths_and_tools() {
+        let stats = JobStatistics::compute(&toolpath("T1 M6\nG0 X10 Y10\nG1 Z-2 F100\nG2 X30 Y10 I10 J0\nT2 M6\nG0 Z5"),
+            &MotionLimits::new());
+        assert_eq!(stats.moves, 4);
+        assert_eq!(stats.tool_changes, 2);
+        assert!((stats.rapid_length - (200f32.sqrt() + 7f32)).abs() < 1e-3);
+        assert!((stats.cut_length - (2f32 + 10f32 * std::f32::consts::PI)).abs() < 0.05f32);
+        assert_eq!(stats.min[2], -2f32);
+        assert!((stats.max[1] - 20f32).abs() < 0.01f32);
+        assert_eq!(stats.estimated_until(0), 0f32);
+        assert_eq!(stats.estimated_until(usize::MAX), stats.estimated_duration);
+        assert_eq!(stats.segments_done_by(0f32), 0);
+        assert_eq!(stats.segments_done_by(stats.estimated_until(2)), 2);
+        assert_eq!(stats.segments_done_by(f32::MAX), stats.segment_ends.len());
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+/// Message counters and timing of the controller connection, for the status bar.
+pub struct LinkStats {
+    pub rx_total        : u64,
+    pub tx_total        : u64,
+    /// Messages per second over the last full `RATE_WINDOW`.
+    pub rx_rate         : f32,
+    pub tx_rate         : f32,
+    pub last_rx         : Option<Instant>,
+    /// Latest ping round trip, `None` until a pong arrives.
+    pub round_trip      : Option<Duration>,
+    /// Cycle time from the latest controller status.
+    pub cycle_time      : Option<i32>,
+    rx_window           : u64,
+    tx_window           : u64,
+    window_start        : Instant,
+    ping_sequence       : u32,
+    ping_pending        : Option<(u32, Instant)>,
+    last_ping           : Option<Instant>,
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        LinkStats::new()
+    }
+}
+
+impl LinkStats {
+    pub const RATE_WINDOW: Duration = Duration::from_secs(1);
+    pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+    /// A ping without answer is given up after this, so firmware without ping support
+    /// just shows no round trip time.
+    pub const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new() -> Self {
+        LinkStats{
+            rx_total        : 0,
+            tx_total        : 0,
+            rx_rate         : 0f32,
+            tx_rate         : 0f32,
+            last_rx         : None,
+            round_trip      : None,
+            cycle_time      : None,
+            rx_window       : 0,
+            tx_window       : 0,
+            window_start    : Instant::now(),
+            ping_sequence   : 0,
+            ping_pending    : None,
+            last_ping       : None,
+        }
+    }
+
+    pub fn on_rx(&mut self) {
+        self.rx_total += 1;
+        self.rx_window += 1;
+        self.last_rx = Some(Instant::now());
+    }
+
+    pub fn on_tx(&mut self) {
+        self.tx_total += 1;
+        self.tx_window += 1;
+    }
+
+    pub fn last_message_age(&self) -> Option<Duration> {
+        self.last_rx.map(|last| last.elapsed())
+    }
+
+    /// Rolls the rate window over once it is full.
+    pub fn update(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= LinkStats::RATE_WINDOW {
+            self.rx_rate = self.rx_window as f32 / elapsed.as_secs_f32();
+            self.tx_rate = self.tx_window as f32 / elapsed.as_secs_f32();
+            self.rx_window = 0;
+            self.tx_window = 0;
+            self.window_start = Instant::now();
+        }
+    }
+
+    /// Sequence number of the next ping if one is due.
+    pub fn next_ping(&mut self) -> Option<u32> {
+        if let Some((_, sent)) = self.ping_pending {
+            if sent.elapsed() < LinkStats::PING_TIMEOUT {
+                return None;
+            }
+            self.round_trip = None;
+        }
+        if self.last_ping.is_some_and(|last| last.elapsed() < LinkStats::PING_INTERVAL) {
+            return None;
+        }
+        self.ping_sequence = self.ping_sequence.wrapping_add(1);
+        let now = Instant::now();
+        self.ping_pending = Some((self.ping_sequence, now));
+        self.last_ping = Some(now);
+        Some(self.ping_sequence)
+    }
+
+    pub fn on_pong(&mut self, sequence: u32) {
+        if let Some((pending, sent)) = self.ping_pending {
+            if pending == sequence {
+                self.round_trip = Some(sent.elapsed());
+                self.ping_pending = None;
+            }
+        }
+    }
+}
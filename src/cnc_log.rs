@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOG_PATH: &str = "./data/cnc.log";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ELogLevel {
+    EError,
+    EWarn,
+    EInfo,
+    EDebug,
+    ETrace,
+}
+
+impl ELogLevel {
+    pub const ALL: [ELogLevel; 5] = [ELogLevel::EError, ELogLevel::EWarn, ELogLevel::EInfo, ELogLevel::EDebug, ELogLevel::ETrace];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ELogLevel::EError => "ERROR",
+            ELogLevel::EWarn => "WARN",
+            ELogLevel::EInfo => "INFO",
+            ELogLevel::EDebug => "DEBUG",
+            ELogLevel::ETrace => "TRACE",
+        }
+    }
+
+    pub fn from_index(index: usize) -> ELogLevel {
+        ELogLevel::ALL[index.min(ELogLevel::ALL.len() - 1)]
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LogEvent {
+    pub timestamp   : SystemTime,
+    pub level       : ELogLevel,
+    /// Module path without the crate name, e.g. `cnc_connection`.
+    pub module      : String,
+    pub message     : String,
+}
+
+impl LogEvent {
+    pub fn format(&self) -> String {
+        format!("{} {:5} [{}] {}", format_timestamp(self.timestamp), self.level.name(), self.module, self.message)
+    }
+}
+
+struct Logger {
+    events          : VecDeque<LogEvent>,
+    /// Events dropped from the front of `events`, so readers can tell what they have seen.
+    dropped         : usize,
+    levels          : BTreeMap<String, ELogLevel>,
+    default_level   : ELogLevel,
+    file            : Option<BufWriter<File>>,
+}
+
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+/// Number of events kept for the console.
+pub const CAPACITY: usize = 2000;
+
+/// Opens the log file. Events logged before this only go to the console.
+pub fn init() {
+    let file = Path::new(LOG_PATH).parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| OpenOptions::new().create(true).append(true).open(LOG_PATH));
+    let error = with_logger(|logger| match file {
+        Ok(file) => {
+            logger.file = Some(BufWriter::new(file));
+            None
+        },
+        Err(e) => Some(format!("Can't open {}: {}", LOG_PATH, e)),
+    });
+    if let Some(e) = error {
+        log(ELogLevel::EError, module_path!(), e);
+    }
+}
+
+fn with_logger<R>(f: impl FnOnce(&mut Logger) -> R) -> R {
+    let mut guard = LOGGER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let logger = guard.get_or_insert_with(|| Logger{
+        events          : VecDeque::with_capacity(CAPACITY),
+        dropped         : 0,
+        levels          : BTreeMap::new(),
+        default_level   : ELogLevel::EInfo,
+        file            : None,
+    });
+    f(logger)
+}
+
+fn short_module(module_path: &str) -> &str {
+    module_path.split_once("::").map_or(module_path, |(_, module)| module)
+}
+
+/// Whether `module_path` logs at `level`. Also registers the module for the verbosity controls.
+pub fn enabled(level: ELogLevel, module_path: &str) -> bool {
+    with_logger(|logger| {
+        let default_level = logger.default_level;
+        let module_level = *logger.levels.entry(String::from(short_module(module_path))).or_insert(default_level);
+        level <= module_level
+    })
+}
+
+pub fn log(level: ELogLevel, module_path: &str, message: String) {
+    let event = LogEvent{
+        timestamp   : SystemTime::now(),
+        level,
+        module      : String::from(short_module(module_path)),
+        message,
+    };
+    with_logger(|logger| {
+        if let Some(ref mut file) = logger.file {
+            // Errors are flushed right away so they survive a crash.
+            let written = writeln!(file, "{}", event.format())
+                .and_then(|()| if level <= ELogLevel::EWarn { file.flush() } else { Ok(()) });
+            if written.is_err() {
+                logger.file = None;
+            }
+        }
+        if logger.events.len() == CAPACITY {
+            logger.events.pop_front();
+            logger.dropped += 1;
+        }
+        logger.events.push_back(event);
+    });
+}
+
+pub fn flush() {
+    with_logger(|logger| {
+        if let Some(ref mut file) = logger.file {
+            let _ = file.flush();
+        }
+    });
+}
+
+/// Events with a sequence number above `after`, and the sequence number of the last event.
+/// Sequence numbers count all events ever logged, starting at 1.
+pub fn events_since(after: usize) -> (Vec<LogEvent>, usize) {
+    with_logger(|logger| {
+        let last = logger.dropped + logger.events.len();
+        let skip = after.saturating_sub(logger.dropped).min(logger.events.len());
+        (logger.events.iter().skip(skip).cloned().collect(), last)
+    })
+}
+
+/// Modules seen so far with their verbosity.
+pub fn module_levels() -> Vec<(String, ELogLevel)> {
+    with_logger(|logger| logger.levels.iter().map(|(module, level)| (module.clone(), *level)).collect())
+}
+
+pub fn set_module_level(module: &str, level: ELogLevel) {
+    with_logger(|logger| {
+        logger.levels.insert(String::from(module), level);
+    });
+}
+
+/// `YYYY-MM-DD HH:MM:SS.mmm` in UTC.
+pub fn format_timestamp(timestamp: SystemTime) -> String {
+    let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time_of_day = seconds % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}", year, month, day,
+        time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60, since_epoch.subsec_millis())
+}
+
+/// Gregorian date of a day count since 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::cnc_log::enabled($level, module_path!()) {
+            $crate::cnc_log::log($level, module_path!(), format!($($arg)+));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => { $crate::log_at!($crate::cnc_log::ELogLevel::EError, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => { $crate::log_at!($crate::cnc_log::ELogLevel::EWarn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => { $crate::log_at!($crate::cnc_log::ELogLevel::EInfo, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)+) => { $crate::log_at!($crate::cnc_log::ELogLevel::EDebug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)+) => { $crate::log_at!($crate::cnc_log::ELogLevel::ETrace, $($arg)+) };
+}
@@ -0,0 +1,167 @@
+/// What the machine is doing, as tracked by `CncCtrl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EMachineState {
+    EDisconnected,
+    /// Connection established, handshake not sent yet.
+    EConnecting,
+    /// Waiting for the controller to report its axes or parameters.
+    EHandshaking,
+    EIdle,
+    /// Moving to a target set by the user.
+    EJogging,
+    ERunning,
+    /// Job paused, the machine stops at the end of the current segment.
+    EHolding,
+    /// Moving to the home position.
+    EHoming,
+    /// Stopped by a fault, needs a reset.
+    EAlarm,
+    /// Stopped by the operator, needs a reset.
+    EEStop,
+}
+
+impl EMachineState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EMachineState::EDisconnected => "DISCONNECTED",
+            EMachineState::EConnecting => "CONNECTING",
+            EMachineState::EHandshaking => "HANDSHAKING",
+            EMachineState::EIdle => "IDLE",
+            EMachineState::EJogging => "JOGGING",
+            EMachineState::ERunning => "RUNNING",
+            EMachineState::EHolding => "HOLDING",
+            EMachineState::EHoming => "HOMING",
+            EMachineState::EAlarm => "ALARM",
+            EMachineState::EEStop => "E-STOP",
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        *self != EMachineState::EDisconnected
+    }
+
+    /// States in which a job is loaded into the runner.
+    pub fn has_active_job(&self) -> bool {
+        matches!(self, EMachineState::ERunning | EMachineState::EHolding)
+    }
+}
+
+/// User commands gated by the machine state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ECommand {
+    EConnect,
+    EJog,
+    EHome,
+    ELoadProgram,
+    /// Playing the loaded job back against the model, without moving.
+    ESimulateJob,
+    EStartJob,
+    ERunMacro,
+    EPauseJob,
+    EResumeJob,
+    EStopJob,
+    /// Sending PID params or axis config.
+    EWriteConfig,
+    /// Changing the axis layout or switching profiles. Only while disconnected, the
+    /// connection decodes replies for the axis count it was started with.
+    ESetAxes,
+    EEStop,
+    EReset,
+}
+
+impl ECommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ECommand::EConnect => "connect",
+            ECommand::EJog => "move",
+            ECommand::EHome => "home",
+            ECommand::ELoadProgram => "load a program",
+            ECommand::ESimulateJob => "simulate the job",
+            ECommand::EStartJob => "start a job",
+            ECommand::ERunMacro => "run a macro",
+            ECommand::EPauseJob => "pause the job",
+            ECommand::EResumeJob => "resume the job",
+            ECommand::EStopJob => "stop the job",
+            ECommand::EWriteConfig => "send parameters",
+            ECommand::ESetAxes => "change the axes",
+            ECommand::EEStop => "emergency stop",
+            ECommand::EReset => "reset",
+        }
+    }
+
+    /// Commands that start motion, refused while a macro drives the machine.
+    pub fn moves_machine(&self) -> bool {
+        matches!(self, ECommand::EJog | ECommand::EHome | ECommand::EStartJob | ECommand::ERunMacro | ECommand::EResumeJob)
+    }
+
+    fn allowed_in(&self, state: EMachineState) -> bool {
+        use EMachineState::*;
+        match self {
+            ECommand::EConnect | ECommand::ELoadProgram | ECommand::ESimulateJob => !matches!(state, ERunning | EHolding | EHoming),
+            ECommand::EJog => matches!(state, EIdle | EJogging),
+            ECommand::EHome | ECommand::EStartJob | ECommand::ERunMacro => state == EIdle,
+            ECommand::EPauseJob => state == ERunning,
+            ECommand::EResumeJob => state == EHolding,
+            ECommand::EStopJob => matches!(state, ERunning | EHolding),
+            ECommand::EWriteConfig => matches!(state, EIdle | EJogging | EHolding | EAlarm | EEStop),
+            ECommand::ESetAxes => state == EDisconnected,
+            ECommand::EEStop => state.is_connected(),
+            ECommand::EReset => matches!(state, EAlarm | EEStop),
+        }
+    }
+}
+
+/// Accepts `command` in `state` or explains why not.
+pub fn check_command(state: EMachineState, command: ECommand) -> Result<(), String> {
+    if command.allowed_in(state) {
+        Ok(())
+    } else {
+        Err(format!("Can't {} while {}", command.name(), state.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use EMachineState::*;
+    use ECommand::*;
+
+    const STATES: [EMachineState; 10] = [EDisconnected, EConnecting, EHandshaking, EIdle, EJogging, ERunning, EHolding, EHoming, EAlarm, EMachineState::EEStop];
+    const COMMANDS: [ECommand; 14] = [EConnect, EJog, EHome, ELoadProgram, ESimulateJob, EStartJob, ERunMacro, EPauseJob, EResumeJob,
+        EStopJob, EWriteConfig, ESetAxes, ECommand::EEStop, EReset];
+
+    /// Commands accepted in each state, everything else is refused.
+    fn allowed(state: EMachineState) -> Vec<ECommand> {
+        match state {
+            EDisconnected => vec![EConnect, ELoadProgram, ESimulateJob, ESetAxes],
+            EConnecting | EHandshaking => vec![EConnect, ELoadProgram, ESimulateJob, ECommand::EEStop],
+            EIdle => vec![EConnect, EJog, EHome, ELoadProgram, ESimulateJob, EStartJob, ERunMacro, EWriteConfig, ECommand::EEStop],
+            EJogging => vec![EConnect, EJog, ELoadProgram, ESimulateJob, EWriteConfig, ECommand::EEStop],
+            ERunning => vec![EPauseJob, EStopJob, ECommand::EEStop],
+            EHolding => vec![EResumeJob, EStopJob, EWriteConfig, ECommand::EEStop],
+            EHoming => vec![ECommand::EEStop],
+            EAlarm | EMachineState::EEStop => vec![EConnect, ELoadProgram, ESimulateJob, EWriteConfig, ECommand::EEStop, EReset],
+        }
+    }
+
+    #[test]
+    fn commands_follow_the_state_matrix() {
+        for state in STATES {
+            let allowed = allowed(state);
+            for command in COMMANDS {
+                assert_eq!(check_command(state, command).is_ok(), allowed.contains(&command), "{:?} in {:?}", command, state);
+            }
+        }
+    }
+
+    #[test]
+    fn refusals_name_the_command_and_state() {
+        assert_eq!(check_command(ERunning, EJog).err().unwrap(), "Can't move while RUNNING");
+    }
+
+    #[test]
+    fn only_motion_is_held_back_by_macros() {
+        let moving: Vec<ECommand> = COMMANDS.iter().copied().filter(|command| command.moves_machine()).collect();
+        assert_eq!(moving, vec![EJog, EHome, EStartJob, ERunMacro, EResumeJob]);
+    }
+}
@@ -0,0 +1,854 @@
+//! Macros: short scripts for repeated sequences like going to the tool change position.
+//!
+//! One statement per line, `#` starts a comment, keywords are case insensitive:
+//!
+//! ```text
+//! let safe_z = 40
+//! move Z=safe_z           # absolute move of the named axes, the others keep their target
+//! jog X=-10 Y=5           # relative to the last target
+//! wait                    # until the machine has arrived
+//! sleep 0.5               # seconds
+//! home
+//! print probing at {pos.X}
+//! if pos.Z < safe_z and idle
+//!     move Z=safe_z
+//! else
+//!     jog Z=1
+//! end
+//! repeat 3
+//!     jog X=10
+//! end
+//! while pos.X < 100
+//!     jog X=10
+//!     wait
+//! end
+//! ```
+//!
+//! Expressions use numbers, variables, `pos.<axis>`, `target.<axis>`, `error.<axis>` (the
+//! following error), `idle`, `+ - * /`, comparisons and `and`, `or`, `not`. Comparisons
+//! are 1 when true and 0 when false.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cnc_ctrl::CncCtrl;
+use crate::cnc_machine_state::EMachineState;
+use crate::cnc_msg::{AxisInfo, CncCoordinates};
+
+/// Longest `sleep`, anything longer is more likely a mistake than a wait.
+const MAX_SLEEP: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A macro as stored in the machine profile.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MacroDef {
+    pub name    : String,
+    pub source  : String,
+}
+
+impl MacroDef {
+    /// Reads a macro from a file, named after the file without its extension.
+    pub fn import(path: &str) -> Result<MacroDef, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("Failed to read macro from {}: {}", path, e))?;
+        let name = Path::new(path).file_stem().and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("Can't name a macro after {}", path))?;
+        Ok(MacroDef{ name: String::from(name), source })
+    }
+}
+
+/// What a macro sees and drives: the real machine or the dry-run model.
+pub trait MacroMachine {
+    fn position(&self) -> Vec<f32>;
+    fn following_error(&self, axis: usize) -> f32;
+    /// Whether the last move is still under way.
+    fn is_moving(&self) -> bool;
+    /// Why the machine can't go on, if it can't.
+    fn fault(&self) -> Option<String>;
+    fn move_to(&mut self, target: &[f32]) -> Result<(), String>;
+    /// Starts homing, returning the position it ends at.
+    fn home(&mut self) -> Result<Vec<f32>, String>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EBinaryOp {
+    EAdd,
+    ESub,
+    EMul,
+    EDiv,
+    ELess,
+    ELessEqual,
+    EGreater,
+    EGreaterEqual,
+    EEqual,
+    ENotEqual,
+    EAnd,
+    EOr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EExpr {
+    ENumber(f32),
+    EVariable(String),
+    EPosition(usize),
+    ETarget(usize),
+    EFollowingError(usize),
+    EIdle,
+    ENegate(Box<EExpr>),
+    ENot(Box<EExpr>),
+    EBinary(EBinaryOp, Box<EExpr>, Box<EExpr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EPrintPart {
+    EText(String),
+    EValue(EExpr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EInstruction {
+    ELet(String, EExpr),
+    EMove{ axes: Vec<(usize, EExpr)>, relative: bool },
+    EHome,
+    EWait,
+    ESleep(EExpr),
+    EPrint(Vec<EPrintPart>),
+    /// Continues at the index if the condition is 0.
+    EJumpUnless(EExpr, usize),
+    EJump(usize),
+}
+
+#[derive(Clone, Debug)]
+struct Statement {
+    instruction : EInstruction,
+    /// Source line, counting from 1.
+    line        : usize,
+}
+
+/// A macro compiled for a machine's axes.
+#[derive(Clone, Debug)]
+pub struct Macro {
+    pub name    : String,
+    statements  : Vec<Statement>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EToken {
+    ENumber(f32),
+    /// Identifiers and keywords, with an optional `.axis` suffix.
+    EName(String),
+    EOperator(&'static str),
+}
+
+const OPERATORS: [&str; 13] = ["==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "(", ")", "="];
+const BUILTINS: [&str; 5] = ["idle", "and", "or", "not", "pos"];
+
+fn tokenize(text: &str) -> Result<Vec<EToken>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            tokens.push(EToken::ENumber(number.parse().map_err(|_| format!("Invalid number {}", number))?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(EToken::EName(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            match OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+                Some(operator) => {
+                    tokens.push(EToken::EOperator(operator));
+                    i += operator.len();
+                },
+                None => return Err(format!("Unexpected '{}'", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens of one line, lowest precedence first.
+struct Parser<'a> {
+    tokens  : Vec<EToken>,
+    next    : usize,
+    axes    : &'a [AxisInfo],
+    defined : &'a HashSet<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&EToken> {
+        self.tokens.get(self.next)
+    }
+
+    fn take(&mut self) -> Option<EToken> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn at_end(&self) -> bool {
+        self.next >= self.tokens.len()
+    }
+
+    fn expect_end(&self) -> Result<(), String> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(format!("Unexpected {:?} at the end", token)),
+        }
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(EToken::EName(n)) if n.eq_ignore_ascii_case(name))
+    }
+
+    fn axis(&self, name: &str) -> Result<usize, String> {
+        self.axes.iter().position(|axis| axis.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("The machine has no axis {}", name))
+    }
+
+    fn expression(&mut self) -> Result<EExpr, String> {
+        let mut left = self.and()?;
+        while self.is_name("or") {
+            self.take();
+            left = EExpr::EBinary(EBinaryOp::EOr, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<EExpr, String> {
+        let mut left = self.comparison()?;
+        while self.is_name("and") {
+            self.take();
+            left = EExpr::EBinary(EBinaryOp::EAnd, Box::new(left), Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<EExpr, String> {
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Some(EToken::EOperator("<")) => EBinaryOp::ELess,
+            Some(EToken::EOperator("<=")) => EBinaryOp::ELessEqual,
+            Some(EToken::EOperator(">")) => EBinaryOp::EGreater,
+            Some(EToken::EOperator(">=")) => EBinaryOp::EGreaterEqual,
+            Some(EToken::EOperator("==")) => EBinaryOp::EEqual,
+            Some(EToken::EOperator("!=")) => EBinaryOp::ENotEqual,
+            _ => return Ok(left),
+        };
+        self.take();
+        Ok(EExpr::EBinary(op, Box::new(left), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<EExpr, String> {
+        let mut left = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some(EToken::EOperator("+")) => EBinaryOp::EAdd,
+                Some(EToken::EOperator("-")) => EBinaryOp::ESub,
+                _ => return Ok(left),
+            };
+            self.take();
+            left = EExpr::EBinary(op, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<EExpr, String> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(EToken::EOperator("*")) => EBinaryOp::EMul,
+                Some(EToken::EOperator("/")) => EBinaryOp::EDiv,
+                _ => return Ok(left),
+            };
+            self.take();
+            left = EExpr::EBinary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<EExpr, String> {
+        if let Some(EToken::EOperator("-")) = self.peek() {
+            self.take();
+            return Ok(EExpr::ENegate(Box::new(self.unary()?)));
+        }
+        if self.is_name("not") {
+            self.take();
+            return Ok(EExpr::ENot(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<EExpr, String> {
+        match self.take() {
+            Some(EToken::ENumber(value)) => Ok(EExpr::ENumber(value)),
+            Some(EToken::EOperator("(")) => {
+                let inner = self.expression()?;
+                match self.take() {
+                    Some(EToken::EOperator(")")) => Ok(inner),
+                    _ => Err(String::from("Missing ')'")),
+                }
+            },
+            Some(EToken::EName(name)) => self.name(&name),
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err(String::from("Expression missing")),
+        }
+    }
+
+    fn name(&self, name: &str) -> Result<EExpr, String> {
+        if let Some((object, axis)) = name.split_once('.') {
+            let axis = self.axis(axis)?;
+            return match object.to_lowercase().as_str() {
+                "pos" => Ok(EExpr::EPosition(axis)),
+                "target" => Ok(EExpr::ETarget(axis)),
+                "error" => Ok(EExpr::EFollowingError(axis)),
+                _ => Err(format!("Unknown value {}", name)),
+            };
+        }
+        if name.eq_ignore_ascii_case("idle") {
+            return Ok(EExpr::EIdle);
+        }
+        if !self.defined.contains(name) {
+            return Err(format!("Unknown variable {}", name));
+        }
+        Ok(EExpr::EVariable(String::from(name)))
+    }
+
+    /// `AXIS=expression` pairs of `move` and `jog`.
+    fn axis_values(&mut self) -> Result<Vec<(usize, EExpr)>, String> {
+        let mut values = Vec::new();
+        while !self.at_end() {
+            let axis = match self.take() {
+                Some(EToken::EName(name)) => self.axis(&name)?,
+                _ => return Err(String::from("Expected AXIS=value")),
+            };
+            if self.take() != Some(EToken::EOperator("=")) {
+                return Err(String::from("Expected AXIS=value"));
+            }
+            values.push((axis, self.expression()?));
+        }
+        if values.is_empty() {
+            return Err(String::from("Expected AXIS=value"));
+        }
+        Ok(values)
+    }
+}
+
+/// Splits `print` text into literal parts and `{expression}` parts.
+fn print_parts(text: &str, axes: &[AxisInfo], defined: &HashSet<String>) -> Result<Vec<EPrintPart>, String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..].find('}').map(|close| open + close).ok_or_else(|| String::from("Missing '}'"))?;
+        if open > 0 {
+            parts.push(EPrintPart::EText(String::from(&rest[..open])));
+        }
+        let mut parser = Parser{ tokens: tokenize(&rest[open + 1..close])?, next: 0, axes, defined };
+        parts.push(EPrintPart::EValue(parser.expression()?));
+        parser.expect_end()?;
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(EPrintPart::EText(String::from(rest)));
+    }
+    Ok(parts)
+}
+
+/// Open `if`, `repeat` and `while` blocks while compiling, with the jumps to patch at `end`.
+enum EBlock {
+    EIf{ jump_unless: usize, jump_over_else: Option<usize>, line: usize },
+    ELoop{ start: usize, jump_unless: usize, counter: Option<String>, line: usize },
+}
+
+impl Macro {
+    /// Checks the macro against `axes` and turns it into jumps. Errors name the line.
+    pub fn compile(definition: &MacroDef, axes: &[AxisInfo]) -> Result<Macro, String> {
+        let mut statements: Vec<Statement> = Vec::new();
+        let mut blocks: Vec<EBlock> = Vec::new();
+        let mut defined: HashSet<String> = HashSet::new();
+
+        for (index, raw_line) in definition.source.lines().enumerate() {
+            let line = index + 1;
+            let text = raw_line.split('#').next().unwrap_or("").trim();
+            if text.is_empty() {
+                continue;
+            }
+            let (keyword, rest) = match text.split_once(char::is_whitespace) {
+                Some((keyword, rest)) => (keyword.to_lowercase(), rest.trim()),
+                None => (text.to_lowercase(), ""),
+            };
+            let at_line = |e: String| format!("line {}: {}", line, e);
+            let tokens = if keyword == "print" { Vec::new() } else { tokenize(rest).map_err(at_line)? };
+            let mut parser = Parser{ tokens, next: 0, axes, defined: &defined };
+            let push = |statements: &mut Vec<Statement>, instruction| statements.push(Statement{ instruction, line });
+
+            match keyword.as_str() {
+                "let" => {
+                    let name = match parser.take() {
+                        Some(EToken::EName(name)) if !name.contains('.') && !BUILTINS.contains(&name.to_lowercase().as_str()) => name,
+                        _ => return Err(at_line(String::from("Expected a variable name after let"))),
+                    };
+                    if parser.take() != Some(EToken::EOperator("=")) {
+                        return Err(at_line(String::from("Expected let NAME = value")));
+                    }
+                    let value = parser.expression().map_err(at_line)?;
+                    parser.expect_end().map_err(at_line)?;
+                    defined.insert(name.clone());
+                    push(&mut statements, EInstruction::ELet(name, value));
+                },
+                "move" | "jog" => {
+                    let axes = parser.axis_values().map_err(at_line)?;
+                    push(&mut statements, EInstruction::EMove{ axes, relative: keyword == "jog" });
+                },
+                "wait" | "home" | "else" | "end" if !rest.is_empty() => return Err(at_line(format!("{} takes no arguments", keyword))),
+                "wait" => push(&mut statements, EInstruction::EWait),
+                "home" => push(&mut statements, EInstruction::EHome),
+                "sleep" => {
+                    let seconds = parser.expression().map_err(at_line)?;
+                    parser.expect_end().map_err(at_line)?;
+                    push(&mut statements, EInstruction::ESleep(seconds));
+                },
+                "print" => push(&mut statements, EInstruction::EPrint(print_parts(rest, axes, &defined).map_err(at_line)?)),
+                "if" => {
+                    let condition = parser.expression().map_err(at_line)?;
+                    parser.expect_end().map_err(at_line)?;
+                    blocks.push(EBlock::EIf{ jump_unless: statements.len(), jump_over_else: None, line });
+                    push(&mut statements, EInstruction::EJumpUnless(condition, 0));
+                },
+                "else" => match blocks.last_mut() {
+                    Some(EBlock::EIf{ jump_unless, jump_over_else: jump_over_else @ None, .. }) => {
+                        *jump_over_else = Some(statements.len());
+                        let else_start = statements.len() + 1;
+                        patch(&mut statements, *jump_unless, else_start);
+                        push(&mut statements, EInstruction::EJump(0));
+                    },
+                    _ => return Err(at_line(String::from("else without if"))),
+                },
+                "repeat" | "while" => {
+                    let value = parser.expression().map_err(at_line)?;
+                    parser.expect_end().map_err(at_line)?;
+                    // The counter name can't clash with variables, those can't contain '#'.
+                    let counter = match keyword.as_str() {
+                        "repeat" => Some(format!("#repeat{}", line)),
+                        _ => None,
+                    };
+                    let condition = match counter {
+                        Some(ref counter) => {
+                            push(&mut statements, EInstruction::ELet(counter.clone(), value));
+                            EExpr::EBinary(EBinaryOp::EGreater, Box::new(EExpr::EVariable(counter.clone())), Box::new(EExpr::ENumber(0.5f32)))
+                        },
+                        None => value,
+                    };
+                    blocks.push(EBlock::ELoop{ start: statements.len(), jump_unless: statements.len(), counter, line });
+                    push(&mut statements, EInstruction::EJumpUnless(condition, 0));
+                },
+                "end" => match blocks.pop() {
+                    Some(EBlock::EIf{ jump_unless, jump_over_else, .. }) => {
+                        let end = statements.len();
+                        patch(&mut statements, jump_over_else.unwrap_or(jump_unless), end);
+                    },
+                    Some(EBlock::ELoop{ start, jump_unless, counter, .. }) => {
+                        if let Some(counter) = counter {
+                            let decrement = EExpr::EBinary(EBinaryOp::ESub, Box::new(EExpr::EVariable(counter.clone())), Box::new(EExpr::ENumber(1f32)));
+                            push(&mut statements, EInstruction::ELet(counter, decrement));
+                        }
+                        push(&mut statements, EInstruction::EJump(start));
+                        let end = statements.len();
+                        patch(&mut statements, jump_unless, end);
+                    },
+                    None => return Err(at_line(String::from("end without if, repeat or while"))),
+                },
+                _ => return Err(at_line(format!("Unknown statement {}", keyword))),
+            }
+        }
+        if let Some(block) = blocks.last() {
+            let line = match block {
+                EBlock::EIf{ line, .. } | EBlock::ELoop{ line, .. } => line,
+            };
+            return Err(format!("line {}: block isn't closed with end", line));
+        }
+        Ok(Macro{ name: definition.name.clone(), statements })
+    }
+}
+
+/// Points the jump at `index` to `target`.
+fn patch(statements: &mut [Statement], index: usize, target: usize) {
+    match statements[index].instruction {
+        EInstruction::EJumpUnless(_, ref mut to) | EInstruction::EJump(ref mut to) => *to = target,
+        _ => {},
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EMacroState {
+    ERunning,
+    EFinished,
+    EFailed(String),
+    EStopped,
+}
+
+enum EWaiting {
+    ENothing,
+    EArrival,
+    EUntil(Instant),
+}
+
+/// One execution of a macro, advanced by `update` until it finishes.
+pub struct MacroRun {
+    program     : Macro,
+    next        : usize,
+    variables   : HashMap<String, f32>,
+    /// Where the macro last sent the machine, starting at the position it started at.
+    target      : Vec<f32>,
+    waiting     : EWaiting,
+    /// Sleeps are only added up, not waited for.
+    dry_run     : bool,
+    /// Seconds slept.
+    pub slept   : f32,
+    pub output  : Vec<String>,
+    pub state   : EMacroState,
+}
+
+impl MacroRun {
+    /// Statements run per update, so a loop without waits can't freeze the caller.
+    pub const STEPS_PER_UPDATE: usize = 1000;
+
+    pub fn new(program: Macro, start_position: Vec<f32>, dry_run: bool) -> Self {
+        MacroRun{
+            program,
+            next        : 0,
+            variables   : HashMap::new(),
+            target      : start_position,
+            waiting     : EWaiting::ENothing,
+            dry_run,
+            slept       : 0f32,
+            output      : Vec::new(),
+            state       : EMacroState::ERunning,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.program.name
+    }
+
+    /// Source line of the statement running or up next.
+    pub fn line(&self) -> usize {
+        self.program.statements.get(self.next.saturating_sub(1)).map_or(0, |statement| statement.line)
+    }
+
+    pub fn stop(&mut self) {
+        if self.state == EMacroState::ERunning {
+            self.state = EMacroState::EStopped;
+        }
+    }
+
+    pub fn update(&mut self, machine: &mut dyn MacroMachine) {
+        for _ in 0..MacroRun::STEPS_PER_UPDATE {
+            if self.state != EMacroState::ERunning {
+                return;
+            }
+            if let Some(fault) = machine.fault() {
+                self.state = EMacroState::EFailed(format!("line {}: {}", self.line(), fault));
+                return;
+            }
+            match self.waiting {
+                EWaiting::EArrival if machine.is_moving() => return,
+                EWaiting::EUntil(until) if Instant::now() < until => return,
+                _ => self.waiting = EWaiting::ENothing,
+            }
+            let statement = match self.program.statements.get(self.next) {
+                Some(statement) => statement.clone(),
+                None => {
+                    self.state = EMacroState::EFinished;
+                    return;
+                },
+            };
+            self.next += 1;
+            if let Err(e) = self.execute(statement.instruction, machine) {
+                self.state = EMacroState::EFailed(format!("line {}: {}", statement.line, e));
+            }
+        }
+    }
+
+    fn execute(&mut self, instruction: EInstruction, machine: &mut dyn MacroMachine) -> Result<(), String> {
+        match instruction {
+            EInstruction::ELet(name, value) => {
+                let value = self.eval(&value, machine)?;
+                self.variables.insert(name, value);
+            },
+            EInstruction::EMove{ axes, relative } => {
+                let mut target = self.target.clone();
+                for (axis, value) in axes.iter() {
+                    let value = self.eval(value, machine)?;
+                    target[*axis] = if relative { target[*axis] + value } else { value };
+                }
+                machine.move_to(&target)?;
+                self.target = target;
+            },
+            EInstruction::EHome => self.target = machine.home()?,
+            EInstruction::EWait => self.waiting = EWaiting::EArrival,
+            EInstruction::ESleep(seconds) => {
+                let seconds = self.eval(&seconds, machine)?;
+                let duration = Duration::try_from_secs_f32(seconds).ok()
+                    .filter(|duration| *duration <= MAX_SLEEP)
+                    .ok_or_else(|| format!("Can't sleep {} seconds, at most {}", seconds, MAX_SLEEP.as_secs()))?;
+                self.slept += seconds;
+                if !self.dry_run {
+                    self.waiting = EWaiting::EUntil(Instant::now() + duration);
+                }
+            },
+            EInstruction::EPrint(parts) => {
+                let mut text = String::new();
+                for part in parts.iter() {
+                    match part {
+                        EPrintPart::EText(part) => text.push_str(part),
+                        EPrintPart::EValue(value) => text.push_str(&format!("{:.3}", self.eval(value, machine)?)),
+                    }
+                }
+                self.output.push(text);
+            },
+            EInstruction::EJumpUnless(condition, to) => {
+                if self.eval(&condition, machine)? == 0f32 {
+                    self.next = to;
+                }
+            },
+            EInstruction::EJump(to) => self.next = to,
+        }
+        Ok(())
+    }
+
+    fn eval(&self, expr: &EExpr, machine: &dyn MacroMachine) -> Result<f32, String> {
+        let truth = |value: bool| if value { 1f32 } else { 0f32 };
+        Ok(match expr {
+            EExpr::ENumber(value) => *value,
+            EExpr::EVariable(name) => *self.variables.get(name).ok_or_else(|| format!("{} is used before it is set", name))?,
+            EExpr::EPosition(axis) => machine.position().get(*axis).copied().unwrap_or(0f32),
+            EExpr::ETarget(axis) => self.target.get(*axis).copied().unwrap_or(0f32),
+            EExpr::EFollowingError(axis) => machine.following_error(*axis),
+            EExpr::EIdle => truth(!machine.is_moving()),
+            EExpr::ENegate(inner) => -self.eval(inner, machine)?,
+            EExpr::ENot(inner) => truth(self.eval(inner, machine)? == 0f32),
+            EExpr::EBinary(op, left, right) => {
+                let left = self.eval(left, machine)?;
+                let right = self.eval(right, machine)?;
+                match op {
+                    EBinaryOp::EAdd => left + right,
+                    EBinaryOp::ESub => left - right,
+                    EBinaryOp::EMul => left * right,
+                    EBinaryOp::EDiv if right == 0f32 => return Err(String::from("Division by zero")),
+                    EBinaryOp::EDiv => left / right,
+                    EBinaryOp::ELess => truth(left < right),
+                    EBinaryOp::ELessEqual => truth(left <= right),
+                    EBinaryOp::EGreater => truth(left > right),
+                    EBinaryOp::EGreaterEqual => truth(left >= right),
+                    EBinaryOp::EEqual => truth(left == right),
+                    EBinaryOp::ENotEqual => truth(left != right),
+                    EBinaryOp::EAnd => truth(left != 0f32 && right != 0f32),
+                    EBinaryOp::EOr => truth(left != 0f32 || right != 0f32),
+                }
+            },
+        })
+    }
+}
+
+/// Machine model for dry runs: moves arrive at once and must stay within the axis limits.
+struct DryRunMachine<'a> {
+    axes        : &'a [AxisInfo],
+    position    : Vec<f32>,
+    moves       : usize,
+    distance    : f32,
+}
+
+impl<'a> MacroMachine for DryRunMachine<'a> {
+    fn position(&self) -> Vec<f32> {
+        self.position.clone()
+    }
+
+    fn following_error(&self, _axis: usize) -> f32 {
+        0f32
+    }
+
+    fn is_moving(&self) -> bool {
+        false
+    }
+
+    fn fault(&self) -> Option<String> {
+        None
+    }
+
+    fn move_to(&mut self, target: &[f32]) -> Result<(), String> {
+        for (axis, value) in self.axes.iter().zip(target.iter()) {
+            if *value < axis.min || *value > axis.max {
+                return Err(format!("{} {:.3} is outside the limits {:.3} to {:.3}", axis.name, value, axis.min, axis.max));
+            }
+        }
+        self.distance += self.position.iter().zip(target.iter()).map(|(a, b)| (b - a) * (b - a)).sum::<f32>().sqrt();
+        self.moves += 1;
+        self.position = target.to_vec();
+        Ok(())
+    }
+
+    fn home(&mut self) -> Result<Vec<f32>, String> {
+        let home: Vec<f32> = self.axes.iter().map(|axis| axis.min).collect();
+        self.move_to(&home)?;
+        Ok(home)
+    }
+}
+
+/// Outcome of running a macro against the dry-run model.
+#[derive(Clone, Debug)]
+pub struct DryRunReport {
+    pub name            : String,
+    pub state           : EMacroState,
+    pub moves           : usize,
+    /// Straight line distance of all moves in machine units.
+    pub distance        : f32,
+    pub slept           : f32,
+    pub final_position  : Vec<f32>,
+    pub output          : Vec<String>,
+}
+
+impl DryRunReport {
+    /// Updates a dry run gets before it counts as endless.
+    const MAX_UPDATES: usize = 100;
+
+    /// Runs `program` from `start_position` without touching the machine.
+    pub fn run(program: Macro, axes: &[AxisInfo], start_position: Vec<f32>) -> DryRunReport {
+        let mut machine = DryRunMachine{ axes, position: start_position.clone(), moves: 0, distance: 0f32 };
+        let mut run = MacroRun::new(program, start_position, true);
+        for _ in 0..DryRunReport::MAX_UPDATES {
+            run.update(&mut machine);
+        }
+        if run.state == EMacroState::ERunning {
+            run.state = EMacroState::EFailed(format!("line {}: still running after {} statements, endless loop?", run.line(),
+                DryRunReport::MAX_UPDATES * MacroRun::STEPS_PER_UPDATE));
+        }
+        DryRunReport{
+            name            : run.name().to_string(),
+            state           : run.state,
+            moves           : machine.moves,
+            distance        : machine.distance,
+            slept           : run.slept,
+            final_position  : machine.position,
+            output          : run.output,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let outcome = match self.state {
+            EMacroState::EFailed(ref e) => format!("fails at {}", e),
+            _ => String::from("ok"),
+        };
+        format!("Dry run of {}: {}, {} moves over {:.1}, {:.1} s of sleeps", self.name, outcome, self.moves, self.distance, self.slept)
+    }
+}
+
+impl MacroMachine for CncCtrl {
+    fn position(&self) -> Vec<f32> {
+        self.current_coords.values.clone()
+    }
+
+    fn following_error(&self, axis: usize) -> f32 {
+        self.following_error.axes.get(axis).map_or(0f32, |axis| axis.current)
+    }
+
+    fn is_moving(&self) -> bool {
+        matches!(self.state(), EMachineState::EJogging | EMachineState::EHoming)
+    }
+
+    fn fault(&self) -> Option<String> {
+        match self.state() {
+            EMachineState::EAlarm | EMachineState::EEStop | EMachineState::EDisconnected => Some(format!("Stopped, machine is {}", self.state().name())),
+            _ => None,
+        }
+    }
+
+    fn move_to(&mut self, target: &[f32]) -> Result<(), String> {
+        let mut coords = CncCoordinates::with_axes(target.len());
+        for (axis, value) in target.iter().enumerate() {
+            coords.set(axis, *value);
+        }
+        self.set_target_coords(coords)
+    }
+
+    fn home(&mut self) -> Result<Vec<f32>, String> {
+        CncCtrl::home(self)?;
+        Ok(self.axes.iter().map(|axis| axis.min).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str) -> Result<Macro, String> {
+        Macro::compile(&MacroDef{ name: String::from("test"), source: String::from(source) }, &AxisInfo::default_axes())
+    }
+
+    fn dry_run(source: &str) -> DryRunReport {
+        DryRunReport::run(compile(source).unwrap(), &AxisInfo::default_axes(), vec![0f32, 0f32, 0f32])
+    }
+
+    #[test]
+    fn compile_errors_name_the_line() {
+        assert_eq!(compile("wait\nmove Q=1").unwrap_err(), "line 2: The machine has no axis Q");
+        assert_eq!(compile("jog X=depth").unwrap_err(), "line 1: Unknown variable depth");
+        assert_eq!(compile("\nif 1\nwait").unwrap_err(), "line 2: block isn't closed with end");
+        assert_eq!(compile("end").unwrap_err(), "line 1: end without if, repeat or while");
+        assert!(compile("fly X=1").is_err());
+    }
+
+    #[test]
+    fn loops_and_conditions_drive_the_moves() {
+        let report = dry_run("\
+            let step = 10   # mm
+            repeat 3
+                jog X=step
+            end
+            while pos.X < 50
+                jog X=step / 2
+                wait
+            end
+            if pos.X == 50 and not (pos.Y > 0)
+                move Y=20
+            else
+                move Y=40
+            end
+            print at {pos.X}, {target.Y}");
+        assert_eq!(report.state, EMacroState::EFinished);
+        assert_eq!(report.final_position, vec![50f32, 20f32, 0f32]);
+        assert_eq!(report.moves, 8);
+        assert_eq!(report.output, vec![String::from("at 50.000, 20.000")]);
+    }
+
+    #[test]
+    fn dry_run_stops_at_the_soft_limits() {
+        let report = dry_run("move Z=100\nmove Z=200\nmove Z=0");
+        assert_eq!(report.state, EMacroState::EFailed(String::from("line 2: Z 200.000 is outside the limits 0.000 to 150.000")));
+        assert_eq!(report.final_position, vec![0f32, 0f32, 100f32]);
+    }
+
+    #[test]
+    fn sleeps_must_be_reasonable() {
+        assert_eq!(dry_run("sleep 0.5\nsleep 2").slept, 2.5f32);
+        assert_eq!(dry_run("sleep 100000000000000000000").state, EMacroState::EFailed(String::from("line 1: Can't sleep 100000000000000000000 seconds, at most 86400")));
+        assert!(matches!(dry_run("sleep 0 - 1").state, EMacroState::EFailed(_)));
+        assert!(matches!(dry_run("let a = 0\nsleep 1 / a").state, EMacroState::EFailed(_)));
+    }
+
+    #[test]
+    fn endless_loops_are_reported() {
+        let report = dry_run("let x = 1\nwhile x > 0\nsleep 1\nend");
+        assert!(matches!(report.state, EMacroState::EFailed(ref e) if e.contains("endless loop")));
+    }
+}
@@ -1,32 +1,238 @@
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 use std::error::Error;
 
+/// Upper bound on the axis count accepted from a handshake or profile.
+pub const MAX_AXES: usize = 6;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EAxisKind {
+    ELinear,
+    ERotary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AxisInfo{
+    pub name: String,
+    pub kind: EAxisKind,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl AxisInfo {
+    pub fn new(name: &str, kind: EAxisKind, min: f32, max: f32) -> AxisInfo {
+        AxisInfo{
+            name: String::from(name),
+            kind,
+            min,
+            max,
+        }
+    }
+
+    /// The original X/Y/Z machine.
+    pub fn default_axes() -> Vec<AxisInfo> {
+        vec![
+            AxisInfo::new("X", EAxisKind::ELinear, 0f32, 500f32),
+            AxisInfo::new("Y", EAxisKind::ELinear, 0f32, 500f32),
+            AxisInfo::new("Z", EAxisKind::ELinear, 0f32, 150f32),
+        ]
+    }
+
+    /// Parses a list like `X Y Z A` or `X:0:300 Y:0:200`. A, B and C are rotary axes.
+    pub fn parse_axes(spec: &str) -> Result<Vec<AxisInfo>, String> {
+        let mut axes = Vec::new();
+        for token in spec.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty()) {
+            let parts: Vec<&str> = token.split(':').collect();
+            let name = parts[0].to_uppercase();
+            let kind = match name.as_str() {
+                "A" | "B" | "C" => EAxisKind::ERotary,
+                "X" | "Y" | "Z" | "U" | "V" | "W" => EAxisKind::ELinear,
+                _ => return Err(format!("Unknown axis '{}'", parts[0])),
+            };
+            let (min, max) = match parts.len() {
+                1 => match kind {
+                    EAxisKind::ERotary => (0f32, 360f32),
+                    EAxisKind::ELinear if name == "Z" => (0f32, 150f32),
+                    EAxisKind::ELinear => (0f32, 500f32),
+                },
+                3 => {
+                    let min = parts[1].parse::<f32>().map_err(|e| format!("Bad minimum in '{}': {:?}", token, e))?;
+                    let max = parts[2].parse::<f32>().map_err(|e| format!("Bad maximum in '{}': {:?}", token, e))?;
+                    (min, max)
+                },
+                _ => return Err(format!("Expected NAME or NAME:MIN:MAX, got '{}'", token)),
+            };
+            axes.push(AxisInfo::new(&name, kind, min, max));
+        }
+        AxisInfo::validate_axes(&axes)?;
+        Ok(axes)
+    }
+
+    /// Checks axes wherever they come from: the axes form, a profile or the handshake.
+    pub fn validate_axes(axes: &[AxisInfo]) -> Result<(), String> {
+        if axes.is_empty() || axes.len() > MAX_AXES {
+            return Err(format!("Expected between 1 and {} axes, got {}", MAX_AXES, axes.len()));
+        }
+        for (i, axis) in axes.iter().enumerate() {
+            if axis.name.is_empty() {
+                return Err(format!("Axis {} has no name", i + 1));
+            }
+            if axes[..i].iter().any(|other| other.name == axis.name) {
+                return Err(format!("Axis {} listed twice", axis.name));
+            }
+            if !axis.min.is_finite() || !axis.max.is_finite() {
+                return Err(format!("Axis {} limits must be numbers", axis.name));
+            }
+            if axis.max <= axis.min {
+                return Err(format!("Axis {} maximum must be above minimum", axis.name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn format_axes(axes: &[AxisInfo]) -> String {
+        let tokens: Vec<String> = axes.iter().map(|axis| format!("{}:{}:{}", axis.name, axis.min, axis.max)).collect();
+        tokens.join(" ")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EAlarmCode {
+    EFollowingError,
+    ELimitSwitch,
+    EEncoderFailure,
+    EWatchdogReset,
+    /// A code this version doesn't know.
+    EUnknown(u16),
+}
+
+impl EAlarmCode {
+    pub fn from_code(code: u16) -> EAlarmCode {
+        match code {
+            1 => EAlarmCode::EFollowingError,
+            2 => EAlarmCode::ELimitSwitch,
+            3 => EAlarmCode::EEncoderFailure,
+            4 => EAlarmCode::EWatchdogReset,
+            _ => EAlarmCode::EUnknown(code),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            EAlarmCode::EFollowingError => 1,
+            EAlarmCode::ELimitSwitch => 2,
+            EAlarmCode::EEncoderFailure => 3,
+            EAlarmCode::EWatchdogReset => 4,
+            EAlarmCode::EUnknown(code) => *code,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            EAlarmCode::EFollowingError => String::from("Following error exceeded"),
+            EAlarmCode::ELimitSwitch => String::from("Limit switch hit"),
+            EAlarmCode::EEncoderFailure => String::from("Encoder failure"),
+            EAlarmCode::EWatchdogReset => String::from("Watchdog reset"),
+            EAlarmCode::EUnknown(code) => format!("Unknown fault {}", code),
+        }
+    }
+}
+
+/// Axis affected by an alarm, with a code specific value such as the following error
+/// in mm or the limit switch position.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlarmAxisDetail{
+    pub axis: u8,
+    pub value: f32,
+}
+
+/// Fault reported by the controller. It stays latched on the controller until reset.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlarmReport{
+    /// `EAlarmCode::code`, kept numeric so unknown codes still decode.
+    pub code: u16,
+    pub axes: Vec<AlarmAxisDetail>,
+}
+
+/// Controller handshake, describing the axes it drives.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MachineInfo{
+    pub protocol_version: u16,
+    pub axes: Vec<AxisInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct CncCoordinates{
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+    pub values: Vec<f32>,
 }
 
 impl CncCoordinates {
-    pub fn new() -> CncCoordinates {
+    pub fn with_axes(axis_count: usize) -> CncCoordinates {
         CncCoordinates{
-            x: 0.0f32,
-            y: 0f32,
-            z: 0f32,
+            values: vec![0f32; axis_count],
         }
     }
+
+    pub fn get(&self, axis: usize) -> f32 {
+        self.values.get(axis).copied().unwrap_or(0f32)
+    }
+
+    pub fn set(&mut self, axis: usize, value: f32) {
+        if let Some(v) = self.values.get_mut(axis) {
+            *v = value;
+        }
+    }
+
+    pub fn x(&self) -> f32 {
+        self.get(0)
+    }
+
+    pub fn y(&self) -> f32 {
+        self.get(1)
+    }
+
+    pub fn z(&self) -> f32 {
+        self.get(2)
+    }
+
+    /// Resizes to `axis_count`, keeping existing values and zero filling new axes.
+    pub fn resize(&mut self, axis_count: usize) {
+        self.values.resize(axis_count, 0f32);
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Per axis values go on the wire back to back without a length prefix, so that
+/// three axes keep the layout of the original fixed size `[T; 3]` messages.
+fn serialize_seq<T: Serialize>(type_id: u8, items: &[T]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut temp_vec = Vec::from( [type_id; 1] );
+    for item in items {
+        temp_vec.append(&mut bincode::serialize(item)?);
+    }
+    Ok(temp_vec)
+}
+
+fn deserialize_seq<T: DeserializeOwned>(payload: &mut &[u8], count: usize) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        items.push(bincode::deserialize_from(&mut *payload)?);
+    }
+    Ok(items)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PIDParams{
     pub prop: f32,
     pub inte: f32,
     pub deri: f32,
 }
 
+impl Default for PIDParams {
+    fn default() -> Self {
+        PIDParams::new()
+    }
+}
+
 impl PIDParams {
     pub fn new() -> PIDParams {
         PIDParams{
@@ -35,12 +241,135 @@ impl PIDParams {
             deri: 0f32,
         }
     }
+
+    /// Compares all gains, allowing for float rounding on the controller side.
+    pub fn approx_eq(&self, other: &PIDParams, tolerance: f32) -> bool {
+        fn close(a: f32, b: f32, tolerance: f32) -> bool {
+            (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0f32)
+        }
+        close(self.prop, other.prop, tolerance)
+            && close(self.inte, other.inte, tolerance)
+            && close(self.deri, other.deri, tolerance)
+    }
+
+    /// Component wise `self - other`.
+    pub fn diff(&self, other: &PIDParams) -> PIDParams {
+        PIDParams{
+            prop: self.prop - other.prop,
+            inte: self.inte - other.inte,
+            deri: self.deri - other.deri,
+        }
+    }
 }
 
+/// Field names and valid ranges of the numeric `AxisConfig` parameters, in `get_field` order.
+pub const AXIS_CONFIG_FIELDS: [(&str, f32, f32); 7] = [
+    ("I LIMIT",     0.0f32,     1_000_000.0f32),
+    ("OUT CLAMP",   0.0f32,     100.0f32),
+    ("D FILTER",    0.0f32,     1.0f32),
+    ("FF VEL",      0.0f32,     1000.0f32),
+    ("FF ACC",      0.0f32,     1000.0f32),
+    ("DEADBAND",    0.0f32,     10.0f32),
+    ("COUNTS/MM",   0.001f32,   1_000_000.0f32),
+];
+
+/// Full per axis controller configuration. Firmware that only understands
+/// `PIDParams` keeps receiving those; this is sent in addition when supported.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AxisConfig{
+    pub version: u16,
+    pub pid: PIDParams,
+    pub integral_limit: f32,
+    pub output_limit: f32,
+    pub derivative_filter: f32,
+    pub feed_forward_vel: f32,
+    pub feed_forward_acc: f32,
+    pub deadband: f32,
+    pub counts_per_mm: f32,
+    pub invert_direction: bool,
+}
+
+impl Default for AxisConfig {
+    fn default() -> Self {
+        AxisConfig::new()
+    }
+}
+
+impl AxisConfig {
+    pub const VERSION: u16 = 1;
+
+    pub fn new() -> AxisConfig {
+        AxisConfig{
+            version: AxisConfig::VERSION,
+            pid: PIDParams::new(),
+            integral_limit: 100.0f32,
+            output_limit: 100.0f32,
+            derivative_filter: 0.0f32,
+            feed_forward_vel: 0.0f32,
+            feed_forward_acc: 0.0f32,
+            deadband: 0.0f32,
+            counts_per_mm: 100.0f32,
+            invert_direction: false,
+        }
+    }
+
+    pub fn get_field(&self, index: usize) -> f32 {
+        match index {
+            0 => self.integral_limit,
+            1 => self.output_limit,
+            2 => self.derivative_filter,
+            3 => self.feed_forward_vel,
+            4 => self.feed_forward_acc,
+            5 => self.deadband,
+            6 => self.counts_per_mm,
+            _ => 0.0f32,
+        }
+    }
+
+    pub fn set_field(&mut self, index: usize, value: f32) {
+        match index {
+            0 => self.integral_limit = value,
+            1 => self.output_limit = value,
+            2 => self.derivative_filter = value,
+            3 => self.feed_forward_vel = value,
+            4 => self.feed_forward_acc = value,
+            5 => self.deadband = value,
+            6 => self.counts_per_mm = value,
+            _ => {},
+        }
+    }
+
+    pub fn field_in_range(&self, index: usize) -> bool {
+        let (_, min, max) = AXIS_CONFIG_FIELDS[index];
+        let value = self.get_field(index);
+        value.is_finite() && value >= min && value <= max
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, (name, min, max)) in AXIS_CONFIG_FIELDS.iter().enumerate() {
+            if !self.field_in_range(index) {
+                return Err(format!("{} must be between {} and {}", name, min, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
 pub enum ECncCtrlMessage {
     ETargetPosition(CncCoordinates),
-    EPIDParams([PIDParams; 3]),
+    EPIDParams(Vec<PIDParams>),
     EQuit,
+    ERequestPIDParams,
+    EAxisConfig(Vec<AxisConfig>),
+    ERequestAxisConfig,
+    ERequestMachineInfo,
+    /// Answered with `ECncStatusMessage::EPong` carrying the same sequence number.
+    EPing(u32),
+    /// Clears latched alarms on the controller.
+    EResetAlarm,
+    /// Decelerates to a stop on the current path and holds there.
+    EFeedHold,
 }
 
 impl ECncCtrlMessage {
@@ -49,6 +378,13 @@ impl ECncCtrlMessage {
             ECncCtrlMessage::ETargetPosition(_) => 1,
             ECncCtrlMessage::EPIDParams(_) => 2,
             ECncCtrlMessage::EQuit => 3,
+            ECncCtrlMessage::ERequestPIDParams => 4,
+            ECncCtrlMessage::EAxisConfig(_) => 5,
+            ECncCtrlMessage::ERequestAxisConfig => 6,
+            ECncCtrlMessage::ERequestMachineInfo => 7,
+            ECncCtrlMessage::EPing(_) => 8,
+            ECncCtrlMessage::EResetAlarm => 9,
+            ECncCtrlMessage::EFeedHold => 10,
         }
     }
 
@@ -56,32 +392,48 @@ impl ECncCtrlMessage {
         let u_type_id = self.get_type_id();
         match self {
             ECncCtrlMessage::ETargetPosition(coords) => {
-                match bincode::serialize(&coords) {
-                    Ok(mut vec) => {
-                        let mut temp_vec = Vec::from( [u_type_id; 1] );
-                        temp_vec.append(&mut vec);
-                        Ok(temp_vec)
-                    },
-                    Err(e) => {
-                        Err(e)
-                    },
-                }
+                serialize_seq(u_type_id, &coords.values)
             },
             ECncCtrlMessage::EPIDParams(params) => {
-                match bincode::serialize(&params) {
-                    Ok(mut vec) => {
-                        let mut temp_vec = Vec::from( [u_type_id; 1] );
-                        temp_vec.append(&mut vec);
-                        Ok(temp_vec)
-                    },
-                    Err(e) => {
-                        Err(e)
-                    },
-                }
+                serialize_seq(u_type_id, params)
             },
             ECncCtrlMessage::EQuit => {
                 Ok(Vec::new())
             },
+            ECncCtrlMessage::ERequestPIDParams | ECncCtrlMessage::ERequestAxisConfig | ECncCtrlMessage::ERequestMachineInfo
+                | ECncCtrlMessage::EResetAlarm | ECncCtrlMessage::EFeedHold => {
+                Ok(Vec::from( [u_type_id; 1] ))
+            },
+            ECncCtrlMessage::EAxisConfig(configs) => {
+                serialize_seq(u_type_id, configs)
+            },
+            ECncCtrlMessage::EPing(sequence) => {
+                let mut payload = Vec::from( [u_type_id; 1] );
+                payload.append(&mut bincode::serialize(sequence)?);
+                Ok(payload)
+            },
+        }
+    }
+
+    /// Decodes one message from the front of `buffer`, advancing it past the message so
+    /// that several messages arriving in one read can be taken apart. Used by the simulator.
+    pub fn bin_deserialize(buffer: &mut &[u8], axis_count: usize) -> Result<ECncCtrlMessage, Box<dyn Error>> {
+        let (msg_type, payload) = match buffer.split_first() {
+            Some((msg_type, payload)) => (*msg_type, payload),
+            None => return Err("Empty control message".into()),
+        };
+        *buffer = payload;
+        match msg_type {
+            1 => Ok(ECncCtrlMessage::ETargetPosition(CncCoordinates{ values: deserialize_seq(buffer, axis_count)? })),
+            2 => Ok(ECncCtrlMessage::EPIDParams(deserialize_seq(buffer, axis_count)?)),
+            4 => Ok(ECncCtrlMessage::ERequestPIDParams),
+            5 => Ok(ECncCtrlMessage::EAxisConfig(deserialize_seq(buffer, axis_count)?)),
+            6 => Ok(ECncCtrlMessage::ERequestAxisConfig),
+            7 => Ok(ECncCtrlMessage::ERequestMachineInfo),
+            8 => Ok(ECncCtrlMessage::EPing(bincode::deserialize_from(&mut *buffer)?)),
+            9 => Ok(ECncCtrlMessage::EResetAlarm),
+            10 => Ok(ECncCtrlMessage::EFeedHold),
+            _ => Err(format!("Unknown control message received: {}", msg_type).into()),
         }
     }
 }
@@ -101,14 +453,19 @@ pub struct CncAxisStatus{
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct  CncStatus{
     pub cycle_time: i32,
-    pub axis_status: [CncAxisStatus; 3],
+    pub axis_status: Vec<CncAxisStatus>,
 }
 
+#[derive(Debug)]
 pub enum ECncStatusMessage {
     ECurrentPosition(CncCoordinates),
     EStatus(CncStatus),
-    EPIDParams([PIDParams;3]),
+    EPIDParams(Vec<PIDParams>),
     EDisconnected,
+    EAxisConfig(Vec<AxisConfig>),
+    EMachineInfo(MachineInfo),
+    EPong(u32),
+    EAlarm(AlarmReport),
 }
 
 impl ECncStatusMessage {
@@ -118,6 +475,150 @@ impl ECncStatusMessage {
             ECncStatusMessage::EStatus(_) => 1,
             ECncStatusMessage::EPIDParams(_) => 2,
             ECncStatusMessage::EDisconnected => 3,
+            ECncStatusMessage::EAxisConfig(_) => 4,
+            ECncStatusMessage::EMachineInfo(_) => 5,
+            ECncStatusMessage::EPong(_) => 6,
+            ECncStatusMessage::EAlarm(_) => 7,
         }
     }
-}
\ No newline at end of file
+
+    /// Encodes the message the way the controller sends it. Used by the simulator.
+    pub fn bin_serialize(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let u_type_id = self.get_type_id();
+        match self {
+            ECncStatusMessage::ECurrentPosition(coords) => {
+                serialize_seq(u_type_id, &coords.values)
+            },
+            ECncStatusMessage::EStatus(status) => {
+                let mut payload = Vec::from( [u_type_id; 1] );
+                payload.append(&mut bincode::serialize(&status.cycle_time)?);
+                payload.append(&mut serialize_seq(u_type_id, &status.axis_status)?.split_off(1));
+                Ok(payload)
+            },
+            ECncStatusMessage::EPIDParams(params) => {
+                serialize_seq(u_type_id, params)
+            },
+            ECncStatusMessage::EDisconnected => {
+                Err("EDisconnected is not sent on the wire".into())
+            },
+            ECncStatusMessage::EAxisConfig(configs) => {
+                serialize_seq(u_type_id, configs)
+            },
+            ECncStatusMessage::EMachineInfo(info) => {
+                let mut payload = Vec::from( [u_type_id; 1] );
+                payload.append(&mut bincode::serialize(info)?);
+                Ok(payload)
+            },
+            ECncStatusMessage::EPong(sequence) => {
+                let mut payload = Vec::from( [u_type_id; 1] );
+                payload.append(&mut bincode::serialize(sequence)?);
+                Ok(payload)
+            },
+            ECncStatusMessage::EAlarm(report) => {
+                let mut payload = Vec::from( [u_type_id; 1] );
+                payload.append(&mut bincode::serialize(report)?);
+                Ok(payload)
+            },
+        }
+    }
+
+    /// Decodes one message from the front of `buffer`, reading `axis_count` entries for per
+    /// axis data, and advances it past the message. A read can hold several messages and
+    /// end part way through one, see `is_truncated`.
+    pub fn bin_deserialize(buffer: &mut &[u8], axis_count: usize) -> Result<ECncStatusMessage, Box<dyn Error>> {
+        let (status_type, payload) = match buffer.split_first() {
+            Some((status_type, payload)) => (*status_type, payload),
+            None => return Err("Empty status message".into()),
+        };
+        *buffer = payload;
+        match status_type {
+            0 => {
+                Ok(ECncStatusMessage::ECurrentPosition(CncCoordinates{ values: deserialize_seq(buffer, axis_count)? }))
+            },
+            1 => {
+                let cycle_time = bincode::deserialize_from(&mut *buffer)?;
+                let axis_status = deserialize_seq(buffer, axis_count)?;
+                Ok(ECncStatusMessage::EStatus(CncStatus{ cycle_time, axis_status }))
+            },
+            2 => {
+                Ok(ECncStatusMessage::EPIDParams(deserialize_seq(buffer, axis_count)?))
+            },
+            4 => {
+                Ok(ECncStatusMessage::EAxisConfig(deserialize_seq(buffer, axis_count)?))
+            },
+            5 => {
+                let info: MachineInfo = bincode::deserialize_from(&mut *buffer)?;
+                AxisInfo::validate_axes(&info.axes).map_err(|e| format!("Handshake reported invalid axes: {}", e))?;
+                Ok(ECncStatusMessage::EMachineInfo(info))
+            },
+            6 => {
+                Ok(ECncStatusMessage::EPong(bincode::deserialize_from(&mut *buffer)?))
+            },
+            7 => {
+                Ok(ECncStatusMessage::EAlarm(bincode::deserialize_from(&mut *buffer)?))
+            },
+            _ => {
+                Err(format!("Unknown status received: {}", status_type).into())
+            },
+        }
+    }
+}
+
+/// Whether decoding failed only because the buffer ended part way through the message,
+/// whose rest comes with the next read.
+pub fn is_truncated(error: &(dyn Error + 'static)) -> bool {
+    match error.downcast_ref::<bincode::Error>().map(|e| &**e) {
+        Some(bincode::ErrorKind::Io(e)) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axis_specs_parse_with_default_or_explicit_limits() {
+        let axes = AxisInfo::parse_axes("x y:-10:10, z a").unwrap();
+        assert_eq!(axes, vec![
+            AxisInfo::new("X", EAxisKind::ELinear, 0f32, 500f32),
+            AxisInfo::new("Y", EAxisKind::ELinear, -10f32, 10f32),
+            AxisInfo::new("Z", EAxisKind::ELinear, 0f32, 150f32),
+            AxisInfo::new("A", EAxisKind::ERotary, 0f32, 360f32),
+        ]);
+        assert_eq!(AxisInfo::parse_axes(&AxisInfo::format_axes(&axes)).unwrap(), axes);
+    }
+
+    #[test]
+    fn bad_axis_specs_are_rejected() {
+        for spec in ["", "Q", "X X", "X:5:1", "X:1", "X:a:2", "X Y Z U V W A", "X:nan:1", "X:0:inf", "X:-inf:0"] {
+            assert!(AxisInfo::parse_axes(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn handshakes_with_invalid_axes_are_refused() {
+        let decode = |axes: Vec<AxisInfo>| {
+            let payload = ECncStatusMessage::EMachineInfo(MachineInfo{ protocol_version: 1, axes }).bin_serialize().unwrap();
+            ECncStatusMessage::bin_deserialize(&mut payload.as_slice(), 3)
+        };
+        assert!(decode(AxisInfo::default_axes()).is_ok());
+        assert!(decode(Vec::new()).is_err());
+        assert!(decode(vec![AxisInfo::new("X", EAxisKind::ELinear, 0f32, f32::NAN)]).is_err());
+        assert!(decode(vec![AxisInfo::new("X", EAxisKind::ELinear, 0f32, 1f32), AxisInfo::new("X", EAxisKind::ELinear, 0f32, 1f32)]).is_err());
+    }
+
+    #[test]
+    fn status_messages_are_taken_apart_and_truncation_is_told_apart() {
+        let mut payload = ECncStatusMessage::EPong(7).bin_serialize().unwrap();
+        payload.extend(ECncStatusMessage::ECurrentPosition(CncCoordinates{ values: vec![1f32, 2f32, 3f32] }).bin_serialize().unwrap());
+        let mut buffer = payload.as_slice();
+        assert!(matches!(ECncStatusMessage::bin_deserialize(&mut buffer, 3), Ok(ECncStatusMessage::EPong(7))));
+        let position = ECncStatusMessage::bin_deserialize(&mut buffer, 3);
+        assert!(matches!(position, Ok(ECncStatusMessage::ECurrentPosition(ref coords)) if coords.values == vec![1f32, 2f32, 3f32]));
+        assert!(buffer.is_empty());
+
+        let e = ECncStatusMessage::bin_deserialize(&mut &payload[5..payload.len() - 1], 3).err().unwrap();
+        assert!(is_truncated(&*e));
+        assert!(!is_truncated(&*ECncStatusMessage::bin_deserialize(&mut &[42u8][..], 3).err().unwrap()));
+    }
+}
@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cnc_log::civil_from_days;
+use crate::cnc_msg::PIDParams;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EPidSource {
+    ESent,
+    EReceived,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PidHistoryEntry {
+    pub version     : u32,
+    pub timestamp   : u64,
+    pub source      : EPidSource,
+    pub params      : Vec<PIDParams>,
+    pub note        : String,
+}
+
+impl PidHistoryEntry {
+    pub fn label(&self) -> String {
+        let source = match self.source {
+            EPidSource::ESent => "SENT",
+            EPidSource::EReceived => "RECV",
+        };
+        if self.note.is_empty() {
+            format!("v{} {} {}", self.version, format_timestamp(self.timestamp), source)
+        } else {
+            format!("v{} {} {} - {}", self.version, format_timestamp(self.timestamp), source, self.note)
+        }
+    }
+}
+
+/// Versioned record of every PID parameter set sent to or received from the controller.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PidHistory {
+    entries         : Vec<PidHistoryEntry>,
+    next_version    : u32,
+}
+
+impl Default for PidHistory {
+    fn default() -> Self {
+        PidHistory::new()
+    }
+}
+
+impl PidHistory {
+    pub fn new() -> Self {
+        PidHistory{
+            entries         : Vec::new(),
+            next_version    : 1,
+        }
+    }
+
+    /// Adds a new version and returns its number. Received sets identical to the
+    /// latest entry are not recorded again, since the controller may echo them.
+    pub fn record(&mut self, source: EPidSource, params: &[PIDParams], note: &str) -> Option<u32> {
+        if source == EPidSource::EReceived {
+            if let Some(last) = self.entries.last() {
+                if last.params == params {
+                    return None;
+                }
+            }
+        }
+
+        let version = self.next_version;
+        self.next_version += 1;
+        self.entries.push(PidHistoryEntry{
+            version,
+            timestamp   : now_timestamp(),
+            source,
+            params      : params.to_vec(),
+            note        : String::from(note),
+        });
+        Some(version)
+    }
+
+    pub fn entries(&self) -> &[PidHistoryEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, version: u32) -> Option<&PidHistoryEntry> {
+        self.entries.iter().find(|entry| entry.version == version)
+    }
+
+    pub fn set_note(&mut self, version: u32, note: &str) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.version == version) {
+            Some(entry) => {
+                entry.note = String::from(note);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Per axis difference `current - version`, or `None` if the version is unknown.
+    /// Axes missing from either side are left out.
+    pub fn diff(&self, version: u32, current: &[PIDParams]) -> Option<Vec<PIDParams>> {
+        self.get(version).map(|entry| {
+            current.iter().zip(entry.params.iter()).map(|(current, old)| current.diff(old)).collect()
+        })
+    }
+}
+
+pub fn now_timestamp() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+    let secs = timestamp % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EPidVerifyState {
+    EUnknown,
+    EPending,
+    EApplied,
+    EMismatch,
+    ENoReply,
+}
+
+/// Tracks whether the last sent PID set has been read back from the controller.
+pub struct PidVerification {
+    pub axis_state  : Vec<EPidVerifyState>,
+    expected        : Option<Vec<PIDParams>>,
+    requested_at    : Option<Instant>,
+    attempts        : u32,
+    /// Requests sent whose reply hasn't arrived yet. Replies come back in order.
+    replies_due     : u32,
+    /// Replies still to come for requests sent before the params, which can't show them.
+    stale_replies   : u32,
+}
+
+impl PidVerification {
+    pub const TOLERANCE: f32 = 1e-4;
+    pub const REPLY_TIMEOUT: Duration = Duration::from_millis(1000);
+    pub const MAX_ATTEMPTS: u32 = 3;
+
+    pub fn new(axis_count: usize) -> Self {
+        PidVerification{
+            axis_state  : vec![EPidVerifyState::EUnknown; axis_count],
+            expected    : None,
+            requested_at: None,
+            attempts    : 0,
+            replies_due : 0,
+            stale_replies: 0,
+        }
+    }
+
+    pub fn start(&mut self, sent: &[PIDParams]) {
+        self.expected = Some(sent.to_vec());
+        self.axis_state = vec![EPidVerifyState::EPending; sent.len()];
+        self.attempts = 0;
+        self.requested_at = None;
+        self.stale_replies = self.replies_due;
+    }
+
+    pub fn on_requested(&mut self) {
+        self.requested_at = Some(Instant::now());
+        self.attempts += 1;
+        self.replies_due += 1;
+    }
+
+    /// Returns true when a (re)request for the controller's params should be sent.
+    pub fn needs_request(&mut self) -> bool {
+        if self.expected.is_none() {
+            return false;
+        }
+        match self.requested_at {
+            None => true,
+            Some(at) => {
+                if at.elapsed() < PidVerification::REPLY_TIMEOUT {
+                    false
+                } else if self.attempts < PidVerification::MAX_ATTEMPTS {
+                    true
+                } else {
+                    self.axis_state = vec![EPidVerifyState::ENoReply; self.axis_state.len()];
+                    self.expected = None;
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn on_received(&mut self, received: &[PIDParams]) {
+        self.replies_due = self.replies_due.saturating_sub(1);
+        if self.stale_replies > 0 {
+            self.stale_replies -= 1;
+            return;
+        }
+        if let Some(ref expected) = self.expected {
+            for (axis, state) in self.axis_state.iter_mut().enumerate() {
+                *state = match (expected.get(axis), received.get(axis)) {
+                    (Some(expected), Some(received)) if expected.approx_eq(received, PidVerification::TOLERANCE) => EPidVerifyState::EApplied,
+                    _ => EPidVerifyState::EMismatch,
+                };
+            }
+            self.expected = None;
+        }
+    }
+
+    pub fn state(&self, axis: usize) -> EPidVerifyState {
+        self.axis_state.get(axis).copied().unwrap_or(EPidVerifyState::EUnknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_to_earlier_requests_are_not_the_read_back() {
+        let old = vec![PIDParams::new()];
+        let sent = vec![PIDParams{ prop: 2f32, inte: 0.5f32, deri: 0f32 }];
+        let mut verification = PidVerification::new(1);
+        verification.on_requested();
+        verification.start(&sent);
+        verification.on_requested();
+        // Answers the request made before the params were sent.
+        verification.on_received(&old);
+        assert_eq!(verification.state(0), EPidVerifyState::EPending);
+        verification.on_received(&sent);
+        assert_eq!(verification.state(0), EPidVerifyState::EApplied);
+    }
+
+    #[test]
+    fn timestamps_format_as_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34:56");
+    }
+}
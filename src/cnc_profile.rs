@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cnc_msg::{AxisInfo, PIDParams};
+use crate::cnc_following_error::FollowingErrorLimits;
+use crate::cnc_job_stats::MotionLimits;
+use crate::cnc_macro::MacroDef;
+use crate::cnc_pid_history::PidHistory;
+use crate::log_error;
+
+const PROFILE_DIR: &str = "./data/profiles";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PidPreset {
+    pub name    : String,
+    pub params  : Vec<PIDParams>,
+}
+
+impl PidPreset {
+    pub fn export(&self, path: &str) -> Result<(), String> {
+        let payload = serde_json::to_vec_pretty(self).map_err(|e| format!("Failed to serialize preset: {:?}", e))?;
+        fs::write(path, payload).map_err(|e| format!("Failed to write preset to {}: {:?}", path, e))
+    }
+
+    pub fn import(path: &str) -> Result<PidPreset, String> {
+        let payload = fs::read(path).map_err(|e| format!("Failed to read preset from {}: {:?}", path, e))?;
+        serde_json::from_slice(&payload).map_err(|e| format!("Failed to deserialize preset: {}", e))
+    }
+}
+
+/// Per machine settings that survive restarts, stored as JSON so fields can be added
+/// without breaking saved profiles: missing fields take their `new` value.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MachineProfile {
+    pub name        : String,
+    pub axes        : Vec<AxisInfo>,
+    pub pid_presets : Vec<PidPreset>,
+    pub pid_history : PidHistory,
+    /// Per axis, missing entries use `FollowingErrorLimits::new`.
+    pub following_error_limits : Vec<FollowingErrorLimits>,
+    pub macros      : Vec<MacroDef>,
+    /// Speeds the run time estimate and the job simulation assume for this machine.
+    pub motion_limits : MotionLimits,
+    /// Set when the file exists but couldn't be read, so it is never saved over.
+    #[serde(skip)]
+    unreadable      : bool,
+}
+
+impl Default for MachineProfile {
+    fn default() -> Self {
+        MachineProfile::new("default")
+    }
+}
+
+impl MachineProfile {
+    pub fn new(name: &str) -> Self {
+        MachineProfile{
+            name        : String::from(name),
+            axes        : AxisInfo::default_axes(),
+            pid_presets : Vec::new(),
+            pid_history : PidHistory::new(),
+            following_error_limits : Vec::new(),
+            macros      : Vec::new(),
+            motion_limits : MotionLimits::new(),
+            unreadable  : false,
+        }
+    }
+
+    /// Loads the named profile, or starts an empty one if it has never been saved.
+    /// A profile that exists but can't be read is left alone on disk.
+    pub fn load_or_new(name: &str) -> Self {
+        let exists = MachineProfile::path(name).map(|path| path.exists()).unwrap_or(false);
+        if !exists {
+            return MachineProfile::new(name);
+        }
+        match MachineProfile::load(name) {
+            Ok(profile) => profile,
+            Err(e) => {
+                log_error!("{}, changes won't be saved until it is fixed or removed", e);
+                let mut profile = MachineProfile::new(name);
+                profile.unreadable = true;
+                profile
+            },
+        }
+    }
+
+    pub fn load(name: &str) -> Result<MachineProfile, String> {
+        let path = MachineProfile::path(name)?;
+        let payload = fs::read(&path).map_err(|e| format!("Failed to read profile {:?}: {:?}", path, e))?;
+        MachineProfile::from_json(&payload).map_err(|e| format!("Failed to load profile {:?}: {}", path, e))
+    }
+
+    fn from_json(payload: &[u8]) -> Result<MachineProfile, String> {
+        let profile: MachineProfile = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+        AxisInfo::validate_axes(&profile.axes)?;
+        Ok(profile)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = MachineProfile::path(&self.name)?;
+        if self.unreadable {
+            return Err(format!("Not saving over profile {:?}, it couldn't be loaded", path));
+        }
+        fs::create_dir_all(PROFILE_DIR).map_err(|e| format!("Failed to create {}: {:?}", PROFILE_DIR, e))?;
+        let payload = serde_json::to_vec_pretty(self).map_err(|e| format!("Failed to serialize profile: {:?}", e))?;
+        fs::write(&path, payload).map_err(|e| format!("Failed to write profile {:?}: {:?}", path, e))
+    }
+
+    /// Profile names become file names, so only letters, digits, `-` and `_` are allowed.
+    pub fn check_name(name: &str) -> Result<(), String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid profile name {:?}, use letters, digits, - and _", name));
+        }
+        Ok(())
+    }
+
+    /// Adds the preset, replacing any existing one with the same name.
+    pub fn save_preset(&mut self, preset: PidPreset) {
+        match self.pid_presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => self.pid_presets.push(preset),
+        }
+    }
+
+    pub fn remove_preset(&mut self, name: &str) {
+        self.pid_presets.retain(|p| p.name != name);
+    }
+
+    /// Adds the macro, replacing any existing one with the same name.
+    pub fn save_macro(&mut self, definition: MacroDef) {
+        match self.macros.iter_mut().find(|m| m.name == definition.name) {
+            Some(existing) => *existing = definition,
+            None => self.macros.push(definition),
+        }
+    }
+
+    pub fn remove_macro(&mut self, name: &str) {
+        self.macros.retain(|m| m.name != name);
+    }
+
+    fn path(name: &str) -> Result<PathBuf, String> {
+        MachineProfile::check_name(name)?;
+        Ok(PathBuf::from(PROFILE_DIR).join(format!("{}.json", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_inside_the_profile_directory() {
+        assert!(MachineProfile::check_name("mill-2_a").is_ok());
+        for name in ["", "../x", "a/b", "a\\b", ".", "x.json"] {
+            assert!(MachineProfile::check_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn profiles_with_invalid_axes_are_refused() {
+        assert!(MachineProfile::from_json(br#"{"axes": []}"#).is_err());
+        assert!(MachineProfile::from_json(br#"{"axes": [{"name": "X", "kind": "ELinear", "min": 10, "max": 0}]}"#).is_err());
+        assert!(MachineProfile::from_json(br#"{"axes": [{"name": "X", "kind": "ELinear", "min": 0, "max": 10}]}"#).is_ok());
+    }
+
+    #[test]
+    fn missing_fields_take_defaults() {
+        let profile: MachineProfile = serde_json::from_str(r#"{"name": "old", "future_field": 1}"#).unwrap();
+        assert_eq!(profile.name, "old");
+        assert_eq!(profile.axes.len(), AxisInfo::default_axes().len());
+        assert!(profile.macros.is_empty());
+        assert_eq!(profile.motion_limits, MotionLimits::new());
+
+        let profile: MachineProfile = serde_json::from_str(r#"{"motion_limits": {"acceleration": 200}}"#).unwrap();
+        assert_eq!(profile.motion_limits, MotionLimits{ rapid_feed: MotionLimits::new().rapid_feed, acceleration: 200f32 });
+    }
+}
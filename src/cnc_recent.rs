@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use crate::log_error;
+
+const RECENT_PATH: &str = "./data/recent_connections.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecentConnection {
+    pub host    : String,
+    pub port    : u16,
+}
+
+impl RecentConnection {
+    /// `host:port`, with IPv6 addresses in brackets.
+    pub fn label(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Controllers connected to successfully, most recent first.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RecentConnections {
+    pub entries : Vec<RecentConnection>,
+    /// Set when the file exists but couldn't be read, so it is never saved over.
+    #[serde(skip)]
+    unreadable  : bool,
+}
+
+impl RecentConnections {
+    pub const MAX_ENTRIES: usize = 10;
+
+    /// Loads the list saved by a previous run, or starts empty. A file that can't be
+    /// read is left alone.
+    pub fn load() -> Self {
+        if !Path::new(RECENT_PATH).exists() {
+            return RecentConnections::default();
+        }
+        let result = fs::read(RECENT_PATH)
+            .map_err(|e| format!("Failed to read {}: {:?}", RECENT_PATH, e))
+            .and_then(|payload| serde_json::from_slice(&payload).map_err(|e| format!("Failed to deserialize {}: {}", RECENT_PATH, e)));
+        match result {
+            Ok(recent) => recent,
+            Err(e) => {
+                log_error!("{}, new connections won't be remembered until it is fixed or removed", e);
+                RecentConnections{ entries: Vec::new(), unreadable: true }
+            },
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if self.unreadable {
+            return Err(format!("Not saving over {}, it couldn't be loaded", RECENT_PATH));
+        }
+        if let Some(dir) = Path::new(RECENT_PATH).parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {:?}", dir, e))?;
+        }
+        let payload = serde_json::to_vec_pretty(self).map_err(|e| format!("Failed to serialize recent connections: {:?}", e))?;
+        fs::write(RECENT_PATH, payload).map_err(|e| format!("Failed to write {}: {:?}", RECENT_PATH, e))
+    }
+
+    /// Moves the connection to the front, dropping the oldest beyond `MAX_ENTRIES`.
+    pub fn add(&mut self, host: &str, port: u16) {
+        let connection = RecentConnection{ host: String::from(host), port };
+        self.entries.retain(|entry| *entry != connection);
+        self.entries.insert(0, connection);
+        self.entries.truncate(RecentConnections::MAX_ENTRIES);
+    }
+}
@@ -0,0 +1,222 @@
+use std::io::{self, prelude::*};
+use std::net::{Ipv4Addr, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cnc_connection::{answer_discovery, DiscoveredController, DISCOVERY_PORT};
+use crate::cnc_msg::{AlarmAxisDetail, AlarmReport, AxisConfig, AxisInfo, EAlarmCode, CncAxisStatus, CncStatus, ECncCtrlMessage, ECncStatusMessage, MachineInfo};
+use crate::{log_error, log_info, log_warn};
+
+/// Port the simulator accepts connections on.
+pub const SIM_PORT: u16 = 5555;
+const SIM_PROTOCOL_VERSION: u16 = 1;
+const SIM_NAME: &str = "Simulator";
+const SIM_FIRMWARE_VERSION: &str = "sim-0.1";
+/// Setpoint speed in machine units per second.
+const MAX_SPEED: f32 = 50f32;
+/// Time constant of the first order lag between setpoint and position, giving a following error.
+const LAG: f32 = 0.05f32;
+const STATUS_PERIOD: Duration = Duration::from_millis(20);
+
+/// Controller stand in for testing without hardware. Accepts one connection at a time on
+/// loopback and answers discovery probes.
+pub struct CncSimulator {
+    info        : MachineInfo,
+    configs     : Vec<AxisConfig>,
+    target      : Vec<f32>,
+    setpoint    : Vec<f32>,
+    position    : Vec<f32>,
+    speed       : Vec<f32>,
+    setpoint_speed : Vec<f32>,
+    /// Latched until the client resets it. Targets are ignored meanwhile.
+    alarm       : Option<AlarmReport>,
+}
+
+impl CncSimulator {
+    pub fn new(axes: Vec<AxisInfo>) -> Self {
+        let start: Vec<f32> = axes.iter().map(|axis| axis.min).collect();
+        CncSimulator{
+            configs     : vec![AxisConfig::new(); axes.len()],
+            target      : start.clone(),
+            setpoint    : start.clone(),
+            position    : start,
+            speed       : vec![0f32; axes.len()],
+            setpoint_speed : vec![0f32; axes.len()],
+            alarm       : None,
+            info        : MachineInfo{ protocol_version: SIM_PROTOCOL_VERSION, axes },
+        }
+    }
+
+    /// Starts the simulator threads on `port`. Discovery is skipped if its port is taken.
+    pub fn spawn(self, port: u16) -> Result<(), String> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map_err(|e| format!("Simulator can't listen on port {}: {}", port, e))?;
+        match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)) {
+            Ok(socket) => {
+                let controller = DiscoveredController{
+                    name            : String::from(SIM_NAME),
+                    firmware_version: String::from(SIM_FIRMWARE_VERSION),
+                    // Only loopback connections are accepted, whichever interface the probe came in on.
+                    ip              : Ipv4Addr::LOCALHOST.to_string(),
+                    port,
+                };
+                thread::spawn(move || loop {
+                    if let Err(e) = answer_discovery(&socket, &controller) {
+                        log_error!("{}", e);
+                    }
+                });
+            },
+            Err(e) => {
+                log_warn!("Simulator discovery disabled, can't bind port {}: {}", DISCOVERY_PORT, e);
+            },
+        }
+
+        let mut simulator = self;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        log_info!("Simulator accepted a connection");
+                        if let Err(e) = simulator.serve(stream) {
+                            log_info!("Simulator connection closed: {}", e);
+                        }
+                    },
+                    Err(e) => {
+                        log_error!("Simulator failed to accept: {}", e);
+                    },
+                }
+            }
+        });
+        log_info!("Simulator listening on 127.0.0.1:{}", port);
+        Ok(())
+    }
+
+    fn serve(&mut self, mut stream: TcpStream) -> Result<(), String> {
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(Duration::from_millis(5))).map_err(|e| e.to_string())?;
+        let mut buffer = [0u8; 512];
+        // A latched alarm is reported again to every new client.
+        let mut pending: Vec<ECncStatusMessage> = self.alarm.clone().map(ECncStatusMessage::EAlarm).into_iter().collect();
+        let mut last_step = Instant::now();
+        let mut last_status = Instant::now();
+        let cycle_start = Instant::now();
+        loop {
+            let mut replies = std::mem::take(&mut pending);
+            match stream.read(&mut buffer) {
+                Ok(0) => return Err(String::from("disconnected")),
+                Ok(len) => {
+                    let mut received = &buffer[..len];
+                    while !received.is_empty() {
+                        match ECncCtrlMessage::bin_deserialize(&mut received, self.info.axes.len()) {
+                            Ok(msg) => replies.extend(self.handle(msg)),
+                            Err(e) => {
+                                log_warn!("Simulator received an invalid message: {:?}", e);
+                                break;
+                            },
+                        }
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(e.to_string()),
+            }
+
+            let now = Instant::now();
+            self.step(now.duration_since(last_step).as_secs_f32());
+            last_step = now;
+            if now.duration_since(last_status) >= STATUS_PERIOD {
+                last_status = now;
+                let cycle_time = (cycle_start.elapsed().as_micros() % 1000) as i32 + 1000;
+                replies.push(ECncStatusMessage::EStatus(self.status(cycle_time)));
+            }
+
+            for reply in replies {
+                let payload = reply.bin_serialize().map_err(|e| format!("{:?}", e))?;
+                stream.write_all(&payload).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    fn handle(&mut self, msg: ECncCtrlMessage) -> Option<ECncStatusMessage> {
+        match msg {
+            ECncCtrlMessage::ETargetPosition(coords) => {
+                if self.alarm.is_some() {
+                    return None;
+                }
+                // A target beyond the travel runs into the limit switch.
+                let beyond: Vec<AlarmAxisDetail> = self.info.axes.iter().enumerate()
+                    .filter(|(axis, info)| coords.get(*axis) < info.min || coords.get(*axis) > info.max)
+                    .map(|(axis, _)| AlarmAxisDetail{ axis: axis as u8, value: coords.get(axis) })
+                    .collect();
+                if !beyond.is_empty() {
+                    self.target = self.setpoint.clone();
+                    let report = AlarmReport{ code: EAlarmCode::ELimitSwitch.code(), axes: beyond };
+                    self.alarm = Some(report.clone());
+                    return Some(ECncStatusMessage::EAlarm(report));
+                }
+                for axis in 0..self.info.axes.len() {
+                    self.target[axis] = coords.get(axis);
+                }
+                None
+            },
+            ECncCtrlMessage::EFeedHold => {
+                self.target = self.setpoint.clone();
+                None
+            },
+            ECncCtrlMessage::EResetAlarm => {
+                self.alarm = None;
+                None
+            },
+            ECncCtrlMessage::EPIDParams(params) => {
+                for (config, pid) in self.configs.iter_mut().zip(params) {
+                    config.pid = pid;
+                }
+                None
+            },
+            ECncCtrlMessage::EAxisConfig(configs) => {
+                self.configs = configs;
+                None
+            },
+            ECncCtrlMessage::ERequestPIDParams => {
+                Some(ECncStatusMessage::EPIDParams(self.configs.iter().map(|config| config.pid.clone()).collect()))
+            },
+            ECncCtrlMessage::ERequestAxisConfig => Some(ECncStatusMessage::EAxisConfig(self.configs.clone())),
+            ECncCtrlMessage::ERequestMachineInfo => Some(ECncStatusMessage::EMachineInfo(self.info.clone())),
+            ECncCtrlMessage::EPing(sequence) => Some(ECncStatusMessage::EPong(sequence)),
+            ECncCtrlMessage::EQuit => None,
+        }
+    }
+
+    /// Moves the setpoint towards the target at `MAX_SPEED` and lets the position follow it.
+    fn step(&mut self, dt: f32) {
+        if dt <= 0f32 {
+            return;
+        }
+        let follow = 1f32 - (-dt / LAG).exp();
+        for axis in 0..self.info.axes.len() {
+            let max_move = MAX_SPEED * dt;
+            let setpoint_move = (self.target[axis] - self.setpoint[axis]).clamp(-max_move, max_move);
+            self.setpoint[axis] += setpoint_move;
+            self.setpoint_speed[axis] = setpoint_move / dt;
+            let position_move = (self.setpoint[axis] - self.position[axis]) * follow;
+            self.position[axis] += position_move;
+            self.speed[axis] = position_move / dt;
+        }
+    }
+
+    fn status(&self, cycle_time: i32) -> CncStatus {
+        let axis_status = (0..self.info.axes.len()).map(|axis| {
+            let error = self.setpoint[axis] - self.position[axis];
+            let prop = self.configs[axis].pid.prop * error;
+            CncAxisStatus{
+                position        : self.position[axis],
+                speed           : self.speed[axis],
+                target_position : self.setpoint[axis],
+                target_speed    : self.setpoint_speed[axis],
+                pid_prop_control: prop,
+                pid_int_control : 0f32,
+                pid_der_control : 0f32,
+                duty            : prop.clamp(-100f32, 100f32) as i32,
+            }
+        }).collect();
+        CncStatus{ cycle_time, axis_status }
+    }
+}
@@ -0,0 +1,380 @@
+use crate::cnc_gcode::{GCodeLine, GCodeProgram};
+use crate::cnc_msg::AxisInfo;
+use crate::cnc_units::EUnits;
+
+/// Chord length used to break arcs into straight feed moves.
+const ARC_SEGMENT_LENGTH: f32 = 1.0f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EMoveKind {
+    ERapid,
+    EFeed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EMotionMode {
+    ERapid,
+    ELinear,
+    EArcCw,
+    EArcCcw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ESpindle {
+    EOff,
+    EClockwise,
+    ECounterClockwise,
+}
+
+/// Modal G-code state after executing a block.
+#[derive(Clone, Debug)]
+pub struct ModalState {
+    pub units       : EUnits,
+    pub absolute    : bool,
+    motion          : EMotionMode,
+    /// Feed rate in mm/min.
+    pub feed        : f32,
+    pub spindle     : ESpindle,
+    pub spindle_speed : f32,
+    /// Active work coordinate system, 54 to 59.
+    pub work_offset : u8,
+    pub tool        : u32,
+    /// Position in machine units (mm or degrees) per axis.
+    pub position    : Vec<f32>,
+}
+
+impl ModalState {
+    pub fn new(start_position: &[f32]) -> Self {
+        ModalState{
+            units       : EUnits::EMillimeters,
+            absolute    : true,
+            motion      : EMotionMode::ERapid,
+            feed        : 0f32,
+            spindle     : ESpindle::EOff,
+            spindle_speed : 0f32,
+            work_offset : 54,
+            tool        : 0,
+            position    : start_position.to_vec(),
+        }
+    }
+
+    pub fn motion_kind(&self) -> EMoveKind {
+        match self.motion {
+            EMotionMode::ERapid => EMoveKind::ERapid,
+            _ => EMoveKind::EFeed,
+        }
+    }
+
+    /// The state as G-code words, e.g. `G1 G21 G90 G54 F600 M3 S12000 T2`.
+    pub fn describe(&self) -> String {
+        let motion = match self.motion {
+            EMotionMode::ERapid => "G0",
+            EMotionMode::ELinear => "G1",
+            EMotionMode::EArcCw => "G2",
+            EMotionMode::EArcCcw => "G3",
+        };
+        let units = match self.units {
+            EUnits::EMillimeters => "G21",
+            EUnits::EInches => "G20",
+        };
+        let spindle = match self.spindle {
+            ESpindle::EOff => "M5",
+            ESpindle::EClockwise => "M3",
+            ESpindle::ECounterClockwise => "M4",
+        };
+        format!("{} {} {} G{} F{:.0} {} S{:.0} T{}", motion, units, if self.absolute { "G90" } else { "G91" }, self.work_offset,
+            self.units.to_units(crate::cnc_msg::EAxisKind::ELinear, self.feed), spindle, self.spindle_speed, self.tool)
+    }
+}
+
+/// Modal state in effect when the block at `block_index` starts, replaying the program
+/// from `start_position` like `Toolpath::build`, which has already rejected bad blocks.
+pub fn modal_state_before(program: &GCodeProgram, axes: &[AxisInfo], start_position: &[f32], block_index: usize) -> ModalState {
+    let mut state = ModalState::new(start_position);
+    for (index, line) in program.lines.iter().enumerate().take(block_index) {
+        let _ = execute_block(&mut state, line, index, axes);
+    }
+    state
+}
+
+#[derive(Clone, Debug)]
+pub struct ToolpathSegment {
+    pub kind        : EMoveKind,
+    pub start       : Vec<f32>,
+    pub end         : Vec<f32>,
+    /// Programmed feed in mm/min, zero for rapids.
+    pub feed        : f32,
+    pub line_number : usize,
+    /// Index of the block in `GCodeProgram::lines`.
+    pub block_index : usize,
+}
+
+impl ToolpathSegment {
+    /// Straight line length over all axes.
+    pub fn length(&self) -> f32 {
+        self.start.iter().zip(self.end.iter()).map(|(a, b)| (b - a) * (b - a)).sum::<f32>().sqrt()
+    }
+}
+
+/// Moves of a program, expanded to straight segments in machine units.
+pub struct Toolpath {
+    pub segments    : Vec<ToolpathSegment>,
+    pub tool_changes: usize,
+    /// Where the machine was when the program was loaded.
+    pub start_position : Vec<f32>,
+}
+
+impl Toolpath {
+    /// Fails on the first block this planner can't follow, naming its line.
+    pub fn build(program: &GCodeProgram, axes: &[AxisInfo], start_position: &[f32]) -> Result<Toolpath, String> {
+        let mut state = ModalState::new(start_position);
+        let mut toolpath = Toolpath{
+            segments    : Vec::new(),
+            tool_changes: 0,
+            start_position : start_position.to_vec(),
+        };
+        for (block_index, line) in program.lines.iter().enumerate() {
+            let tool_before = state.tool;
+            let segments = execute_block(&mut state, line, block_index, axes)
+                .map_err(|e| format!("{}:{}: {}", program.name, line.line_number, e))?;
+            if line.has_code('M', 6f32) || state.tool != tool_before {
+                toolpath.tool_changes += 1;
+            }
+            toolpath.segments.extend(segments);
+        }
+        Ok(toolpath)
+    }
+
+    /// Index of the first segment on `line_number` or after it.
+    pub fn first_segment_from_line(&self, line_number: usize) -> Option<usize> {
+        self.segments.iter().position(|segment| segment.line_number >= line_number)
+    }
+}
+
+/// Applies the modal words of one block to `state`, leaving its position alone.
+pub fn apply_modal_words(state: &mut ModalState, line: &GCodeLine) -> Result<(), String> {
+    for word in &line.words {
+        match word.letter {
+            'G' => {
+                let code = (word.value * 10f32).round() as i32;
+                match code {
+                    0 => state.motion = EMotionMode::ERapid,
+                    10 => state.motion = EMotionMode::ELinear,
+                    20 => state.motion = EMotionMode::EArcCw,
+                    30 => state.motion = EMotionMode::EArcCcw,
+                    200 => state.units = EUnits::EInches,
+                    210 => state.units = EUnits::EMillimeters,
+                    540 => state.work_offset = 54,
+                    // Offsets are set on the controller, which this planner can't read.
+                    550..=590 if code % 10 == 0 => return Err(format!("G{} is not supported, only G54 work offsets", code / 10)),
+                    900 => state.absolute = true,
+                    910 => state.absolute = false,
+                    _ => {},
+                }
+            },
+            'M' => {
+                match word.value.round() as i32 {
+                    3 => state.spindle = ESpindle::EClockwise,
+                    4 => state.spindle = ESpindle::ECounterClockwise,
+                    5 | 2 | 30 => state.spindle = ESpindle::EOff,
+                    _ => {},
+                }
+            },
+            'F' => state.feed = state.units.to_mm(crate::cnc_msg::EAxisKind::ELinear, word.value),
+            'S' => state.spindle_speed = word.value,
+            'T' => state.tool = word.value.round() as u32,
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Applies one block to `state`, returning the moves it makes.
+fn execute_block(state: &mut ModalState, line: &GCodeLine, block_index: usize, axes: &[AxisInfo]) -> Result<Vec<ToolpathSegment>, String> {
+    apply_modal_words(state, line)?;
+
+    let mut target = state.position.clone();
+    let mut has_motion = false;
+    for (axis, info) in axes.iter().enumerate() {
+        let letter = info.name.chars().next().unwrap_or(' ');
+        if let Some(value) = line.words.iter().find(|word| word.letter == letter).map(|word| word.value) {
+            let value = state.units.to_mm(info.kind, value);
+            if axis < target.len() {
+                target[axis] = if state.absolute { value } else { target[axis] + value };
+                has_motion = true;
+            }
+        }
+    }
+    if !has_motion {
+        return Ok(Vec::new());
+    }
+
+    let mut segments = Vec::new();
+    let start = state.position.clone();
+    match state.motion {
+        EMotionMode::ERapid | EMotionMode::ELinear => {
+            segments.push(ToolpathSegment{
+                kind        : state.motion_kind(),
+                start,
+                end         : target.clone(),
+                feed        : if state.motion == EMotionMode::ERapid { 0f32 } else { state.feed },
+                line_number : line.line_number,
+                block_index,
+            });
+        },
+        EMotionMode::EArcCw | EMotionMode::EArcCcw => {
+            let to_mm = |letter: char| line.words.iter().find(|word| word.letter == letter)
+                .map(|word| state.units.to_mm(crate::cnc_msg::EAxisKind::ELinear, word.value)).unwrap_or(0f32);
+            let has_word = |letter: char| line.words.iter().any(|word| word.letter == letter);
+            let clockwise = state.motion == EMotionMode::EArcCw;
+            let center = if has_word('R') {
+                arc_center_from_radius(&start, &target, to_mm('R'), clockwise)?
+            } else if has_word('I') || has_word('J') {
+                (start[0] + to_mm('I'), start.get(1).copied().unwrap_or(0f32) + to_mm('J'))
+            } else {
+                return Err(String::from("Arc without I/J or R"));
+            };
+            let points = arc_points(&start, &target, center, clockwise);
+            let mut previous = start;
+            for point in points {
+                segments.push(ToolpathSegment{
+                    kind        : EMoveKind::EFeed,
+                    start       : previous,
+                    end         : point.clone(),
+                    feed        : state.feed,
+                    line_number : line.line_number,
+                    block_index,
+                });
+                previous = point;
+            }
+        },
+    }
+    state.position = target;
+    Ok(segments)
+}
+
+/// Centre of an XY arc given by its radius. A negative `radius` selects the arc
+/// longer than a half circle, as in `G2 X10 R-5`.
+fn arc_center_from_radius(start: &[f32], end: &[f32], radius: f32, clockwise: bool) -> Result<(f32, f32), String> {
+    if start.len() < 2 {
+        return Err(String::from("Arc needs X and Y axes"));
+    }
+    let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+    let chord = (dx * dx + dy * dy).sqrt();
+    if chord <= f32::EPSILON {
+        return Err(String::from("Arc with R needs an end point away from the start"));
+    }
+    let half = chord / 2f32;
+    // Allows for the rounding of end points given to fewer decimals than the radius.
+    let tolerance = 1e-3f32.max(radius.abs() * 1e-5f32);
+    if half > radius.abs() + tolerance {
+        return Err(format!("Arc radius {} is too small for an end point {:.3} away", radius.abs(), chord));
+    }
+    let offset = (radius * radius - half * half).max(0f32).sqrt();
+    // Clockwise short arcs turn around a centre on the right of the chord.
+    let side = if clockwise == (radius > 0f32) { 1f32 } else { -1f32 };
+    Ok((start[0] + dx / 2f32 + side * offset * dy / chord, start[1] + dy / 2f32 - side * offset * dx / chord))
+}
+
+/// Intermediate points of an XY arc around `center`, ending exactly at `end`. Other
+/// axes are interpolated linearly, giving helices for Z.
+fn arc_points(start: &[f32], end: &[f32], center: (f32, f32), clockwise: bool) -> Vec<Vec<f32>> {
+    if start.len() < 2 {
+        return vec![end.to_vec()];
+    }
+    let radius = ((start[0] - center.0).powi(2) + (start[1] - center.1).powi(2)).sqrt();
+    let start_angle = (start[1] - center.1).atan2(start[0] - center.0);
+    let end_angle = (end[1] - center.1).atan2(end[0] - center.0);
+    let mut sweep = end_angle - start_angle;
+    if clockwise && sweep >= 0f32 {
+        sweep -= 2f32 * std::f32::consts::PI;
+    } else if !clockwise && sweep <= 0f32 {
+        sweep += 2f32 * std::f32::consts::PI;
+    }
+
+    let steps = ((radius * sweep.abs()) / ARC_SEGMENT_LENGTH).ceil().max(1f32) as usize;
+    (1..=steps).map(|step| {
+        if step == steps {
+            return end.to_vec();
+        }
+        let t = step as f32 / steps as f32;
+        let angle = start_angle + sweep * t;
+        let mut point: Vec<f32> = start.iter().zip(end.iter()).map(|(a, b)| a + (b - a) * t).collect();
+        point[0] = center.0 + radius * angle.cos();
+        point[1] = center.1 + radius * angle.sin();
+        point
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn build(text: &str) -> Result<Toolpath, String> {
+        let program = GCodeProgram::parse("test", text)?;
+        Toolpath::build(&program, &AxisInfo::default_axes(), &[0f32, 0f32, 0f32])
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn arcs_pass_through_the_expected_points() {
+        // Program, a point the arc must pass near, and its end.
+        let cases = [
+            ("G2 X10 Y0 I5 J0", [5f32, 5f32], [10f32, 0f32]),
+            ("G3 X10 Y0 I5 J0", [5f32, -5f32], [10f32, 0f32]),
+            ("G2 X10 Y0 R5", [5f32, 5f32], [10f32, 0f32]),
+            ("G3 X10 Y0 R5", [5f32, -5f32], [10f32, 0f32]),
+            // Quarter circle around (10, 0), and the three quarters around (0, 10).
+            ("G2 X10 Y10 R10", [10f32 - 10f32 * FRAC_1_SQRT_2, 10f32 * FRAC_1_SQRT_2], [10f32, 10f32]),
+            ("G2 X10 Y10 R-10", [-10f32, 10f32], [10f32, 10f32]),
+            ("G20 G2 X1 Y0 R0.5", [12.7f32, 12.7f32], [25.4f32, 0f32]),
+        ];
+        for (text, through, end) in cases {
+            let toolpath = build(text).unwrap();
+            let last = toolpath.segments.last().unwrap();
+            assert!(close(&last.end, &end), "{}: ends at {:?}", text, last.end);
+            let nearest = toolpath.segments.iter()
+                .map(|segment| (segment.end[0] - through[0]).hypot(segment.end[1] - through[1]))
+                .fold(f32::MAX, f32::min);
+            assert!(nearest < 0.6f32, "{}: misses {:?} by {}", text, through, nearest);
+            assert!(toolpath.segments.iter().all(|segment| segment.length() <= ARC_SEGMENT_LENGTH + 1e-3), "{}", text);
+        }
+    }
+
+    #[test]
+    fn helical_arcs_interpolate_z() {
+        let toolpath = build("G3 X0 Y0 Z-3 I5 J0").unwrap();
+        let z: Vec<f32> = toolpath.segments.iter().map(|segment| segment.end[2]).collect();
+        assert!(z.windows(2).all(|pair| pair[1] < pair[0]));
+        assert_eq!(*z.last().unwrap(), -3f32);
+    }
+
+    #[test]
+    fn unsupported_blocks_fail_with_their_line() {
+        let cases = [
+            ("G0 X0\nG2 X30 Y0 R5", "test:2: Arc radius"),
+            ("G2 X10 Y0", "test:1: Arc without"),
+            ("G2 X0 Y0 R5", "test:1: Arc with R"),
+            ("G0 X0\nG0 X1\nG55 X2", "test:3: G55"),
+        ];
+        for (text, prefix) in cases {
+            let error = build(text).err().unwrap();
+            assert!(error.starts_with(prefix), "{}: {}", text, error);
+        }
+    }
+
+    #[test]
+    fn modal_state_follows_the_program() {
+        let toolpath = build("G21 G91\nG0 X5\nG1 X5 F300\nG90 G0 Z2").unwrap();
+        let ends: Vec<f32> = toolpath.segments.iter().map(|segment| segment.end[0]).collect();
+        assert_eq!(ends, vec![5f32, 10f32, 10f32]);
+        assert_eq!(toolpath.segments[1].kind, EMoveKind::EFeed);
+        assert_eq!(toolpath.segments[1].feed, 300f32);
+        assert_eq!(toolpath.segments[2].kind, EMoveKind::ERapid);
+        assert_eq!(toolpath.first_segment_from_line(3), Some(1));
+    }
+}
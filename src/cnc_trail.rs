@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug)]
+pub struct TrailPoint {
+    pub position        : Vec<f32>,
+    /// Distance between the controller's setpoint and the actual position, if reported.
+    pub following_error : Option<f32>,
+}
+
+/// Bounded history of reported machine positions, oldest first.
+pub struct PositionTrail {
+    points  : VecDeque<TrailPoint>,
+}
+
+impl Default for PositionTrail {
+    fn default() -> Self {
+        PositionTrail::new()
+    }
+}
+
+impl PositionTrail {
+    pub const CAPACITY: usize = 5000;
+    /// Moves shorter than this are merged into the previous point to save capacity.
+    pub const MIN_DISTANCE: f32 = 0.01f32;
+
+    pub fn new() -> Self {
+        PositionTrail{
+            points  : VecDeque::with_capacity(PositionTrail::CAPACITY),
+        }
+    }
+
+    pub fn record(&mut self, position: &[f32], following_error: Option<f32>) {
+        if let Some(last) = self.points.back_mut() {
+            let distance = last.position.iter().zip(position.iter()).map(|(a, b)| (b - a) * (b - a)).sum::<f32>().sqrt();
+            if distance < PositionTrail::MIN_DISTANCE && last.position.len() == position.len() {
+                // Keep the worst error seen at this spot so it stays visible.
+                last.following_error = match (last.following_error, following_error) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+                return;
+            }
+        }
+        if self.points.len() == PositionTrail::CAPACITY {
+            self.points.pop_front();
+        }
+        self.points.push_back(TrailPoint{ position: position.to_vec(), following_error });
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn points(&self) -> impl Iterator<Item = &TrailPoint> {
+        self.points.iter()
+    }
+}
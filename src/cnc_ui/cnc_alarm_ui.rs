@@ -0,0 +1,85 @@
+use raylib::prelude::*;
+
+use cnc_desktop::cnc_alarm::Alarm;
+use cnc_desktop::cnc_ctrl::CncCtrl;
+use cnc_desktop::cnc_log::format_timestamp;
+use cnc_desktop::cnc_machine_state::ECommand;
+use cnc_desktop::log_error;
+
+use super::cnc_config_ui::gui_button_enabled;
+use super::cnc_layout::{ESize, Layout};
+
+/// Alarms listed in the banner before the rest are summed up.
+const BANNER_LINES: usize = 3;
+
+/// Height of the banner for the active alarms, zero without any.
+pub fn alarm_banner_height(cnc: &CncCtrl) -> f32 {
+    match cnc.alarms.active.len() {
+        0 => 0f32,
+        count => 20f32 + 30f32 * count.min(BANNER_LINES) as f32,
+    }
+}
+
+/// Red banner above every tab while alarms are latched, with the acknowledge and reset flow.
+pub fn draw_alarm_banner(d: &mut RaylibDrawHandle, font: &Font, layout: &Layout, area: Rectangle, cnc: &mut CncCtrl) {
+    if !cnc.alarms.is_active() || area.height <= 0f32 {
+        return;
+    }
+    let acknowledged = cnc.alarms.all_acknowledged();
+    d.draw_rectangle_rec(area, if acknowledged { Color::MAROON } else { Color::RED });
+
+    let inner = layout.inset(area, 10f32);
+    let columns = layout.columns(inner, &[ESize::EWeight(1f32), ESize::EFixed(180f32), ESize::EFixed(140f32)], 10f32);
+    let font_size = layout.px(22f32);
+    let line_height = layout.px(30f32);
+    let active = &cnc.alarms.active;
+    for (line, alarm) in active.iter().take(BANNER_LINES).enumerate() {
+        let mut text = alarm.describe(|axis| cnc.axis_name(axis));
+        if line + 1 == BANNER_LINES && active.len() > BANNER_LINES {
+            text.push_str(&format!("  +{} more", active.len() - BANNER_LINES));
+        }
+        d.draw_text_ex(font, text.as_str(), Vector2::new(columns[0].x, columns[0].y + line as f32 * line_height), font_size, 0f32, Color::WHITE);
+    }
+
+    let button_height = layout.px(40f32).min(columns[1].height);
+    let rect_ack = Rectangle::new(columns[1].x, columns[1].y, columns[1].width, button_height);
+    let rect_reset = Rectangle::new(columns[2].x, columns[2].y, columns[2].width, button_height);
+    if gui_button_enabled(d, rect_ack, rstr!("ACKNOWLEDGE"), !acknowledged) {
+        cnc.acknowledge_alarms();
+    }
+    if gui_button_enabled(d, rect_reset, rstr!("RESET"), acknowledged && cnc.allows(ECommand::EReset)) {
+        if let Err(e) = cnc.reset() {
+            log_error!("{}", e);
+        }
+    }
+}
+
+fn history_status(alarm: &Alarm) -> (&'static str, Color) {
+    match alarm.cleared_at {
+        Some(_) => ("cleared", Color::DARKGRAY),
+        None if alarm.acknowledged => ("acknowledged", Color::MAROON),
+        None => ("active", Color::RED),
+    }
+}
+
+/// Past alarms, most recent first.
+pub fn draw_alarm_history(d: &mut RaylibDrawHandle, font: &Font, area: Rectangle, font_size: f32, cnc: &CncCtrl) {
+    d.draw_text_ex(font, "Alarm history", Vector2::new(area.x, area.y), font_size * 1.2f32, 0f32, Color::BLACK);
+    let entry_height = font_size * 2.6f32;
+    let mut y = area.y + font_size * 2f32;
+    if cnc.alarms.history.is_empty() {
+        d.draw_text_ex(font, "No alarms", Vector2::new(area.x, y), font_size, 0f32, Color::GRAY);
+        return;
+    }
+    let mut d = d.begin_scissor_mode(area.x as i32, area.y as i32, area.width as i32, area.height as i32);
+    for alarm in cnc.alarms.history.iter().rev() {
+        if y + entry_height > area.y + area.height {
+            break;
+        }
+        let (status, color) = history_status(alarm);
+        let when = format!("{} {}", format_timestamp(alarm.raised_at), status);
+        d.draw_text_ex(font, when.as_str(), Vector2::new(area.x, y), font_size, 0f32, color);
+        d.draw_text_ex(font, alarm.describe(|axis| cnc.axis_name(axis)).as_str(), Vector2::new(area.x, y + font_size * 1.1f32), font_size, 0f32, Color::BLACK);
+        y += entry_height;
+    }
+}
@@ -1,8 +1,11 @@
-use std::{str::FromStr, fmt::Debug};
+use std::{str::FromStr, fmt::Debug, ffi::CString};
 
 use raylib::prelude::*;
 
-use crate::{cnc_ctrl::CncCtrl, cnc_msg::PIDParams};
+use cnc_desktop::{cnc_ctrl::CncCtrl, cnc_msg::{AxisConfig, AxisInfo, PIDParams, AXIS_CONFIG_FIELDS}, cnc_pid_history::EPidVerifyState};
+
+use super::cnc_layout::{EAnchor, ESize, Layout};
+use cnc_desktop::{log_debug, log_error, log_info, log_trace, log_warn};
 
 pub struct ValueInput<T: Default + ToString + FromStr + Copy + Debug > 
     where T: FromStr, <T as std::str::FromStr>::Err : std::fmt::Debug
@@ -19,33 +22,33 @@ impl<T: Default + ToString + FromStr + Copy + Debug> ValueInput<T>
     pub fn new(x: f32, y: f32, w: f32, h: f32, initial_value: T) -> Self {
         let text = initial_value.to_string();
         let mut buffer = text.as_bytes().to_vec();
-        buffer.append(&mut Vec::from(['\0' as u8]));
+        buffer.append(&mut Vec::from([b'\0']));
         ValueInput{
             rect        : Rectangle::new(x, y, w, h),
             value       : initial_value,
-            buffer      : buffer,
+            buffer,
             edit_mode   : false,
         }
     }
     pub fn update(&mut self, d: &mut RaylibDrawHandle) {
         
         let mut buffer = self.buffer.clone();
-        buffer.append(&mut Vec::from(['\0' as u8]));
+        buffer.append(&mut Vec::from([b'\0']));
         if d.gui_text_box(self.rect, buffer.as_mut_slice(), self.edit_mode) {
-            if let Ok(text_input) = String::from_utf8( buffer.clone().into() ) {
-                println!("Converting |{:?}|", text_input.trim_matches('\0').trim());
+            if let Ok(text_input) = String::from_utf8( buffer.clone() ) {
+                log_trace!("Converting |{:?}|", text_input.trim_matches('\0').trim());
                 match T::from_str(text_input.trim().trim_matches('\0').trim()) {
                     Ok(value) => {
                         self.value = value;
-                        println!("Value assigned {:?}", value);
+                        log_debug!("Value assigned {:?}", value);
                     },
                     Err(e) => {
-                        println!("Error converting {:?}", e);
-                        println!("Value reset {:?}", self.value);
+                        log_warn!("Error converting {:?}", e);
+                        log_debug!("Value reset {:?}", self.value);
                     }
                 }
                 self.buffer = self.value.to_string().as_bytes().to_vec();
-                println!("Text assigned {}", text_input);
+                log_trace!("Text assigned {}", text_input);
             }
         } else {
             self.buffer = buffer;
@@ -63,57 +66,195 @@ impl<T: Default + ToString + FromStr + Copy + Debug> ValueInput<T>
             }
         }
     }
+
+    pub fn set_value(&mut self, value: T) {
+        self.value = value;
+        self.buffer = self.value.to_string().as_bytes().to_vec();
+    }
+}
+
+/// Free text input backed by a fixed size, nul terminated buffer.
+pub struct TextInput {
+    pub rect        : Rectangle,
+    buffer          : Vec<u8>,
+    pub edit_mode   : bool,
+}
+
+impl TextInput {
+    pub fn new(x: f32, y: f32, w: f32, h: f32, initial_text: &str, capacity: usize) -> Self {
+        let mut input = TextInput{
+            rect        : Rectangle::new(x, y, w, h),
+            buffer      : vec![0u8; capacity + 1],
+            edit_mode   : false,
+        };
+        input.set_text(initial_text);
+        input
+    }
+
+    /// Returns true when the user confirms the text with enter.
+    pub fn update(&mut self, d: &mut RaylibDrawHandle) -> bool {
+        let mut submitted = false;
+        if d.gui_text_box(self.rect, self.buffer.as_mut_slice(), self.edit_mode) {
+            submitted = self.edit_mode;
+            self.edit_mode = !self.edit_mode;
+        }
+
+        // for some reason this needs to come after gui_text_box
+        if d.is_mouse_button_pressed(MouseButton::MOUSE_LEFT_BUTTON) {
+            let mouse_pos = d.get_mouse_position();
+            self.edit_mode = self.rect.check_collision_point_rec(mouse_pos);
+        }
+        submitted
+    }
+
+    pub fn text(&self) -> String {
+        let len = self.buffer.iter().position(|&b| b == 0).unwrap_or(self.buffer.len());
+        String::from_utf8_lossy(&self.buffer[..len]).trim().to_string()
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        let capacity = self.buffer.len() - 1;
+        for b in self.buffer.iter_mut() {
+            *b = 0;
+        }
+        for (i, b) in text.bytes().take(capacity).enumerate() {
+            self.buffer[i] = b;
+        }
+    }
+}
+
+/// Button that is drawn greyed out and ignores clicks unless `enabled`.
+pub fn gui_button_enabled(d: &mut RaylibDrawHandle, rect: Rectangle, text: &std::ffi::CStr, enabled: bool) -> bool {
+    if enabled {
+        return d.gui_button(rect, Some(text));
+    }
+    d.gui_set_state(GuiControlState::GUI_STATE_DISABLED);
+    d.gui_button(rect, Some(text));
+    d.gui_set_state(GuiControlState::GUI_STATE_NORMAL);
+    false
+}
+
+/// Selectable list of lines drawn with a raygui list view.
+pub struct ListSelect {
+    pub rect        : Rectangle,
+    scroll_index    : i32,
+    pub active      : i32,
+}
+
+impl ListSelect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        ListSelect{
+            rect        : Rectangle::new(x, y, w, h),
+            scroll_index: 0,
+            active      : -1,
+        }
+    }
+
+    pub fn update(&mut self, d: &mut RaylibDrawHandle, items: &[String]) -> Option<usize> {
+        let joined: Vec<String> = items.iter().map(|item| item.replace(';', ",")).collect();
+        let text = CString::new(joined.join(";")).unwrap_or_default();
+        let text = if items.is_empty() { None } else { Some(text.as_c_str()) };
+        self.active = d.gui_list_view(self.rect, text, &mut self.scroll_index, self.active);
+        if self.active >= 0 && (self.active as usize) < items.len() {
+            Some(self.active as usize)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct  CncAxisConfigUi {
     pub rect_bg: Rectangle,
     pub axis: u8,
+    pub name: String,
     pub current_params: PIDParams,
     pub new_params: PIDParams,
+    pub verify_state: EPidVerifyState,
     pub rect_rows : [Rectangle; 4],
     pub rect_columns : [Rectangle; 2],
     pub inputs : [ValueInput<f32>; 3],
+    pub current_config: AxisConfig,
+    pub new_config: AxisConfig,
+    /// The inputs have been filled from a config reported by the controller.
+    pub config_seeded: bool,
+    pub rect_ext: Rectangle,
+    pub ext_inputs: Vec<ValueInput<f32>>,
+    pub rect_invert: Rectangle,
+    font_size: f32,
 }
 
 impl CncAxisConfigUi {
-    pub fn new(axis: u8, x: f32, y: f32, w: f32, h: f32) -> Self {
-        let row_height = h * 0.2f32;
-        let col_width = w * 0.5f32;
-        let row_spacing = 0f32;
-        let col_spacing = 0f32;
-        
-        let title_row = Rectangle::new(x, y + row_height * 1f32 + row_spacing * 1f32, col_width * 2f32 + col_spacing * 1f32, row_height);
-        let p_row = Rectangle::new(x, y + row_height * 2f32 + row_spacing * 2f32, col_width * 2f32 + col_spacing * 1f32, row_height);
-        let i_row = Rectangle::new(x, y + row_height * 3f32 + row_spacing * 3f32, col_width * 2f32 + col_spacing * 1f32, row_height);
-        let d_row = Rectangle::new(x, y + row_height * 4f32 + row_spacing * 4f32, col_width * 2f32 + col_spacing * 1f32, row_height);
-        
-        let curr_col = Rectangle::new(x + col_width * 0f32 + col_spacing * 0f32, y+ row_height * 1f32 + row_spacing * 1f32, col_width, row_height * 4f32 + row_spacing * 3f32);
-        let new_col = Rectangle::new(x + col_width * 1f32 + col_spacing * 1f32, y+ row_height * 1f32 + row_spacing * 1f32, col_width, row_height * 4f32 + row_spacing * 3f32);
-        
-        let p_input = ValueInput::new(x + col_width * 1f32 + col_spacing * 1f32, y + row_height * 2f32 + row_spacing * 2f32, col_width, row_height, 0.0f32);
-        let i_input = ValueInput::new(x + col_width * 1f32 + col_spacing * 1f32, y + row_height * 3f32 + row_spacing * 3f32, col_width, row_height, 0.0f32);
-        let d_input = ValueInput::new(x + col_width * 1f32 + col_spacing * 1f32, y + row_height * 4f32 + row_spacing * 4f32, col_width, row_height, 0.0f32);
+    pub fn new(axis: u8, name: &str) -> Self {
+        let new_config = AxisConfig::new();
+        let ext_inputs = (0..AXIS_CONFIG_FIELDS.len()).map(|i| {
+            ValueInput::new(0f32, 0f32, 0f32, 0f32, new_config.get_field(i))
+        }).collect();
         
         CncAxisConfigUi {
-            rect_bg: Rectangle::new(x, y, w, h),
+            rect_bg: Rectangle::default(),
             axis,
+            name: format!("{} Axis", name),
             current_params: PIDParams::new(),
             new_params: PIDParams::new(),
-            rect_rows: [title_row, p_row, i_row, d_row],
-            rect_columns: [curr_col, new_col],
-            inputs: [p_input, i_input, d_input],
+            verify_state: EPidVerifyState::EUnknown,
+            rect_rows: [Rectangle::default(); 4],
+            rect_columns: [Rectangle::default(); 2],
+            inputs: [ValueInput::new(0f32, 0f32, 0f32, 0f32, 0.0f32), ValueInput::new(0f32, 0f32, 0f32, 0f32, 0.0f32), ValueInput::new(0f32, 0f32, 0f32, 0f32, 0.0f32)],
+            current_config: AxisConfig::new(),
+            new_config,
+            config_seeded: false,
+            rect_ext: Rectangle::default(),
+            ext_inputs,
+            rect_invert: Rectangle::default(),
+            font_size: 18f32,
         }
     }
+
+    /// `rect` holds the title and P/I/D rows, `rect_ext` the extended parameters.
+    pub fn set_rects(&mut self, layout: &Layout, rect: Rectangle, rect_ext: Rectangle) {
+        self.rect_bg = rect;
+        let rows = layout.rows(rect, &[ESize::EWeight(1f32); 5], 0f32);
+        for (row, rect_row) in self.rect_rows.iter_mut().zip(rows[1..].iter()) {
+            *row = *rect_row;
+        }
+        let body = Rectangle::new(rect.x, rows[1].y, rect.width, rect.y + rect.height - rows[1].y);
+        let columns = layout.columns(body, &[ESize::EWeight(1f32), ESize::EWeight(1f32)], 0f32);
+        self.rect_columns = [columns[0], columns[1]];
+        for (input, row) in self.inputs.iter_mut().zip(rows[2..].iter()) {
+            input.rect = Rectangle::new(columns[1].x, row.y, columns[1].width, row.height);
+        }
+
+        self.rect_ext = rect_ext;
+        let ext_rows = layout.rows(rect_ext, &[ESize::EWeight(1f32); AXIS_CONFIG_FIELDS.len() + 1], 0f32);
+        for (input, row) in self.ext_inputs.iter_mut().zip(ext_rows.iter()) {
+            input.rect = Rectangle::new(row.x + row.width * 0.65f32, row.y, row.width * 0.35f32, row.height - layout.px(4f32));
+        }
+        let invert_row = ext_rows[AXIS_CONFIG_FIELDS.len()];
+        self.rect_invert = Rectangle::new(invert_row.x + layout.px(4f32), invert_row.y + layout.px(4f32), layout.px(20f32), layout.px(20f32));
+        self.font_size = layout.px(18f32);
+    }
     
-    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
-        let (bg_color, axis_name) = match self.axis {
-            0 => (Color::RED,   "X Axis"),
-            1 => (Color::DARKGREEN, "Y Axis"),
-            2 => (Color::BLUE,  "Z Axis"),
-            _ => (Color::GRAY,  "Unknown Axis"),
-        };
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, _cnc: &mut CncCtrl) {
+        let bg_color = axis_color(self.axis as usize);
         d.draw_rectangle_rec(self.rect_bg, bg_color);
-        d.draw_text_rec(font, axis_name, self.rect_bg, self.rect_bg.height as f32 * 0.2f32, 0f32, false, Color::WHITE);
+        d.draw_text_rec(font, self.name.as_str(), self.rect_bg, self.rect_bg.height * 0.2f32, 0f32, false, Color::WHITE);
+
+        let (verify_color, verify_text) = match self.verify_state {
+            EPidVerifyState::EUnknown => (Color::LIGHTGRAY, ""),
+            EPidVerifyState::EPending => (Color::ORANGE, "PENDING"),
+            EPidVerifyState::EApplied => (Color::LIME, "APPLIED"),
+            EPidVerifyState::EMismatch => (Color::MAROON, "MISMATCH"),
+            EPidVerifyState::ENoReply => (Color::MAROON, "NO REPLY"),
+        };
+        if !verify_text.is_empty() {
+            let verify_font_size = self.rect_bg.height * 0.1f32;
+            let verify_size = measure_text_ex(font, verify_text, verify_font_size, 0f32);
+            let verify_rect = Rectangle::new(self.rect_bg.x + self.rect_bg.width - verify_size.x - verify_font_size,
+                self.rect_bg.y + verify_font_size * 0.25f32, verify_size.x + verify_font_size * 0.5f32, verify_font_size * 1.5f32);
+            d.draw_rectangle_rec(verify_rect, verify_color);
+            d.draw_text_ex(font, verify_text, Vector2::new(verify_rect.x + verify_font_size * 0.25f32, verify_rect.y + verify_font_size * 0.25f32),
+                verify_font_size, 0f32, Color::WHITE);
+        }
         
         d.draw_rectangle_rec(self.rect_rows[0], Color::LIGHTGRAY);
         d.draw_rectangle_rec(self.rect_rows[1], Color::RAYWHITE);
@@ -131,51 +272,370 @@ impl CncAxisConfigUi {
         self.new_params.inte = self.inputs[1].value;
         self.new_params.deri = self.inputs[2].value;
         
-        d.draw_text_rec(font, "CURRENT", self.rect_rows[0], self.rect_rows[0].height as f32 * 0.5f32, 0f32, false, Color::DARKGRAY);
-        d.draw_text_rec(font, "NEW CONFIG", self.rect_columns[1], self.rect_rows[0].height as f32 * 0.5f32, 0f32, false, Color::DARKGRAY);
+        d.draw_text_rec(font, "CURRENT", self.rect_rows[0], self.rect_rows[0].height * 0.5f32, 0f32, false, Color::DARKGRAY);
+        d.draw_text_rec(font, "NEW CONFIG", self.rect_columns[1], self.rect_rows[0].height * 0.5f32, 0f32, false, Color::DARKGRAY);
         
-        d.draw_text_rec(font, self.current_params.prop.to_string().as_str(), self.rect_rows[1], self.rect_rows[0].height as f32 * 0.5f32, 0f32, false, Color::DARKGRAY);
-        d.draw_text_rec(font, self.current_params.deri.to_string().as_str(), self.rect_rows[2], self.rect_rows[0].height as f32 * 0.5f32, 0f32, false, Color::DARKGRAY);
-        d.draw_text_rec(font, self.current_params.inte.to_string().as_str(), self.rect_rows[3], self.rect_rows[0].height as f32 * 0.5f32, 0f32, false, Color::DARKGRAY);
+        d.draw_text_rec(font, self.current_params.prop.to_string().as_str(), self.rect_rows[1], self.rect_rows[0].height * 0.5f32, 0f32, false, Color::DARKGRAY);
+        d.draw_text_rec(font, self.current_params.deri.to_string().as_str(), self.rect_rows[2], self.rect_rows[0].height * 0.5f32, 0f32, false, Color::DARKGRAY);
+        d.draw_text_rec(font, self.current_params.inte.to_string().as_str(), self.rect_rows[3], self.rect_rows[0].height * 0.5f32, 0f32, false, Color::DARKGRAY);
+    }
+
+    /// Draws the parameters beyond P/I/D, showing the controller's value next to the new one.
+    pub fn draw_extended(&mut self, d: &mut RaylibDrawHandle, font: &Font, supported: bool) {
+        let font_size = self.font_size;
+        let row_height = self.rect_ext.height / (AXIS_CONFIG_FIELDS.len() + 1) as f32;
+        d.draw_rectangle_rec(self.rect_ext, Color::RAYWHITE);
+        d.draw_rectangle_lines_ex(self.rect_ext, 1, Color::DARKGRAY);
+
+        for (i, (name, _, _)) in AXIS_CONFIG_FIELDS.iter().enumerate() {
+            let row_y = self.rect_ext.y + row_height * i as f32;
+            d.draw_text_ex(font, name, Vector2::new(self.rect_ext.x + font_size * 0.2f32, row_y + font_size * 0.3f32), font_size, 0f32, Color::DARKGRAY);
+            if supported {
+                d.draw_text_ex(font, format!("{}", self.current_config.get_field(i)).as_str(),
+                    Vector2::new(self.rect_ext.x + self.rect_ext.width * 0.4f32, row_y + font_size * 0.3f32), font_size, 0f32, Color::GRAY);
+            }
+
+            self.ext_inputs[i].update(d);
+            self.new_config.set_field(i, self.ext_inputs[i].value);
+            if !self.new_config.field_in_range(i) {
+                d.draw_rectangle_lines_ex(self.ext_inputs[i].rect, 2, Color::RED);
+            }
+        }
+
+        self.new_config.invert_direction = d.gui_check_box(self.rect_invert, Some(rstr!("INVERT DIR")), self.new_config.invert_direction);
+        if supported && self.current_config.invert_direction {
+            d.draw_text_ex(font, "(inverted)", Vector2::new(self.rect_ext.x + self.rect_ext.width * 0.65f32, self.rect_invert.y), font_size, 0f32, Color::GRAY);
+        }
+    }
+
+    pub fn set_new_params(&mut self, params: &PIDParams) {
+        self.inputs[0].set_value(params.prop);
+        self.inputs[1].set_value(params.inte);
+        self.inputs[2].set_value(params.deri);
+        self.new_params = params.clone();
+    }
+
+    /// Starts editing from `config`, so fields left alone are sent back unchanged.
+    pub fn set_new_config(&mut self, config: &AxisConfig) {
+        for (i, input) in self.ext_inputs.iter_mut().enumerate() {
+            input.set_value(config.get_field(i));
+        }
+        self.set_new_params(&config.pid);
+        self.new_config = config.clone();
+    }
+}
+
+/// History of sent/received PID sets and the named presets of the active profile.
+pub struct CncPidHistoryUi {
+    pub rect_profile_label  : Rectangle,
+    pub profile_input       : TextInput,
+    pub rect_btn_load       : Rectangle,
+    pub note_input          : TextInput,
+    pub history_list        : ListSelect,
+    pub rect_diff           : Rectangle,
+    pub rect_btn_rollback   : Rectangle,
+    pub rect_btn_set_note   : Rectangle,
+    pub preset_name_input   : TextInput,
+    pub rect_btn_save_preset: Rectangle,
+    pub preset_list         : ListSelect,
+    pub rect_btn_apply      : Rectangle,
+    pub rect_btn_delete     : Rectangle,
+    pub file_input          : TextInput,
+    pub rect_btn_export     : Rectangle,
+    pub rect_btn_import     : Rectangle,
+    font_size               : f32,
+}
+
+impl CncPidHistoryUi {
+    pub fn new() -> Self {
+        CncPidHistoryUi{
+            rect_profile_label  : Rectangle::default(),
+            profile_input       : TextInput::new(0f32, 0f32, 0f32, 0f32, "default", 32),
+            rect_btn_load       : Rectangle::default(),
+            note_input          : TextInput::new(0f32, 0f32, 0f32, 0f32, "", 64),
+            history_list        : ListSelect::new(0f32, 0f32, 0f32, 0f32),
+            rect_diff           : Rectangle::default(),
+            rect_btn_rollback   : Rectangle::default(),
+            rect_btn_set_note   : Rectangle::default(),
+            preset_name_input   : TextInput::new(0f32, 0f32, 0f32, 0f32, "", 32),
+            rect_btn_save_preset: Rectangle::default(),
+            preset_list         : ListSelect::new(0f32, 0f32, 0f32, 0f32),
+            rect_btn_apply      : Rectangle::default(),
+            rect_btn_delete     : Rectangle::default(),
+            file_input          : TextInput::new(0f32, 0f32, 0f32, 0f32, "./data/preset.pid", 128),
+            rect_btn_export     : Rectangle::default(),
+            rect_btn_import     : Rectangle::default(),
+            font_size           : 20f32,
+        }
+    }
+
+    /// `history_area` holds the note and history list, `diff_area` the diff and its
+    /// buttons, `preset_area` the profile and preset column.
+    pub fn layout(&mut self, layout: &Layout, history_area: Rectangle, diff_area: Rectangle, preset_area: Rectangle) {
+        let rows = layout.rows(history_area, &[ESize::EFixed(85f32), ESize::EFixed(40f32), ESize::EFixed(45f32), ESize::EWeight(1f32)], 0f32);
+        self.note_input.rect = rows[1];
+        self.history_list.rect = rows[3];
+
+        let rows = layout.rows(diff_area, &[ESize::EFixed(170f32), ESize::EFixed(130f32), ESize::EFixed(10f32), ESize::EFixed(40f32), ESize::EWeight(1f32)], 0f32);
+        self.rect_diff = rows[1];
+        let buttons = layout.columns(rows[3], &[ESize::EWeight(1f32), ESize::EWeight(1f32)], 10f32);
+        self.rect_btn_rollback = buttons[0];
+        self.rect_btn_set_note = buttons[1];
+
+        let rows = layout.rows(preset_area, &[ESize::EFixed(30f32), ESize::EFixed(40f32), ESize::EFixed(20f32), ESize::EFixed(40f32),
+            ESize::EFixed(10f32), ESize::EFixed(250f32), ESize::EFixed(10f32), ESize::EFixed(40f32), ESize::EFixed(20f32),
+            ESize::EFixed(40f32), ESize::EFixed(10f32), ESize::EFixed(40f32), ESize::EWeight(1f32)], 0f32);
+        self.rect_profile_label = rows[0];
+        let profile_row = layout.columns(rows[1], &[ESize::EFixed(200f32), ESize::EWeight(1f32)], 10f32);
+        self.profile_input.rect = profile_row[0];
+        self.rect_btn_load = profile_row[1];
+        let preset_row = layout.columns(rows[3], &[ESize::EFixed(200f32), ESize::EWeight(1f32)], 10f32);
+        self.preset_name_input.rect = preset_row[0];
+        self.rect_btn_save_preset = preset_row[1];
+        self.preset_list.rect = rows[5];
+        let buttons = layout.columns(rows[7], &[ESize::EWeight(1f32), ESize::EWeight(1f32)], 10f32);
+        self.rect_btn_apply = buttons[0];
+        self.rect_btn_delete = buttons[1];
+        self.file_input.rect = rows[9];
+        let buttons = layout.columns(rows[11], &[ESize::EWeight(1f32), ESize::EWeight(1f32)], 10f32);
+        self.rect_btn_export = buttons[0];
+        self.rect_btn_import = buttons[1];
+        self.font_size = layout.px(20f32);
+    }
+
+    /// Returns a preset or history entry the user wants loaded into the new config inputs.
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) -> Option<Vec<PIDParams>> {
+        let mut to_apply = None;
+        let font_size = self.font_size;
+
+        d.draw_text_ex(font, format!("PROFILE: {}", cnc.profile.name).as_str(), Vector2::new(self.rect_profile_label.x, self.rect_profile_label.y), font_size, 0f32, Color::DARKGRAY);
+        self.profile_input.update(d);
+        if d.gui_button(self.rect_btn_load, Some(rstr!("LOAD PROFILE"))) {
+            let name = self.profile_input.text();
+            if !name.is_empty() {
+                if let Err(e) = cnc.load_profile(&name) {
+                    log_error!("{}", e);
+                }
+            }
+        }
+
+        d.draw_text_ex(font, "NOTE", Vector2::new(self.note_input.rect.x, self.note_input.rect.y - font_size - 2f32), font_size, 0f32, Color::DARKGRAY);
+        self.note_input.update(d);
+
+        d.draw_text_ex(font, "HISTORY", Vector2::new(self.history_list.rect.x, self.history_list.rect.y - font_size - 2f32), font_size, 0f32, Color::DARKGRAY);
+        let entries = cnc.profile.pid_history.entries();
+        let labels: Vec<String> = entries.iter().rev().map(|entry| entry.label()).collect();
+        let selected_version = self.history_list.update(d, &labels).map(|i| entries[entries.len() - 1 - i].version);
+
+        d.draw_rectangle_lines_ex(self.rect_diff, 1, Color::DARKGRAY);
+        if let Some(version) = selected_version {
+            if let Some(diff) = cnc.profile.pid_history.diff(version, &cnc.pid_params) {
+                let mut text = format!("CURRENT - v{}\n", version);
+                for (axis, axis_diff) in cnc.axes.iter().zip(diff.iter()) {
+                    text.push_str(format!("{} P{:+.3} I{:+.3} D{:+.3}\n", axis.name, axis_diff.prop, axis_diff.inte, axis_diff.deri).as_str());
+                }
+                d.draw_text_rec(font, text.as_str(), self.rect_diff, font_size, 0f32, true, Color::DARKGRAY);
+            }
+        }
+        if d.gui_button(self.rect_btn_rollback, Some(rstr!("ROLLBACK"))) {
+            if let Some(version) = selected_version {
+                if let Err(e) = cnc.rollback_pid_params(version) {
+                    log_error!("{}", e);
+                }
+            }
+        }
+        if d.gui_button(self.rect_btn_set_note, Some(rstr!("SET NOTE"))) {
+            if let Some(version) = selected_version {
+                cnc.set_pid_note(version, &self.note_input.text());
+            }
+        }
+
+        d.draw_text_ex(font, "PRESETS", Vector2::new(self.preset_name_input.rect.x, self.preset_name_input.rect.y - font_size - 2f32), font_size, 0f32, Color::DARKGRAY);
+        self.preset_name_input.update(d);
+        let preset_names: Vec<String> = cnc.profile.pid_presets.iter().map(|preset| preset.name.clone()).collect();
+        let selected_preset = self.preset_list.update(d, &preset_names);
+        if d.gui_button(self.rect_btn_apply, Some(rstr!("APPLY"))) {
+            if let Some(i) = selected_preset {
+                to_apply = Some(cnc.profile.pid_presets[i].params.clone());
+            }
+        }
+        if d.gui_button(self.rect_btn_delete, Some(rstr!("DELETE"))) {
+            if let Some(i) = selected_preset {
+                cnc.remove_pid_preset(&preset_names[i]);
+            }
+        }
+        self.file_input.update(d);
+        if d.gui_button(self.rect_btn_export, Some(rstr!("EXPORT"))) {
+            if let Some(i) = selected_preset {
+                if let Err(e) = cnc.profile.pid_presets[i].export(&self.file_input.text()) {
+                    log_error!("{}", e);
+                }
+            }
+        }
+
+        to_apply
+    }
+}
+
+pub fn axis_color(axis: usize) -> Color {
+    match axis {
+        0 => Color::RED,
+        1 => Color::DARKGREEN,
+        2 => Color::BLUE,
+        3 => Color::PURPLE,
+        4 => Color::ORANGE,
+        5 => Color::BROWN,
+        _ => Color::GRAY,
     }
 }
 
 pub struct CncConfigUi {
-    pub axis_params: [CncAxisConfigUi; 3],
+    pub axes: Vec<AxisInfo>,
+    pub axis_params: Vec<CncAxisConfigUi>,
     pub rect_button_set_params : Rectangle,
+    pub pid_history: CncPidHistoryUi,
+    pub show_extended: bool,
+    pub rect_toggle_extended: Rectangle,
+    pub rect_button_set_config: Rectangle,
+    pub config_error: String,
+    font_size: f32,
 }
 
 impl CncConfigUi {
     pub fn new() -> Self {
-        let row_height = 70f32;
-        let col_width = 300f32;
-        let row_spacing = 10f32;
-        let col_spacing = 10f32;
-        
-        let x_axis = CncAxisConfigUi::new(0, 100f32 + col_width * 0f32 + col_spacing * 0f32, 100f32, col_width, row_height * 3f32 + row_spacing * 2f32);
-        let y_axis = CncAxisConfigUi::new(1, 100f32 + col_width * 1f32 + col_spacing * 1f32, 100f32, col_width, row_height * 3f32 + row_spacing * 2f32);
-        let z_axis = CncAxisConfigUi::new(2, 100f32 + col_width * 2f32 + col_spacing * 2f32, 100f32, col_width, row_height * 3f32 + row_spacing * 2f32);
-        
-        let button_rect = Rectangle::new(100f32 + col_width * 2f32 + col_spacing * 2f32, row_height * 6f32 + row_spacing * 4f32, col_width, row_height );
-        
+        let axes = AxisInfo::default_axes();
         CncConfigUi {
-            axis_params: [x_axis, y_axis, z_axis],
-            rect_button_set_params: button_rect,
+            axis_params: CncConfigUi::build_axis_params(&axes),
+            axes,
+            rect_button_set_params: Rectangle::default(),
+            pid_history: CncPidHistoryUi::new(),
+            show_extended: false,
+            rect_toggle_extended: Rectangle::default(),
+            rect_button_set_config: Rectangle::default(),
+            config_error: String::new(),
+            font_size: 20f32,
         }
     }
+
+    fn build_axis_params(axes: &[AxisInfo]) -> Vec<CncAxisConfigUi> {
+        axes.iter().enumerate().map(|(i, axis)| CncAxisConfigUi::new(i as u8, &axis.name)).collect()
+    }
+
+    /// Recomputes every rect for the content `area`. One column per axis, at most
+    /// 300 design pixels wide, then the history or the extended parameters below them.
+    pub fn layout(&mut self, layout: &Layout, area: Rectangle, cnc: &CncCtrl) {
+        if self.axes != cnc.axes {
+            self.axes = cnc.axes.clone();
+            self.axis_params = CncConfigUi::build_axis_params(&self.axes);
+        }
+
+        let columns = layout.columns(area, &[ESize::EFixed(80f32), ESize::EFixed(920f32), ESize::EFixed(40f32), ESize::EFixed(360f32), ESize::EWeight(1f32)], 0f32);
+        let main = layout.rows(columns[1], &[ESize::EFixed(230f32), ESize::EFixed(10f32), ESize::EFixed(30f32), ESize::EFixed(20f32), ESize::EWeight(1f32)], 0f32);
+        self.rect_toggle_extended = layout.anchor(main[2], EAnchor::ETopLeft, 200f32, 30f32);
+
+        let bottom = layout.rows(main[4], &[ESize::EFixed(256f32), ESize::EFixed(54f32), ESize::EFixed(70f32), ESize::EWeight(1f32)], 0f32);
+        let axis_columns = layout.columns(main[0], &vec![ESize::EWeight(1f32); self.axis_params.len().max(1)], 10f32);
+        for (axis_ui, column) in self.axis_params.iter_mut().zip(axis_columns.iter()) {
+            let width = column.width.min(layout.px(300f32));
+            axis_ui.set_rects(layout, Rectangle::new(column.x, column.y, width, column.height),
+                Rectangle::new(column.x, bottom[0].y, width, bottom[0].height));
+        }
+        self.rect_button_set_config = layout.anchor(bottom[2], EAnchor::ETopRight, 300f32, 70f32);
+
+        let history_columns = layout.columns(main[4], &[ESize::EWeight(1f32), ESize::EFixed(20f32), ESize::EFixed(300f32)], 0f32);
+        self.rect_button_set_params = Rectangle::new(history_columns[2].x, history_columns[2].y + layout.px(70f32), history_columns[2].width, layout.px(70f32));
+        self.pid_history.layout(layout, history_columns[0], history_columns[2], columns[3]);
+        self.font_size = layout.px(20f32);
+    }
+
+    fn new_params(&self) -> Vec<PIDParams> {
+        self.axis_params.iter().map(|axis_ui| axis_ui.new_params.clone()).collect()
+    }
     
     pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
-        self.axis_params[0].current_params = cnc.pid_params[0].clone();
-        self.axis_params[1].current_params = cnc.pid_params[1].clone();
-        self.axis_params[2].current_params = cnc.pid_params[2].clone();
+        for (axis, axis_ui) in self.axis_params.iter_mut().enumerate() {
+            axis_ui.current_params = cnc.pid_params.get(axis).cloned().unwrap_or_default();
+            axis_ui.verify_state = cnc.pid_verification.state(axis);
+            axis_ui.current_config = cnc.axis_config.get(axis).cloned().unwrap_or_default();
+            if cnc.axis_config_version.is_none() {
+                axis_ui.config_seeded = false;
+            } else if !axis_ui.config_seeded {
+                let config = axis_ui.current_config.clone();
+                axis_ui.set_new_config(&config);
+                axis_ui.config_seeded = true;
+            }
+        }
         
-        self.axis_params[0].draw(d, font, cnc);
-        self.axis_params[1].draw(d, font, cnc);
-        self.axis_params[2].draw(d, font, cnc);
+        for axis_ui in self.axis_params.iter_mut() {
+            axis_ui.draw(d, font, cnc);
+        }
         
+        self.show_extended = d.gui_toggle(self.rect_toggle_extended, Some(rstr!("ADVANCED")), self.show_extended);
+        if !self.config_error.is_empty() {
+            d.draw_text_ex(font, self.config_error.as_str(), Vector2::new(self.rect_toggle_extended.x, self.rect_button_set_config.y + self.font_size), self.font_size, 0f32, Color::RED);
+        }
+        if self.show_extended {
+            self.draw_extended(d, font, cnc);
+            return;
+        }
+
+        if let Some(params) = self.pid_history.draw(d, font, cnc) {
+            for (axis_ui, axis_params) in self.axis_params.iter_mut().zip(params.iter()) {
+                axis_ui.set_new_params(axis_params);
+            }
+        }
+
+        if d.gui_button(self.pid_history.rect_btn_save_preset, Some(rstr!("SAVE PRESET"))) {
+            let name = self.pid_history.preset_name_input.text();
+            if !name.is_empty() {
+                cnc.save_pid_preset(&name, self.new_params());
+            }
+        }
+
+        if d.gui_button(self.pid_history.rect_btn_import, Some(rstr!("IMPORT"))) {
+            match cnc.import_pid_preset(&self.pid_history.file_input.text()) {
+                Ok(()) => self.config_error.clear(),
+                Err(e) => self.config_error = e,
+            }
+        }
+
         if d.gui_button(self.rect_button_set_params, Some(rstr!("SET PARAMS"))) {
-            println!("Setting params...");
-            cnc.set_pid_params( &self.axis_params[0].new_params, &self.axis_params[1].new_params, &self.axis_params[2].new_params);
+            log_info!("Setting params...");
+            let note = self.pid_history.note_input.text();
+            match cnc.set_pid_params(&self.new_params(), &note) {
+                Ok(()) => {
+                    self.config_error.clear();
+                    self.pid_history.note_input.set_text("");
+                },
+                Err(e) => self.config_error = e,
+            }
+        }
+    }
+
+    fn draw_extended(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        let supported = cnc.axis_config_version.is_some();
+        for axis_ui in self.axis_params.iter_mut() {
+            axis_ui.draw_extended(d, font, supported);
+        }
+
+        let info = match cnc.axis_config_version {
+            Some(version) if version != AxisConfig::VERSION => format!("Controller axis config v{} is not supported, only v{}", version, AxisConfig::VERSION),
+            Some(version) => format!("Controller axis config v{}", version),
+            None => String::from("Controller only supports P/I/D, other parameters will not be sent"),
+        };
+        d.draw_text_ex(font, info.as_str(), Vector2::new(self.rect_toggle_extended.x + self.rect_toggle_extended.width + self.font_size, self.rect_toggle_extended.y + self.font_size * 0.2f32),
+            self.font_size, 0f32, Color::DARKGRAY);
+
+        if gui_button_enabled(d, self.rect_button_set_config, rstr!("SET CONFIG"), supported) {
+            let configs: Vec<AxisConfig> = self.axis_params.iter().map(|axis_ui| {
+                let mut config = axis_ui.new_config.clone();
+                config.pid = axis_ui.new_params.clone();
+                config
+            }).collect();
+            match cnc.set_axis_config(&configs, "") {
+                Ok(()) => self.config_error.clear(),
+                Err(e) => self.config_error = e,
+            }
         }
     }
 }
\ No newline at end of file
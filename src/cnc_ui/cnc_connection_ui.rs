@@ -1,168 +1,280 @@
-
-use std::{str, time::Duration};
-use std::net::{TcpStream, SocketAddr};
+use std::ffi::CString;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, TryRecvError};
 use raylib::prelude::*;
 
-pub struct ValueEdit {
-    pub rect        : Rectangle,
-    pub value       : i32,
-    pub min_value   : i32,
-    pub max_value   : i32,
-    pub text        : String,
-    pub edit_mode   : bool,
+use cnc_desktop::cnc_connection::{connect, parse_host, parse_port, CncConnectionManager, DiscoveredController};
+use cnc_desktop::cnc_ctrl::CncCtrl;
+use cnc_desktop::cnc_msg::{AxisInfo, EAxisKind};
+use cnc_desktop::cnc_recent::RecentConnections;
+
+use super::cnc_config_ui::{ListSelect, TextInput};
+use super::cnc_layout::{ESize, Layout};
+use cnc_desktop::{log_error, log_info};
+
+pub struct GuiHostAddress {
+    pub host_input      : TextInput,
+    pub port_input      : TextInput,
+    pub button_rect     : Rectangle,
+    pub recent_rect     : Rectangle,
+    pub recent          : RecentConnections,
+    recent_active       : i32,
+    recent_open         : bool,
+    rect_host_error     : Rectangle,
+    rect_port_error     : Rectangle,
+    connect_error       : String,
+    pub discover_rect   : Rectangle,
+    discovery_status_rect : Rectangle,
+    discovered_list     : ListSelect,
+    discovered          : Vec<DiscoveredController>,
+    discovery           : Option<mpsc::Receiver<Result<Vec<DiscoveredController>, String>>>,
+    discovery_status    : String,
+    font_size           : f32,
 }
 
-impl ValueEdit {
-    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
-        ValueEdit{
-            rect: Rectangle::new(x, y, w, h),
-            value       : 0,
-            min_value         : 0,
-            max_value         : 10,
-            text: String::new(),
-            edit_mode: false,
+impl GuiHostAddress {
+    pub fn new() -> GuiHostAddress {
+        let recent = RecentConnections::load();
+        let (host, port) = match recent.entries.first() {
+            Some(last) => (last.host.clone(), last.port.to_string()),
+            None => (String::from("192.168.2.232"), String::from("5555")),
+        };
+        GuiHostAddress{
+            host_input      : TextInput::new(0f32, 0f32, 0f32, 0f32, &host, 253),
+            port_input      : TextInput::new(0f32, 0f32, 0f32, 0f32, &port, 5),
+            button_rect     : Rectangle::default(),
+            recent_rect     : Rectangle::default(),
+            recent,
+            recent_active   : 0,
+            recent_open     : false,
+            rect_host_error : Rectangle::default(),
+            rect_port_error : Rectangle::default(),
+            connect_error   : String::new(),
+            discover_rect   : Rectangle::default(),
+            discovery_status_rect : Rectangle::default(),
+            discovered_list : ListSelect::new(0f32, 0f32, 0f32, 0f32),
+            discovered      : Vec::new(),
+            discovery       : None,
+            discovery_status: String::new(),
+            font_size       : 20f32,
         }
     }
-    pub fn update(&mut self, d: &mut RaylibDrawHandle) {
-        if d.gui_value_box(self.rect, None, &mut self.value, self.min_value, self.max_value, self.edit_mode) {
-            self.text = self.value.to_string();
+
+    /// Places the host, port and recent connections in a row with the connect button below,
+    /// followed by the discovery button and the list of discovered controllers.
+    pub fn layout(&mut self, layout: &Layout, area: Rectangle) {
+        let area = layout.columns(area, &[ESize::EFixed(80f32), ESize::EWeight(1f32)], 0f32)[1];
+        let rows = layout.rows(area, &[ESize::EFixed(50f32), ESize::EFixed(50f32), ESize::EFixed(30f32), ESize::EFixed(30f32),
+            ESize::EFixed(30f32), ESize::EFixed(30f32), ESize::EFixed(10f32), ESize::EWeight(1f32)], 0f32);
+        let fields = layout.columns(rows[1], &[ESize::EFixed(400f32), ESize::EFixed(150f32), ESize::EFixed(350f32), ESize::EWeight(1f32)], 15f32);
+        let errors = layout.columns(rows[2], &[ESize::EFixed(400f32), ESize::EFixed(150f32), ESize::EWeight(1f32)], 15f32);
+        self.host_input.rect = fields[0];
+        self.port_input.rect = fields[1];
+        self.recent_rect = Rectangle::new(fields[2].x, fields[2].y, fields[2].width, layout.px(40f32));
+        self.rect_host_error = errors[0];
+        self.rect_port_error = Rectangle::new(errors[1].x, errors[1].y, errors[1].width + errors[2].width, errors[1].height);
+        self.button_rect = Rectangle::new(rows[3].x, rows[3].y, layout.px(150f32), rows[3].height);
+        let discovery_row = layout.columns(rows[5], &[ESize::EFixed(150f32), ESize::EWeight(1f32)], 15f32);
+        self.discover_rect = discovery_row[0];
+        self.discovery_status_rect = discovery_row[1];
+        self.discovered_list.rect = Rectangle::new(rows[7].x, rows[7].y, layout.px(915f32).min(rows[7].width), rows[7].height);
+        self.font_size = layout.px(20f32);
+    }
+
+    fn draw_label(&self, d: &mut RaylibDrawHandle, font: &Font, text: &str, rect: Rectangle) {
+        d.draw_text_ex(font, text, Vector2::new(rect.x, rect.y - self.font_size * 1.5f32), self.font_size * 1.25f32, 0f32, Color::BLACK);
+    }
+
+    /// Connects to the first address of the host that accepts, trying IPv4 first.
+    fn connect(&mut self, host: &str, port: u16) -> Option<TcpStream> {
+        log_info!("Connecting...");
+        match connect(host, port) {
+            Ok(stream) => {
+                self.connect_error.clear();
+                self.recent.add(host, port);
+                if let Err(e) = self.recent.save() {
+                    log_error!("{}", e);
+                }
+                Some(stream)
+            },
+            Err(e) => {
+                self.connect_error = e;
+                None
+            },
         }
-        // for some reason this needs to come after gui_text_box
-        if d.is_mouse_button_pressed(MouseButton::MOUSE_LEFT_BUTTON) {
-            let mouse_pos = d.get_mouse_position();
-            if self.rect.check_collision_point_rec(mouse_pos) {
-                self.edit_mode = true;
-            } else {
-                self.edit_mode = false;
-                self.text = self.value.to_string();
-            }
+    }
+
+    /// Picks up the result of a running discovery.
+    fn poll_discovery(&mut self) {
+        let result = match self.discovery {
+            Some(ref rx) => match rx.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => Err(String::from("Discovery stopped unexpectedly")),
+            },
+            None => return,
+        };
+        self.discovery = None;
+        self.discovered_list.active = -1;
+        match result {
+            Ok(controllers) => {
+                self.discovery_status = match controllers.len() {
+                    0 => String::from("No controllers found"),
+                    1 => String::from("Found 1 controller, click it to connect"),
+                    n => format!("Found {} controllers, click one to connect", n),
+                };
+                self.discovered = controllers;
+            },
+            Err(e) => {
+                log_error!("{}", e);
+                self.discovery_status = e;
+                self.discovered.clear();
+            },
         }
     }
-}
 
-pub struct GuiIpAddress {
-    pub a_ip            : [ValueEdit; 4],
-    pub port            : ValueEdit, 
-    pub button_rect     : Rectangle,
-    rect_ip             : Rectangle,
-    rect_port           : Rectangle,
-    connecting          : bool,
-}
+    /// Discovery list, connecting to a controller as soon as it is clicked.
+    fn draw_discovery(&mut self, d: &mut RaylibDrawHandle, font: &Font, connection_manager: &mut CncConnectionManager) -> Option<TcpStream> {
+        self.poll_discovery();
+        if self.discovery.is_some() {
+            d.gui_set_state(GuiControlState::GUI_STATE_DISABLED);
+            d.gui_button(self.discover_rect, Some(rstr!("SEARCHING")));
+            d.gui_set_state(GuiControlState::GUI_STATE_NORMAL);
+        } else if d.gui_button(self.discover_rect, Some(rstr!("DISCOVER"))) {
+            self.discovery = Some(connection_manager.discover());
+            self.discovery_status = String::from("Searching the network...");
+        }
+        d.draw_text_ex(font, self.discovery_status.as_str(), Vector2::new(self.discovery_status_rect.x, self.discovery_status_rect.y + self.font_size * 0.2f32),
+            self.font_size, 0f32, Color::DARKGRAY);
 
-impl GuiIpAddress {
-    pub fn new() -> GuiIpAddress {
-        let base_x = 100f32;
-        let base_y = 200f32;
-        let ip_rect_width = 80f32;
-        let ip_rect_height = 50f32;
-        let margin = ip_rect_height * 0.1f32;
-        let a_ip = {
-            let mut ip_0 = ValueEdit::new(base_x, base_y, ip_rect_width, ip_rect_height);
-            let mut ip_1 = ValueEdit::new(base_x + (ip_rect_width + margin) * 1f32, base_y, ip_rect_width, ip_rect_height);
-            let mut ip_2 = ValueEdit::new(base_x + (ip_rect_width + margin) * 2f32, base_y, ip_rect_width, ip_rect_height);
-            let mut ip_3 = ValueEdit::new(base_x + (ip_rect_width + margin) * 3f32, base_y, ip_rect_width, ip_rect_height);
-            ip_0.value = 192;
-            ip_0.min_value = 0;
-            ip_0.max_value = 255;
-            ip_1.value = 168;
-            ip_1.min_value = 0;
-            ip_1.max_value = 255;
-            ip_2.value = 2;
-            ip_2.min_value = 0;
-            ip_2.max_value = 255;
-            ip_3.value = 232;
-            ip_3.min_value = 0;
-            ip_3.max_value = 255;
-            [ip_0, ip_1, ip_2, ip_3]
-        };
-        let port = {
-            let mut port_mut =ValueEdit::new(base_x + (ip_rect_width + margin * 2f32) * 4f32, base_y, ip_rect_width * 1.5f32, ip_rect_height);
-            port_mut.value = 5555;
-            port_mut.min_value = 0;
-            port_mut.max_value = 255*255;
-            port_mut
-        };
-        let port_rect = port.rect.clone();
-        GuiIpAddress{
-            a_ip            : a_ip,
-            port            : port,
-            button_rect     : Rectangle::new(base_x, base_y + ip_rect_height + margin * 4f32, 150.0f32, 30f32),
-            rect_ip         : Rectangle::new(base_x - margin, base_y - margin, ip_rect_width * 4f32 + margin * 5f32, ip_rect_height + margin * 2f32),
-            rect_port       : Rectangle::new(port_rect.x - margin, port_rect.y - margin, port_rect.width + margin * 2.0f32, port_rect.height + margin * 2.0f32),
-            connecting      : false,
+        let labels: Vec<String> = self.discovered.iter().map(|controller| controller.label()).collect();
+        let previous = self.discovered_list.active;
+        let selected = self.discovered_list.update(d, &labels)?;
+        if previous == selected as i32 {
+            return None;
         }
+        let controller = self.discovered[selected].clone();
+        self.discovered_list.active = -1;
+        self.host_input.set_text(&controller.ip);
+        self.port_input.set_text(&controller.port.to_string());
+        self.connect(&controller.ip, controller.port)
     }
 }
 
-pub fn configure_ip(d: &mut RaylibDrawHandle, font: &Font, gui: &mut GuiIpAddress) -> Option<TcpStream> {
-    if gui.connecting {
-        d.gui_set_state(GuiControlState::GUI_STATE_DISABLED);
+pub fn configure_host(d: &mut RaylibDrawHandle, font: &Font, gui: &mut GuiHostAddress, connection_manager: &mut CncConnectionManager) -> Option<TcpStream> {
+    gui.draw_label(d, font, "Host", gui.host_input.rect);
+    gui.draw_label(d, font, "Port", gui.port_input.rect);
+    gui.draw_label(d, font, "Recent", gui.recent_rect);
+
+    gui.host_input.update(d);
+    gui.port_input.update(d);
+
+    let host = parse_host(&gui.host_input.text());
+    let port = parse_port(&gui.port_input.text());
+    if let Err(ref e) = host {
+        d.draw_rectangle_lines_ex(gui.host_input.rect, 2, Color::RED);
+        d.draw_text_ex(font, e.as_str(), Vector2::new(gui.rect_host_error.x, gui.rect_host_error.y + gui.font_size * 0.2f32), gui.font_size, 0f32, Color::RED);
+    }
+    if let Err(ref e) = port {
+        d.draw_rectangle_lines_ex(gui.port_input.rect, 2, Color::RED);
+        d.draw_text_ex(font, e.as_str(), Vector2::new(gui.rect_port_error.x, gui.rect_port_error.y + gui.font_size * 0.2f32), gui.font_size, 0f32, Color::RED);
+    }
+    if !gui.connect_error.is_empty() {
+        d.draw_text_ex(font, gui.connect_error.as_str(), Vector2::new(gui.button_rect.x + gui.button_rect.width + gui.font_size, gui.button_rect.y + gui.font_size * 0.2f32),
+            gui.font_size, 0f32, Color::RED);
+    }
+
+    let mut stream = None;
+    if let (Ok(host), Ok(port)) = (&host, &port) {
+        if d.gui_button(gui.button_rect, Some(rstr!("CONNECT"))) {
+            stream = gui.connect(host, *port);
+        }
     } else {
+        d.gui_set_state(GuiControlState::GUI_STATE_DISABLED);
+        d.gui_button(gui.button_rect, Some(rstr!("CONNECT")));
         d.gui_set_state(GuiControlState::GUI_STATE_NORMAL);
     }
-    d.draw_rectangle_lines(gui.rect_ip.x as i32, gui.rect_ip.y as i32, gui.rect_ip.width as i32, gui.rect_ip.height as i32, Color::BLACK);
-    d.draw_rectangle_lines(gui.rect_port.x as i32, gui.rect_port.y as i32, gui.rect_port.width as i32, gui.rect_port.height as i32, Color::BLACK);
-    d.draw_text_ex(&font, 
-        "IP Address", 
-        Vector2::new(gui.rect_ip.x, gui.rect_ip.y - gui.rect_ip.height / 4f32 * 2f32), 
-        gui.rect_ip.height / 2f32, 0.0f32,Color::BLACK);
-    d.draw_text_ex(&font, 
-        "Port", 
-        Vector2::new(gui.rect_port.x, gui.rect_port.y - gui.rect_port.height / 4f32 * 2f32), 
-        gui.rect_port.height / 2f32, 
-        0.0f32,
-        Color::BLACK);
-    for i in 0..4 {
-        gui.a_ip[i].update(d);
+
+    if stream.is_none() {
+        stream = gui.draw_discovery(d, font, connection_manager);
     }
-    gui.port.update(d);
 
-    let button_text = {
-        if gui.connecting {
-            Some(rstr!("CONNECTING"))
-        } else {
-            Some(rstr!("CONNECT"))
+    // Drawn last so the open list covers the widgets below it.
+    let labels: Vec<String> = gui.recent.entries.iter().map(|entry| entry.label().replace(';', ",")).collect();
+    let text = if labels.is_empty() { CString::new("-").unwrap_or_default() } else { CString::new(labels.join(";")).unwrap_or_default() };
+    if d.gui_dropdown_box(gui.recent_rect, Some(text.as_c_str()), &mut gui.recent_active, gui.recent_open) {
+        gui.recent_open = !gui.recent_open;
+        if !gui.recent_open {
+            if let Some(entry) = gui.recent.entries.get(gui.recent_active as usize) {
+                gui.host_input.set_text(&entry.host);
+                gui.port_input.set_text(&entry.port.to_string());
+            }
         }
-    };
-
-    if d.gui_button(gui.button_rect, button_text) {
-        let str_input: String = format!("{}.{}.{}.{}:{}",
-            gui.a_ip[0].text.clone(),
-            gui.a_ip[1].text.clone(),
-            gui.a_ip[2].text.clone(),
-            gui.a_ip[3].text.clone(), 
-            gui.port.text.clone());
-        
-        let mut saddr: Option<SocketAddr> = None;
-        let str_trimmed: &str = str_input.trim();
-        // assert_eq!(str_trimmed, "192.168.43.184:5555");
-        match str_trimmed.parse() {
-        // match SocketAddr::from_str("192.168.43.184:5555") {
-            Ok(sock_addres) => {
-                saddr = Some(sock_addres);
-            },
-            Err(parse_error) => {
-                println!("error parsing the input [{}] with error '{:?}'", str_trimmed, parse_error);
-            },
+    }
+
+    stream
+}
+
+/// Shows the machine's axes and lets the profile define them for controllers without a handshake.
+pub struct CncAxesUi {
+    pub rect_label      : Rectangle,
+    pub spec_input      : TextInput,
+    pub rect_btn_apply  : Rectangle,
+    error               : String,
+    font_size           : f32,
+}
+
+impl CncAxesUi {
+    pub fn new(axes: &[AxisInfo]) -> Self {
+        let base_x = 100f32;
+        let base_y = 350f32;
+        CncAxesUi{
+            rect_label      : Rectangle::new(base_x, base_y, 800f32, 30f32),
+            spec_input      : TextInput::new(base_x, base_y + 35f32, 500f32, 40f32, &AxisInfo::format_axes(axes), 128),
+            rect_btn_apply  : Rectangle::new(base_x + 510f32, base_y + 35f32, 150f32, 40f32),
+            error           : String::new(),
+            font_size       : 20f32,
         }
+    }
+
+    pub fn layout(&mut self, layout: &Layout, area: Rectangle) {
+        let area = layout.columns(area, &[ESize::EFixed(80f32), ESize::EWeight(1f32)], 0f32)[1];
+        let rows = layout.rows(area, &[ESize::EFixed(30f32), ESize::EFixed(40f32), ESize::EWeight(1f32)], 5f32);
+        self.rect_label = Rectangle::new(rows[0].x, rows[0].y, layout.px(800f32).min(rows[0].width), rows[0].height);
+        let input_row = layout.columns(rows[1], &[ESize::EFixed(500f32), ESize::EFixed(150f32), ESize::EWeight(1f32)], 10f32);
+        self.spec_input.rect = input_row[0];
+        self.rect_btn_apply = input_row[1];
+        self.font_size = layout.px(20f32);
+    }
 
-        if let Some(address) = saddr {
-            gui.connecting = true;
-            // let mut tcp_stream: TcpStream;
-            println!("Connecting...");
-            match TcpStream::connect_timeout(&address, Duration::from_secs(5)) {
-            // match TcpStream::connect("192.168.43.184:5555") {
-                Ok(stream) => {
-                    gui.connecting = false;
-                    return Some(stream);
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        let names: Vec<String> = cnc.axes.iter().map(|axis| {
+            match axis.kind {
+                EAxisKind::ELinear => axis.name.clone(),
+                EAxisKind::ERotary => format!("{}(rot)", axis.name),
+            }
+        }).collect();
+        let source = if cnc.axes_from_handshake { "controller" } else { "profile" };
+        d.draw_text_ex(font, format!("Axes: {} (from {})", names.join(" "), source).as_str(),
+            Vector2::new(self.rect_label.x, self.rect_label.y), self.rect_label.height * 0.8f32, 0f32, Color::BLACK);
+
+        self.spec_input.update(d);
+        if d.gui_button(self.rect_btn_apply, Some(rstr!("SET AXES"))) {
+            match AxisInfo::parse_axes(&self.spec_input.text()) {
+                Ok(axes) => {
+                    self.error.clear();
+                    if let Err(e) = cnc.set_profile_axes(axes) {
+                        self.error = e;
+                    }
+                },
+                Err(e) => {
+                    self.error = e;
                 },
-                Err(error) => {
-                    gui.connecting = false;
-                    println!("Failed to connect to {:?} with error {:?}", saddr, error);
-                }
             }
         }
+        if !self.error.is_empty() {
+            d.draw_text_ex(font, self.error.as_str(), Vector2::new(self.spec_input.rect.x, self.spec_input.rect.y + self.spec_input.rect.height + 5f32),
+                self.font_size, 0f32, Color::RED);
+        }
     }
-
-    None
-}
\ No newline at end of file
+}
@@ -1,16 +1,29 @@
+use std::ffi::CString;
 use std::str::FromStr;
 
-use raylib::{ffi::IsMouseButtonDown, math::Rectangle};
+use raylib::math::Rectangle;
 use raylib::prelude::*;
 
-use crate::cnc_ctrl::{CncCtrl};
-use crate::cnc_msg::{CncCoordinates};
+use cnc_desktop::cnc_ctrl::{CncCtrl};
+use cnc_desktop::cnc_msg::{AxisInfo, CncCoordinates, EAxisKind};
+use cnc_desktop::cnc_units::EUnits;
+use cnc_desktop::cnc_toolpath::{EMoveKind, Toolpath};
+use cnc_desktop::cnc_job::EJobState;
+use cnc_desktop::cnc_job_sim::format_duration;
+use cnc_desktop::cnc_machine_state::ECommand;
+use cnc_desktop::cnc_trail::PositionTrail;
+use cnc_desktop::cnc_bus::{EEventKind, EStatusEvent, Subscription};
+use cnc_desktop::cnc_macro::{EMacroState, MacroDef};
+
+use super::cnc_config_ui::{axis_color, gui_button_enabled, TextInput, ValueInput};
+use super::cnc_layout::{ESize, Layout};
+use super::cnc_following_error_ui::CncFollowingErrorUi;
 
 struct CoordIndicator {
     background: Rectangle,
     label_background: Rectangle,
     label: String,
-    coords: f32,
+    coords: String,
     color: Color,
 }
 
@@ -20,17 +33,8 @@ impl CoordIndicator {
             background: Rectangle::new(0.0f32, 0.0f32, 100.0f32, 100.0f32),
             label_background: Rectangle::new(0.0f32, 0.0f32, 100.0f32, 100.0f32),
             label: String::from_str(label).unwrap(),
-            coords: 0.0f32,
-            color: color,
-        }
-    }
-    pub fn new_with_color_and_position(label: &str, pos: Vector2, size: Vector2, color: Color) -> Self {
-        CoordIndicator{
-            background: Rectangle::new(pos.x, pos.y, size.x, size.y),
-            label_background: Rectangle::new(pos.x, pos.y, size.y, size.y),
-            label: String::from_str(label).unwrap(),
-            coords: 0.0f32,
-            color: color,
+            coords: String::new(),
+            color,
         }
     }
     pub fn set_pos(&mut self, pos: Vector2) {
@@ -45,29 +49,38 @@ impl CoordIndicator {
         self.label_background.width = h;
         self.label_background.height = h;
     }
-    pub fn set_coords(&mut self, coords: f32) {
+    /// Sets the already formatted coordinate text.
+    pub fn set_coords(&mut self, coords: String) {
         self.coords = coords;
     }
     pub fn draw(&self, d: &mut RaylibDrawHandle, font: &Font) {
         let font_size = self.background.height * 0.75f32;
 
-        d.draw_rectangle_rec(&self.background, Color::WHITE);
+        d.draw_rectangle_rec(self.background, Color::WHITE);
         d.draw_rectangle_lines_ex(self.background, (font_size * 0.075f32) as i32, self.color);
 
-        d.draw_rectangle_rec(&self.label_background, self.color);
+        d.draw_rectangle_rec(self.label_background, self.color);
         
         let text_label = self.label.clone();
         let text_label_size = measure_text_ex(font, text_label.as_str(), font_size, 0f32);
         let position = Vector2::new( self.label_background.x + self.label_background.width * 0.5f32 - text_label_size.x * 0.5f32,
             self.label_background.y + self.label_background.height * 0.5f32 - text_label_size.y * 0.5f32);
-        d.draw_text_ex(&font,text_label.as_str(), 
+        d.draw_text_ex(font,text_label.as_str(), 
             position, font_size, 0f32, Color::WHITE);
         
-        let text_coords = format!("{:3.3}", self.coords);
-        let text_coords_size = measure_text_ex(font, text_coords.as_str(), font_size, 0f32);
+        let text_coords = self.coords.as_str();
+        let available_width = self.background.width - self.label_background.width - font_size * 0.4f32;
+        let mut text_coords_size = measure_text_ex(font, text_coords, font_size, 0f32);
+        let font_size = if text_coords_size.x > available_width && text_coords_size.x > 0f32 {
+            let scaled = font_size * available_width / text_coords_size.x;
+            text_coords_size = measure_text_ex(font, text_coords, scaled, 0f32);
+            scaled
+        } else {
+            font_size
+        };
         let position = Vector2::new( self.background.x + self.background.width - font_size * 0.2f32 - text_coords_size.x,
             self.background.y + self.background.height * 0.5f32 - text_coords_size.y * 0.5f32);
-        d.draw_text_ex(&font,text_coords.as_str(), 
+        d.draw_text_ex(font,text_coords, 
             position, font_size, 0f32, Color::BLACK);
         
         
@@ -77,42 +90,39 @@ impl CoordIndicator {
 struct CncCoordsDisplay{
     background: Rectangle,
     title: String,
-    indicators: [CoordIndicator; 3],
+    units: String,
+    indicators: Vec<CoordIndicator>,
     coords: CncCoordinates,
 }
 
 impl CncCoordsDisplay {
-    pub fn new(title: &str) -> Self {
+    pub fn new(title: &str, axes: &[AxisInfo]) -> Self {
         CncCoordsDisplay{
             background: Rectangle::new(0f32, 0f32, 10f32, 10f32),
             title: String::from_str(title).unwrap(),
-            indicators: [
-                CoordIndicator::new("X", Color::RED), 
-                CoordIndicator::new("Y", Color::DARKGREEN),
-                CoordIndicator::new("Z", Color::BLUE)],
-            coords: CncCoordinates::new(),
+            units: String::new(),
+            indicators: axes.iter().enumerate().map(|(i, axis)| CoordIndicator::new(&axis.name, axis_color(i))).collect(),
+            coords: CncCoordinates::with_axes(axes.len()),
         }
     }
-    pub fn set_coords(&mut self, coords: CncCoordinates) {
+    /// Shows `coords` (machine units) in the controller's display units, with the
+    /// same precision on every axis of a kind.
+    pub fn set_coords(&mut self, coords: CncCoordinates, cnc: &CncCtrl) {
         self.coords = coords;
-        self.indicators[0].set_coords(self.coords.x);
-        self.indicators[1].set_coords(self.coords.y);
-        self.indicators[2].set_coords(self.coords.z);
+        for (axis, indicator) in self.indicators.iter_mut().enumerate() {
+            indicator.set_coords(cnc.format_axis_value(axis, self.coords.get(axis)));
+        }
+        self.units = String::from(cnc.display_units.suffix(EAxisKind::ELinear));
 
         self.calculate_indicator_positions();
     }
     fn calculate_indicator_positions(&mut self) {
-        let position = Vector2::new( self.background.x + self.background.width * 0.333f32 * 0.5f32 - self.indicators[0].background.width * 0.5f32,
-            self.background.y + self.background.height * 0.75f32 - self.indicators[0].background.height * 0.5f32);
-        self.indicators[0].set_pos(position);
-
-        let position = Vector2::new( self.background.x + self.background.width * 0.333f32 * 1.5f32 - self.indicators[0].background.width * 0.5f32,
-            self.background.y + self.background.height * 0.75f32 - self.indicators[0].background.height * 0.5f32);
-        self.indicators[1].set_pos(position);
-
-        let position = Vector2::new( self.background.x + self.background.width * 0.333f32 * 2.5f32 - self.indicators[0].background.width * 0.5f32,
-            self.background.y + self.background.height * 0.75f32 - self.indicators[0].background.height * 0.5f32);
-        self.indicators[2].set_pos(position);
+        let slot_width = self.background.width / self.indicators.len().max(1) as f32;
+        for (i, indicator) in self.indicators.iter_mut().enumerate() {
+            let position = Vector2::new( self.background.x + slot_width * (i as f32 + 0.5f32) - indicator.background.width * 0.5f32,
+                self.background.y + self.background.height * 0.75f32 - indicator.background.height * 0.5f32);
+            indicator.set_pos(position);
+        }
     }
     pub fn set_pos(&mut self, pos: Vector2) {
         self.background.x = pos.x;
@@ -124,8 +134,9 @@ impl CncCoordsDisplay {
         self.background.width = w;
         self.background.height = h;
 
+        let indicator_width = self.background.width * 0.75f32 / self.indicators.len().max(1) as f32;
         for indicator in &mut self.indicators {
-            (*indicator).set_size(self.background.width * 0.25f32, self.background.height * 0.45f32);
+            (*indicator).set_size(indicator_width, self.background.height * 0.45f32);
         }
 
         self.calculate_indicator_positions();
@@ -136,8 +147,13 @@ impl CncCoordsDisplay {
         let position = Vector2::new( self.background.x + font_size,
             self.background.y + self.background.height * 0.25f32 - font_size * 0.5f32);
 
-        d.draw_rectangle_rec(&self.background, Color::LIGHTGRAY);
-        d.draw_text_ex(&font,self.title.as_str(), position, font_size, 0f32, Color::BLACK);
+        d.draw_rectangle_rec(self.background, Color::LIGHTGRAY);
+        d.draw_text_ex(font,self.title.as_str(), position, font_size, 0f32, Color::BLACK);
+
+        let units_size = measure_text_ex(font, self.units.as_str(), font_size * 0.6f32, 0f32);
+        let position = Vector2::new( self.background.x + self.background.width - units_size.x - font_size,
+            self.background.y + self.background.height * 0.25f32 - units_size.y * 0.5f32);
+        d.draw_text_ex(font,self.units.as_str(), position, font_size * 0.6f32, 0f32, Color::DARKGRAY);
 
         for indicator in &self.indicators {
             (*indicator).draw(d, font);
@@ -160,7 +176,7 @@ impl CncZCoordIndicator {
         }
     }
 
-    pub fn draw(&self, d: &mut RaylibDrawHandle) {
+    pub fn draw(&self, d: &mut impl RaylibDraw) {
         let start = self.pos - Vector2::new(1.0f32 * self.size, 0f32);
         let end = self.pos + Vector2::new(0.15f32 * self.size, 0f32);
         d.draw_line_v(start, end, self.color);
@@ -187,7 +203,7 @@ impl CncXyCoordsIndicator {
         }
     }
 
-    pub fn draw(&self, d: &mut RaylibDrawHandle) {
+    pub fn draw(&self, d: &mut impl RaylibDraw) {
         let start = self.pos - Vector2::new(0.5f32 * self.size, 0f32);
         let end = self.pos + Vector2::new(0.5f32 * self.size, 0f32);
         d.draw_line_v(start, end, self.color);
@@ -207,9 +223,23 @@ struct CncAreaRect{
     x_max: f32,
     y_min: f32,
     y_max: f32,
+    /// Machine region currently shown, kept at the aspect ratio of `rect`.
+    view_x_min: f32,
+    view_x_max: f32,
+    view_y_min: f32,
+    view_y_max: f32,
+    pan_anchor: Option<Vector2>,
+    /// Layout scale, for the rulers.
+    scale: f32,
 }
 
 impl CncAreaRect {
+    const ZOOM_STEP: f32 = 0.8f32;
+    /// Smallest visible width in mm, about a micron per pixel.
+    const MIN_VIEW_LENGTH: f32 = 0.5f32;
+    /// Minimum grid spacing in pixels before switching to a coarser step.
+    const MIN_GRID_PIXELS: f32 = 50f32;
+
     pub fn new(rec_width: f32, x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> Self {
         let x_length = x_max - x_min;
         let y_length = y_max - y_min;
@@ -225,50 +255,295 @@ impl CncAreaRect {
             x_max,
             y_min,
             y_max,
+            view_x_min: x_min,
+            view_x_max: x_max,
+            view_y_min: y_min,
+            view_y_max: y_max,
+            pan_anchor: None,
+            scale: 1f32,
+        }
+    }
+
+    /// Width over height of the machine travel.
+    pub fn aspect(&self) -> f32 {
+        (self.x_max - self.x_min) / (self.y_max - self.y_min)
+    }
+
+    /// Moves and resizes the area. The visible machine region is kept, so the view
+    /// only scales as long as the aspect ratio doesn't change.
+    pub fn set_rect(&mut self, rect: Rectangle, scale: f32) {
+        self.rect = rect;
+        self.scale = scale;
+    }
+
+    pub fn fit_machine(&mut self) {
+        self.fit(self.x_min, self.x_max, self.y_min, self.y_max);
+    }
+
+    /// Shows the XY bounding box of the toolpath with a small margin.
+    pub fn fit_toolpath(&mut self, toolpath: &Toolpath) {
+        let mut points = toolpath.segments.iter()
+            .flat_map(|segment| vec![&segment.start, &segment.end])
+            .map(|point| (point[0], point.get(1).copied().unwrap_or(0f32)));
+        let first = match points.next() {
+            Some(first) => first,
+            None => return,
+        };
+        let (x_min, x_max, y_min, y_max) = points.fold((first.0, first.0, first.1, first.1),
+            |(x_min, x_max, y_min, y_max), (x, y)| (x_min.min(x), x_max.max(x), y_min.min(y), y_max.max(y)));
+        let margin = (x_max - x_min).max(y_max - y_min) * 0.05f32;
+        self.fit(x_min - margin, x_max + margin, y_min - margin, y_max + margin);
+    }
+
+    /// Centers the view on the given box, growing one side to keep the aspect ratio.
+    fn fit(&mut self, x_min: f32, x_max: f32, y_min: f32, y_max: f32) {
+        let aspect = self.rect.width / self.rect.height;
+        let mut width = (x_max - x_min).max(CncAreaRect::MIN_VIEW_LENGTH);
+        let mut height = (y_max - y_min).max(CncAreaRect::MIN_VIEW_LENGTH);
+        if width / height > aspect {
+            height = width / aspect;
+        } else {
+            width = height * aspect;
+        }
+        let center = Vector2::new((x_min + x_max) * 0.5f32, (y_min + y_max) * 0.5f32);
+        self.view_x_min = center.x - width * 0.5f32;
+        self.view_x_max = center.x + width * 0.5f32;
+        self.view_y_min = center.y - height * 0.5f32;
+        self.view_y_max = center.y + height * 0.5f32;
+    }
+
+    /// Zooms with the mouse wheel around the cursor and pans while the right button
+    /// is dragged.
+    pub fn handle_input(&mut self, d: &RaylibDrawHandle) {
+        let mouse_pos = d.get_mouse_position();
+        let hovered = self.rect.check_collision_point_rec(mouse_pos);
+
+        let wheel = d.get_mouse_wheel_move();
+        if hovered && wheel != 0 {
+            let pivot = self.screen_to_view(&mouse_pos);
+            let machine_length = (self.x_max - self.x_min).max(self.y_max - self.y_min);
+            let scale = CncAreaRect::ZOOM_STEP.powi(wheel);
+            let width = self.view_x_max - self.view_x_min;
+            let height = self.view_y_max - self.view_y_min;
+            let new_width = (width * scale).max(CncAreaRect::MIN_VIEW_LENGTH).min(machine_length * 4f32);
+            let scale = new_width / width;
+            self.view_x_min = pivot.x - (pivot.x - self.view_x_min) * scale;
+            self.view_x_max = self.view_x_min + new_width;
+            self.view_y_min = pivot.y - (pivot.y - self.view_y_min) * scale;
+            self.view_y_max = self.view_y_min + height * scale;
+        }
+
+        if d.is_mouse_button_pressed(MouseButton::MOUSE_RIGHT_BUTTON) && hovered {
+            self.pan_anchor = Some(mouse_pos);
+        }
+        if !d.is_mouse_button_down(MouseButton::MOUSE_RIGHT_BUTTON) {
+            self.pan_anchor = None;
+        }
+        if let Some(anchor) = self.pan_anchor {
+            let dx = (mouse_pos.x - anchor.x) / self.rect.width * (self.view_x_max - self.view_x_min);
+            let dy = (mouse_pos.y - anchor.y) / self.rect.height * (self.view_y_max - self.view_y_min);
+            self.view_x_min -= dx;
+            self.view_x_max -= dx;
+            self.view_y_min -= dy;
+            self.view_y_max -= dy;
+            self.pan_anchor = Some(mouse_pos);
         }
     }
 
-    pub fn set_pos(&mut self, x: f32, y: f32) {
-        self.rect.x = x;
-        self.rect.y = y;
+    /// Visible fraction of the machine's X travel, 1.0 when the whole machine fits.
+    pub fn zoom(&self) -> f32 {
+        (self.x_max - self.x_min) / (self.view_x_max - self.view_x_min)
     }
 
-    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font) {
-        d.draw_rectangle_rec(&self.rect, Color::WHITE);
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, _font: &Font) {
+        d.draw_rectangle_rec(self.rect, Color::WHITE);
         d.draw_rectangle_lines_ex(self.rect, 2, Color::BLACK);
     }
 
+    /// Grid spacing in mm: a 1-2-5 step that keeps lines at least `MIN_GRID_PIXELS` apart.
+    fn grid_step(&self) -> f32 {
+        let pixels_per_mm = self.rect.width / (self.view_x_max - self.view_x_min);
+        grid_step(CncAreaRect::MIN_GRID_PIXELS * self.scale / pixels_per_mm)
+    }
 
-    pub fn map_to_screen(&self, coords: &Vector2) -> Vector2 {
+    /// Draws the grid and the machine travel limits; meant to be clipped to the area.
+    pub fn draw_grid(&self, d: &mut impl RaylibDraw) {
+        let step = self.grid_step();
+        let line_color = Color::new(230, 230, 230, 255);
+
+        let mut x = (self.view_x_min / step).ceil() * step;
+        while x <= self.view_x_max {
+            let screen = self.map_to_screen(&Vector2::new(x, self.view_y_min));
+            let color = if x.abs() < step * 0.5f32 { Color::GRAY } else { line_color };
+            d.draw_line_v(Vector2::new(screen.x, self.rect.y), Vector2::new(screen.x, self.rect.y + self.rect.height), color);
+            x += step;
+        }
+        let mut y = (self.view_y_min / step).ceil() * step;
+        while y <= self.view_y_max {
+            let screen = self.map_to_screen(&Vector2::new(self.view_x_min, y));
+            let color = if y.abs() < step * 0.5f32 { Color::GRAY } else { line_color };
+            d.draw_line_v(Vector2::new(self.rect.x, screen.y), Vector2::new(self.rect.x + self.rect.width, screen.y), color);
+            y += step;
+        }
+
+        let top_left = self.map_to_screen(&Vector2::new(self.x_min, self.y_min));
+        let bottom_right = self.map_to_screen(&Vector2::new(self.x_max, self.y_max));
+        d.draw_rectangle_lines_ex(Rectangle::new(top_left.x, top_left.y, bottom_right.x - top_left.x, bottom_right.y - top_left.y), 1, Color::RED);
+    }
+
+    /// Draws the rulers with grid labels above and left of the area.
+    pub fn draw_rulers(&self, d: &mut RaylibDrawHandle, font: &Font) {
+        let step = self.grid_step();
+        let decimals = if step >= 1f32 { 0 } else { (-step.log10()).ceil() as usize };
+        let ruler = 20f32 * self.scale;
+        let font_size = 14f32 * self.scale;
+
+        d.draw_rectangle_rec(Rectangle::new(self.rect.x, self.rect.y - ruler, self.rect.width, ruler), Color::LIGHTGRAY);
+        d.draw_rectangle_rec(Rectangle::new(self.rect.x - ruler * 2f32, self.rect.y, ruler * 2f32, self.rect.height), Color::LIGHTGRAY);
+
+        let mut x = (self.view_x_min / step).ceil() * step;
+        while x <= self.view_x_max {
+            let screen = self.map_to_screen(&Vector2::new(x, self.view_y_min));
+            d.draw_line_v(Vector2::new(screen.x, self.rect.y - ruler * 0.3f32), Vector2::new(screen.x, self.rect.y), Color::DARKGRAY);
+            let label = format!("{:.*}", decimals, x);
+            let size = measure_text_ex(font, label.as_str(), font_size, 0f32);
+            if screen.x - size.x * 0.5f32 >= self.rect.x && screen.x + size.x * 0.5f32 <= self.rect.x + self.rect.width {
+                d.draw_text_ex(font, label.as_str(), Vector2::new(screen.x - size.x * 0.5f32, self.rect.y - ruler), font_size, 0f32, Color::BLACK);
+            }
+            x += step;
+        }
+
+        let mut y = (self.view_y_min / step).ceil() * step;
+        while y <= self.view_y_max {
+            let screen = self.map_to_screen(&Vector2::new(self.view_x_min, y));
+            d.draw_line_v(Vector2::new(self.rect.x - ruler * 0.3f32, screen.y), Vector2::new(self.rect.x, screen.y), Color::DARKGRAY);
+            let label = format!("{:.*}", decimals, y);
+            let size = measure_text_ex(font, label.as_str(), font_size, 0f32);
+            if screen.y - size.y * 0.5f32 >= self.rect.y && screen.y + size.y * 0.5f32 <= self.rect.y + self.rect.height {
+                d.draw_text_ex(font, label.as_str(), Vector2::new(self.rect.x - ruler * 0.4f32 - size.x, screen.y - size.y * 0.5f32), font_size, 0f32, Color::BLACK);
+            }
+            y += step;
+        }
+        d.draw_rectangle_lines_ex(self.rect, 2, Color::BLACK);
+    }
+
+    /// Draws the XY projection of the toolpath. Segments before `completed` are done,
+    /// the one at `completed` is being executed while `running`.
+    pub fn draw_toolpath(&self, d: &mut impl RaylibDraw, toolpath: &Toolpath, completed: usize, running: bool) {
+        for (i, segment) in toolpath.segments.iter().enumerate() {
+            let start = self.map_to_screen(&Vector2::new(segment.start[0], segment.start.get(1).copied().unwrap_or(0f32)));
+            let end = self.map_to_screen(&Vector2::new(segment.end[0], segment.end.get(1).copied().unwrap_or(0f32)));
+            d.draw_line_ex(start, end, segment_thickness(i, completed, running), segment_color(segment.kind, i, completed, running));
+        }
+    }
+
+    /// Draws the path the machine actually took, coloured by following error.
+    pub fn draw_trail(&self, d: &mut impl RaylibDraw, trail: &PositionTrail) {
+        let mut previous: Option<Vector2> = None;
+        for point in trail.points() {
+            let screen = self.map_to_screen(&Vector2::new(point.position[0], point.position.get(1).copied().unwrap_or(0f32)));
+            if let Some(start) = previous {
+                d.draw_line_ex(start, screen, 2f32, following_error_color(point.following_error));
+            }
+            previous = Some(screen);
+        }
+    }
+
+    /// Draws Z over the course of the program, left to right, for the Z strip.
+    pub fn draw_z_profile(&self, d: &mut impl RaylibDraw, toolpath: &Toolpath, completed: usize, running: bool) {
+        let count = toolpath.segments.len().max(1) as f32;
         let x_length = self.x_max - self.x_min;
-        let y_length = self.y_max - self.y_min;
-        let x_ratio = (coords.x - self.x_min) / x_length;
-        let y_ratio = (coords.y - self.y_min) / y_length;
+        for (i, segment) in toolpath.segments.iter().enumerate() {
+            let z_start = segment.start.get(2).copied().unwrap_or(0f32);
+            let z_end = segment.end.get(2).copied().unwrap_or(0f32);
+            let start = self.map_to_screen(&Vector2::new(self.x_min + x_length * i as f32 / count, z_start));
+            let end = self.map_to_screen(&Vector2::new(self.x_min + x_length * (i + 1) as f32 / count, z_end));
+            d.draw_line_ex(start, end, segment_thickness(i, completed, running), segment_color(segment.kind, i, completed, running));
+        }
+    }
+
+    pub fn map_to_screen(&self, coords: &Vector2) -> Vector2 {
+        let x_length = self.view_x_max - self.view_x_min;
+        let y_length = self.view_y_max - self.view_y_min;
+        let x_ratio = (coords.x - self.view_x_min) / x_length;
+        let y_ratio = (coords.y - self.view_y_min) / y_length;
 
         Vector2::new(self.rect.x + self.rect.width * x_ratio, self.rect.y + self.rect.height * y_ratio)
     }
 
+    fn screen_to_view(&self, coords: &Vector2) -> Vector2 {
+        let x_length = self.view_x_max - self.view_x_min;
+        let y_length = self.view_y_max - self.view_y_min;
+
+        let x_ratio = (coords.x - self.rect.x) / self.rect.width;
+        let y_ratio = (coords.y - self.rect.y) / self.rect.height;
+
+        Vector2::new(self.view_x_min + x_length * x_ratio, self.view_y_min + y_length * y_ratio)
+    }
+
+    /// Machine position under a screen point in the area, clamped to the travel limits.
     pub fn map_to_machine(&self, coords: &Vector2) -> Option<Vector2> {
         if !self.rect.check_collision_point_rec(coords) {
             return None;
         }
 
-        let x_length = self.x_max - self.x_min;
-        let y_length = self.y_max - self.y_min;
+        let machine = self.screen_to_view(coords);
+        Some(Vector2::new(machine.x.max(self.x_min).min(self.x_max), machine.y.max(self.y_min).min(self.y_max)))
+    }
+}
 
-        let x_ratio = (coords.x - self.rect.x) / self.rect.width;
-        let y_ratio = (coords.y - self.rect.y) / self.rect.height;
+/// Smallest 1, 2 or 5 times a power of ten that is at least `min_step`.
+fn grid_step(min_step: f32) -> f32 {
+    let magnitude = 10f32.powf(min_step.log10().floor());
+    for factor in [1f32, 2f32, 5f32].iter() {
+        if magnitude * factor >= min_step {
+            return magnitude * factor;
+        }
+    }
+    magnitude * 10f32
+}
+
+fn segment_color(kind: EMoveKind, index: usize, completed: usize, running: bool) -> Color {
+    if index < completed {
+        Color::LIME
+    } else if running && index == completed {
+        Color::MAGENTA
+    } else {
+        match kind {
+            EMoveKind::ERapid => Color::LIGHTGRAY,
+            EMoveKind::EFeed => Color::BLUE,
+        }
+    }
+}
+
+/// Following error in mm drawn green, shading through yellow to red at `TRAIL_ERROR_MAX`.
+const TRAIL_ERROR_OK: f32 = 0.05f32;
+const TRAIL_ERROR_MAX: f32 = 0.5f32;
 
-        Some(Vector2::new(self.x_min + x_length * x_ratio, self.y_min + y_length * y_ratio))
+fn following_error_color(following_error: Option<f32>) -> Color {
+    let error = match following_error {
+        Some(error) => error,
+        None => return Color::DARKGRAY,
+    };
+    let t = ((error - TRAIL_ERROR_OK) / (TRAIL_ERROR_MAX - TRAIL_ERROR_OK)).clamp(0f32, 1f32);
+    if t < 0.5f32 {
+        Color::new((t * 2f32 * 255f32) as u8, 200, 0, 255)
+    } else {
+        Color::new(255, ((1f32 - t) * 2f32 * 200f32) as u8, 0, 255)
     }
 }
 
+fn segment_thickness(index: usize, completed: usize, running: bool) -> f32 {
+    if running && index == completed { 3f32 } else { 1f32 }
+}
+
 pub struct CncCtrlUi {
+    axes                    : Vec<AxisInfo>,
     target_coords           : CncCoordinates,
     current_coords          : CncCoordinates,
     cnc_target_coords       : CncCoordinates,
     cnc_area_xy             : CncAreaRect,
-    cnc_area_z              : CncAreaRect,
+    cnc_area_z              : Option<CncAreaRect>,
     current_indicator       : CncXyCoordsIndicator,
     cnc_target_indicator    : CncXyCoordsIndicator,
     target_indicator        : CncXyCoordsIndicator,
@@ -279,51 +554,82 @@ pub struct CncCtrlUi {
     cnc_target_display      : CncCoordsDisplay,
     target_display          : CncCoordsDisplay,
     rect_btn_send           : Rectangle,
+    target_inputs           : Vec<ValueInput<f32>>,
+    rect_units              : Rectangle,
+    program_input           : TextInput,
+    rect_btn_load           : Rectangle,
+    program_status          : String,
+    rect_btn_start          : Rectangle,
+    rect_btn_pause          : Rectangle,
+    rect_btn_stop           : Rectangle,
+    rect_btn_home           : Rectangle,
+    rect_btn_reset          : Rectangle,
+    rect_btn_estop          : Rectangle,
+    rect_btn_fit_machine    : Rectangle,
+    rect_btn_fit_job        : Rectangle,
+    rect_btn_clear_trail    : Rectangle,
+    rect_zoom               : Rectangle,
+    rect_program_status     : Rectangle,
+    rect_job_status         : Rectangle,
+    /// Line to resume the job at, filled in when a job is interrupted.
+    resume_line             : ValueInput<usize>,
+    resume_line_from        : Option<usize>,
+    rect_btn_prepare_resume : Rectangle,
+    rect_btn_confirm_resume : Rectangle,
+    rect_btn_cancel_resume  : Rectangle,
+    rect_resume_spindle     : Rectangle,
+    /// The user says the spindle runs, which a resume with the spindle on needs.
+    resume_spindle_started  : bool,
+    rect_resume_plan        : Rectangle,
+    /// Statistics of the loaded program and, while it runs, elapsed against estimated time.
+    rect_job_info           : Rectangle,
+    rect_btn_simulate       : Rectangle,
+    /// Simulated seconds per real second.
+    simulation_speed        : ValueInput<f32>,
+    rect_btn_simulate_instant : Rectangle,
+    rect_btn_stop_simulation : Rectangle,
+    rect_simulation_report  : Rectangle,
+    /// One per macro in the profile, up to `MAX_MACRO_BUTTONS`.
+    rect_macro_buttons      : Vec<Rectangle>,
+    macro_input             : TextInput,
+    rect_btn_import_macro   : Rectangle,
+    rect_btn_remove_macro   : Rectangle,
+    rect_macro_dry_run      : Rectangle,
+    /// Macro buttons only simulate the macro when set.
+    macro_dry_run           : bool,
+    rect_btn_stop_macro     : Rectangle,
+    rect_macro_status       : Rectangle,
+    macro_status            : String,
+    /// Whether the status line follows the machine's macro run rather than `macro_status`.
+    show_macro_run          : bool,
+    following_error_ui      : CncFollowingErrorUi,
+    /// Positions the machine actually reported, for drawing the traversed path.
+    trail                   : PositionTrail,
+    trail_events            : Option<Subscription>,
+    font_size               : f32,
 }
 
 impl CncCtrlUi {
-    pub fn new() -> CncCtrlUi {
-        let area_size = 600.0f32;
-
-        let xy_area = {
-            let mut xy_area_mut = CncAreaRect::new(area_size, 0f32, 500f32, 0f32, 500f32);
-            xy_area_mut.set_pos(50f32, 100f32);
-            xy_area_mut
-        };
-        let z_area = {
-            let mut area_mut = CncAreaRect::new(30f32, 0f32, 150f32/area_size*30f32, 0f32, 150f32);
-            area_mut.set_pos( xy_area.rect.x + xy_area.rect.width + area_size * 0.05f32, xy_area.rect.y );
-            area_mut
-        };
+    const MAX_MACRO_BUTTONS: usize = 8;
 
-        let left_align = z_area.rect.x + z_area.rect.width + area_size * 0.1f32;
-        let top_align = z_area.rect.y;
-        let rect_h = area_size * 0.15f32;
-        let vert_spacing = rect_h + area_size * 0.033f32;
-        let coords_display_w = 1200f32 - xy_area.rect.width;
-        let current_pos = {
-            let mut display_mut = CncCoordsDisplay::new("CURRENT POSITION");
-            display_mut.set_pos( Vector2::new(left_align, top_align + vert_spacing * 0.0f32) );
-            display_mut.set_size(coords_display_w, rect_h);
-            display_mut
-        };
-        let cnc_target_display = {
-            let mut display_mut = CncCoordsDisplay::new("ACTIVE TARGET");
-            display_mut.set_pos( Vector2::new(left_align, top_align + vert_spacing * 1.0f32) );
-            display_mut.set_size(coords_display_w, rect_h);
-            display_mut
-        };
-        let target_display = {
-            let mut display_mut = CncCoordsDisplay::new("NEW TARGET");
-            display_mut.set_pos( Vector2::new(left_align, top_align + vert_spacing * 2.0f32) );
-            display_mut.set_size(coords_display_w, rect_h);
-            display_mut
+    /// Creates the view for `axes`: the first two drive the XY area and a linear
+    /// third axis gets the Z strip. Any further axes only appear in the coordinate displays.
+    /// Positions are assigned by `layout` every frame.
+    pub fn new(axes: &[AxisInfo]) -> CncCtrlUi {
+        let x_axis = &axes[0];
+        let y_axis = axes.get(1).unwrap_or(x_axis);
+        let xy_area = CncAreaRect::new(600f32, x_axis.min, x_axis.max, y_axis.min, y_axis.max);
+        let z_area = match axes.get(2) {
+            Some(z_axis) if z_axis.kind == EAxisKind::ELinear => Some(CncAreaRect::new(30f32, 0f32, 1f32, z_axis.min, z_axis.max)),
+            _ => None,
         };
+        let target_inputs = (0..axes.len()).map(|_| ValueInput::new(0f32, 0f32, 0f32, 0f32, 0f32)).collect();
 
         CncCtrlUi{
-            target_coords           : CncCoordinates::new(),
-            current_coords          : CncCoordinates::new(),
-            cnc_target_coords       : CncCoordinates::new(),
+            axes                    : axes.to_vec(),
+            target_coords           : CncCoordinates::with_axes(axes.len()),
+            current_coords          : CncCoordinates::with_axes(axes.len()),
+            cnc_target_coords       : CncCoordinates::with_axes(axes.len()),
             cnc_area_xy             : xy_area,
             cnc_area_z              : z_area,
             current_indicator       : CncXyCoordsIndicator::new(20f32, Color::BLACK),
@@ -332,65 +638,584 @@ impl CncCtrlUi {
             ind_z_current           : CncZCoordIndicator::new(20f32, Color::BLACK),
             ind_z_cnc_target        : CncZCoordIndicator::new(30f32, Color::DARKGRAY),
             ind_z_target            : CncZCoordIndicator::new(40f32, Color::GRAY),
-            current_pos_display     : current_pos,
-            cnc_target_display      : cnc_target_display,
-            target_display          : target_display,
-            rect_btn_send           : Rectangle::new(left_align, top_align + vert_spacing * 3.0f32, coords_display_w * 0.3f32, rect_h * 0.75f32),
+            current_pos_display     : CncCoordsDisplay::new("CURRENT POSITION", axes),
+            cnc_target_display      : CncCoordsDisplay::new("ACTIVE TARGET", axes),
+            target_display          : CncCoordsDisplay::new("NEW TARGET", axes),
+            rect_btn_send           : Rectangle::default(),
+            target_inputs,
+            rect_units              : Rectangle::default(),
+            program_input           : TextInput::new(0f32, 0f32, 0f32, 0f32, "./data/program.gcode", 256),
+            rect_btn_load           : Rectangle::default(),
+            program_status          : String::from("No program loaded"),
+            rect_program_status     : Rectangle::default(),
+            rect_btn_start          : Rectangle::default(),
+            rect_btn_pause          : Rectangle::default(),
+            rect_btn_stop           : Rectangle::default(),
+            rect_btn_home           : Rectangle::default(),
+            rect_btn_reset          : Rectangle::default(),
+            rect_btn_estop          : Rectangle::default(),
+            rect_job_status         : Rectangle::default(),
+            resume_line             : ValueInput::new(0f32, 0f32, 0f32, 0f32, 1),
+            resume_line_from        : None,
+            rect_btn_prepare_resume : Rectangle::default(),
+            rect_btn_confirm_resume : Rectangle::default(),
+            rect_btn_cancel_resume  : Rectangle::default(),
+            rect_resume_spindle     : Rectangle::default(),
+            resume_spindle_started  : false,
+            rect_resume_plan        : Rectangle::default(),
+            rect_btn_fit_machine    : Rectangle::default(),
+            rect_btn_fit_job        : Rectangle::default(),
+            rect_btn_clear_trail    : Rectangle::default(),
+            rect_zoom               : Rectangle::default(),
+            rect_job_info           : Rectangle::default(),
+            rect_btn_simulate       : Rectangle::default(),
+            simulation_speed        : ValueInput::new(0f32, 0f32, 0f32, 0f32, 10f32),
+            rect_btn_simulate_instant : Rectangle::default(),
+            rect_btn_stop_simulation : Rectangle::default(),
+            rect_simulation_report  : Rectangle::default(),
+            rect_macro_buttons      : Vec::new(),
+            macro_input             : TextInput::new(0f32, 0f32, 0f32, 0f32, "./data/macro.txt", 256),
+            rect_btn_import_macro   : Rectangle::default(),
+            rect_btn_remove_macro   : Rectangle::default(),
+            rect_macro_dry_run      : Rectangle::default(),
+            macro_dry_run           : false,
+            rect_btn_stop_macro     : Rectangle::default(),
+            rect_macro_status       : Rectangle::default(),
+            macro_status            : String::from("Import a macro file to get a button for it"),
+            show_macro_run          : false,
+            following_error_ui      : CncFollowingErrorUi::new(axes.len()),
+            trail                   : PositionTrail::new(),
+            trail_events            : None,
+            font_size               : 20f32,
         }
     }
 
-    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+    /// Records every reported position into the trail, not just the one per frame the
+    /// controller state keeps. Runs every frame, whichever tab is shown. The trail starts
+    /// over when the axes change, whether from the profile or the controller.
+    pub fn poll(&mut self, cnc: &CncCtrl) {
+        if self.axes != cnc.axes {
+            self.trail.clear();
+        }
+        let events = self.trail_events.get_or_insert_with(|| cnc.bus.subscribe(&[EEventKind::EPosition, EEventKind::EStatus, EEventKind::EMachineInfo],
+            PositionTrail::CAPACITY));
+        for event in events.drain() {
+            match event {
+                EStatusEvent::EMachineInfo(_) => self.trail.clear(),
+                EStatusEvent::EPosition(position) => self.trail.record(&position, None),
+                EStatusEvent::EStatus(status) => {
+                    let position: Vec<f32> = status.axis_status.iter().map(|axis| axis.position).collect();
+                    // Distance off the path in mm, rotary axes in degrees don't add to it.
+                    let following_error = status.axis_status.iter().zip(self.axes.iter())
+                        .filter(|(_, info)| info.kind == EAxisKind::ELinear)
+                        .map(|(axis, _)| (axis.target_position - axis.position).powi(2)).sum::<f32>().sqrt();
+                    self.trail.record(&position, Some(following_error));
+                },
+                _ => {},
+            }
+        }
+    }
 
+    /// Recomputes every rect for the content `area`: the work area on the left, kept
+    /// at the machine's aspect ratio, and the displays and tools in a column on the right.
+    pub fn layout(&mut self, layout: &Layout, area: Rectangle, cnc: &CncCtrl) {
+        if self.axes != cnc.axes {
+            *self = CncCtrlUi::new(&cnc.axes);
+        }
+
+        let columns = layout.columns(area, &[ESize::EWeight(1f32), ESize::EFixed(20f32), ESize::EFixed(620f32)], 0f32);
+        let left = layout.rows(columns[0], &[ESize::EFixed(20f32), ESize::EWeight(1f32), ESize::EFixed(10f32), ESize::EFixed(30f32),
+            ESize::EFixed(10f32), ESize::EFixed(30f32), ESize::EFixed(10f32), ESize::EFixed(30f32), ESize::EFixed(5f32), ESize::EFixed(20f32),
+            ESize::EFixed(10f32), ESize::EFixed(30f32), ESize::EFixed(5f32), ESize::EFixed(40f32)], 0f32);
+        let view = layout.columns(left[1], &[ESize::EFixed(40f32), ESize::EWeight(1f32), ESize::EFixed(30f32), ESize::EFixed(30f32)], 0f32);
+        let xy_space = match self.cnc_area_z {
+            Some(_) => view[1],
+            None => Rectangle::new(view[1].x, view[1].y, view[3].x + view[3].width - view[1].x, view[1].height),
+        };
+        let xy_rect = layout.fit_aspect(xy_space, self.cnc_area_xy.aspect());
+        self.cnc_area_xy.set_rect(xy_rect, layout.scale);
+        if let Some(ref mut cnc_area_z) = self.cnc_area_z {
+            cnc_area_z.set_rect(Rectangle::new(xy_rect.x + xy_rect.width + layout.px(30f32), xy_rect.y, layout.px(30f32), xy_rect.height), layout.scale);
+        }
+        for (indicator, size) in [&mut self.current_indicator, &mut self.cnc_target_indicator, &mut self.target_indicator].iter_mut().zip([20f32, 30f32, 40f32].iter()) {
+            indicator.size = layout.px(*size);
+        }
+        for (indicator, size) in [&mut self.ind_z_current, &mut self.ind_z_cnc_target, &mut self.ind_z_target].iter_mut().zip([20f32, 30f32, 40f32].iter()) {
+            indicator.size = layout.px(*size);
+        }
+
+        let view_tools = layout.columns(left[3], &[ESize::EFixed(140f32), ESize::EFixed(140f32), ESize::EFixed(140f32), ESize::EWeight(1f32)], 10f32);
+        self.rect_btn_fit_machine = view_tools[0];
+        self.rect_btn_fit_job = view_tools[1];
+        self.rect_btn_clear_trail = view_tools[2];
+        self.rect_zoom = view_tools[3];
+
+        let macro_count = cnc.profile.macros.len().clamp(1, CncCtrlUi::MAX_MACRO_BUTTONS);
+        self.rect_macro_buttons = layout.columns(left[5], &vec![ESize::EWeight(1f32); macro_count], 10f32);
+        self.rect_macro_buttons.truncate(cnc.profile.macros.len());
+        let macro_tools = layout.columns(left[7], &[ESize::EWeight(1f32), ESize::EFixed(110f32), ESize::EFixed(110f32),
+            ESize::EFixed(110f32), ESize::EFixed(140f32)], 10f32);
+        self.macro_input.rect = macro_tools[0];
+        self.rect_btn_import_macro = macro_tools[1];
+        self.rect_btn_remove_macro = macro_tools[2];
+        self.rect_macro_dry_run = macro_tools[3];
+        self.rect_btn_stop_macro = macro_tools[4];
+        self.rect_macro_status = left[9];
+
+        let resume_row = layout.columns(left[11], &[ESize::EFixed(100f32), ESize::EFixed(200f32), ESize::EFixed(200f32),
+            ESize::EFixed(110f32), ESize::EFixed(160f32), ESize::EWeight(1f32)], 10f32);
+        self.resume_line.rect = resume_row[0];
+        self.rect_btn_prepare_resume = resume_row[1];
+        self.rect_btn_confirm_resume = resume_row[2];
+        self.rect_btn_cancel_resume = resume_row[3];
+        self.rect_resume_spindle = resume_row[4];
+        self.rect_resume_plan = left[13];
+
+        let right = layout.rows(columns[2], &[ESize::EFixed(90f32), ESize::EFixed(20f32), ESize::EFixed(90f32), ESize::EFixed(20f32),
+            ESize::EFixed(90f32), ESize::EFixed(20f32), ESize::EFixed(68f32), ESize::EFixed(20f32), ESize::EFixed(40f32), ESize::EFixed(20f32),
+            ESize::EFixed(40f32), ESize::EFixed(10f32), ESize::EFixed(20f32), ESize::EFixed(10f32), ESize::EFixed(40f32), ESize::EFixed(10f32),
+            ESize::EFixed(20f32), ESize::EFixed(10f32), ESize::EFixed(40f32), ESize::EFixed(5f32), ESize::EFixed(40f32),
+            ESize::EFixed(10f32), ESize::EFixed(60f32), ESize::EWeight(1f32)], 0f32);
+        for (display, row) in [&mut self.current_pos_display, &mut self.cnc_target_display, &mut self.target_display].iter_mut().zip([right[0], right[2], right[4]].iter()) {
+            display.set_pos(Vector2::new(row.x, row.y));
+            display.set_size(row.width, row.height);
+        }
+
+        let send_row = layout.columns(right[6], &[ESize::EWeight(0.3f32), ESize::EWeight(0.05f32), ESize::EWeight(0.65f32)], 0f32);
+        self.rect_btn_send = send_row[0];
+        let inputs = layout.columns(send_row[2], &vec![ESize::EWeight(1f32); self.target_inputs.len().max(1)], 5f32);
+        for (input, rect) in self.target_inputs.iter_mut().zip(inputs.iter()) {
+            input.rect = *rect;
+        }
+
+        let machine_row = layout.columns(right[8], &[ESize::EFixed(200f32), ESize::EWeight(1f32), ESize::EFixed(110f32),
+            ESize::EFixed(110f32), ESize::EFixed(140f32)], 10f32);
+        self.rect_units = machine_row[0];
+        self.rect_btn_home = machine_row[2];
+        self.rect_btn_reset = machine_row[3];
+        self.rect_btn_estop = machine_row[4];
+        let program_row = layout.columns(right[10], &[ESize::EWeight(0.65f32), ESize::EWeight(0.05f32), ESize::EWeight(0.3f32)], 0f32);
+        self.program_input.rect = program_row[0];
+        self.rect_btn_load = program_row[2];
+        self.rect_program_status = right[12];
+        let job_row = layout.columns(right[14], &[ESize::EWeight(1f32), ESize::EWeight(1f32), ESize::EWeight(1f32)], 30f32);
+        self.rect_btn_start = job_row[0];
+        self.rect_btn_pause = job_row[1];
+        self.rect_btn_stop = job_row[2];
+        self.rect_job_status = right[16];
+        let simulation_row = layout.columns(right[18], &[ESize::EWeight(1f32), ESize::EFixed(80f32), ESize::EWeight(1f32), ESize::EWeight(1f32)], 10f32);
+        self.rect_btn_simulate = simulation_row[0];
+        self.simulation_speed.rect = simulation_row[1];
+        self.rect_btn_simulate_instant = simulation_row[2];
+        self.rect_btn_stop_simulation = simulation_row[3];
+        self.rect_simulation_report = right[20];
+        self.rect_job_info = right[22];
+        let following_error_area = layout.rows(right[23], &[ESize::EFixed(20f32), ESize::EWeight(1f32)], 0f32)[1];
+        self.following_error_ui.layout(layout, following_error_area);
+        self.font_size = layout.px(20f32);
+    }
+
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        self.cnc_area_xy.handle_input(d);
         if d.is_mouse_button_down(MouseButton::MOUSE_LEFT_BUTTON) {
             let mouse_pos = d.get_mouse_position();
             if let Some(machine_coords) = self.cnc_area_xy.map_to_machine(&mouse_pos) {
-                self.target_coords.x = machine_coords.x;
-                self.target_coords.y = machine_coords.y;
+                self.target_coords.set(0, machine_coords.x);
+                self.target_coords.set(1, machine_coords.y);
+            }
+            if let Some(ref cnc_area_z) = self.cnc_area_z {
+                if let Some(machine_coords) = cnc_area_z.map_to_machine(&mouse_pos) {
+                    self.target_coords.set(2, machine_coords.y);
+                }
             }
-            if let Some(machine_coords) = self.cnc_area_z.map_to_machine(&mouse_pos) {
-                self.target_coords.z = machine_coords.y;
+        }
+
+        self.current_indicator.pos = self.cnc_area_xy.map_to_screen(&Vector2::new(self.current_coords.x(), self.current_coords.y()) );
+        self.cnc_target_indicator.pos = self.cnc_area_xy.map_to_screen(&Vector2::new(self.cnc_target_coords.x(), self.cnc_target_coords.y()) );
+        self.target_indicator.pos = self.cnc_area_xy.map_to_screen(&Vector2::new(self.target_coords.x(), self.target_coords.y()) );
+
+        if let Some(ref cnc_area_z) = self.cnc_area_z {
+            self.ind_z_current.pos = cnc_area_z.map_to_screen(&Vector2::new(cnc_area_z.x_max, self.current_coords.z()) );
+            self.ind_z_cnc_target.pos = cnc_area_z.map_to_screen(&Vector2::new(cnc_area_z.x_max, self.cnc_target_coords.z()) );
+            self.ind_z_target.pos = cnc_area_z.map_to_screen(&Vector2::new(cnc_area_z.x_max, self.target_coords.z()) );
+        }
+
+
+        for (axis, input) in self.target_inputs.iter_mut().enumerate() {
+            let before = input.value;
+            input.update(d);
+            if input.edit_mode {
+                if input.value != before {
+                    self.target_coords.set(axis, cnc.display_to_machine(axis, input.value));
+                }
+            } else {
+                input.set_value(cnc.to_display(axis, self.target_coords.get(axis)));
             }
         }
 
-        self.current_indicator.pos = self.cnc_area_xy.map_to_screen(&Vector2::new(self.current_coords.x, self.current_coords.y) );
-        self.cnc_target_indicator.pos = self.cnc_area_xy.map_to_screen(&Vector2::new(self.cnc_target_coords.x, self.cnc_target_coords.y) );
-        self.target_indicator.pos = self.cnc_area_xy.map_to_screen(&Vector2::new(self.target_coords.x, self.target_coords.y) );
+        let units_active = match cnc.display_units {
+            EUnits::EMillimeters => 0,
+            EUnits::EInches => 1,
+        };
+        let units_selected = d.gui_toggle_group(self.rect_units, Some(rstr!("MM;INCH")), units_active);
+        if units_selected != units_active {
+            cnc.set_display_units(if units_selected == 1 { EUnits::EInches } else { EUnits::EMillimeters });
+        }
 
-        self.ind_z_current.pos = self.cnc_area_z.map_to_screen(&Vector2::new(self.cnc_area_z.x_max, self.current_coords.z) );
-        self.ind_z_cnc_target.pos = self.cnc_area_z.map_to_screen(&Vector2::new(self.cnc_area_z.x_max, self.cnc_target_coords.z) );
-        self.ind_z_target.pos = self.cnc_area_z.map_to_screen(&Vector2::new(self.cnc_area_z.x_max, self.target_coords.z) );
+        self.program_input.update(d);
+        if d.gui_button(self.rect_btn_load, Some(rstr!("LOAD PROGRAM"))) {
+            self.program_status = match cnc.load_program(&self.program_input.text()) {
+                Ok(()) => {
+                    if let Some(ref toolpath) = cnc.toolpath {
+                        self.cnc_area_xy.fit_toolpath(toolpath);
+                    }
+                    String::new()
+                },
+                Err(e) => e,
+            };
+        }
+        if let Some(ref program) = cnc.program {
+            if self.program_status.is_empty() {
+                self.program_status = format!("{}: {} blocks", program.name, program.lines.len());
+            }
+        }
+        d.draw_text_ex(font, self.program_status.as_str(), Vector2::new(self.rect_program_status.x, self.rect_program_status.y),
+            self.font_size, 0f32, Color::DARKGRAY);
 
+        self.draw_job_controls(d, font, cnc);
+        self.draw_simulation_controls(d, font, cnc);
+        self.draw_job_info(d, font, cnc);
+        self.draw_resume_controls(d, font, cnc);
 
-        if d.gui_button(self.rect_btn_send, Some(rstr!("SEND"))) {
-            cnc.set_target_coords(self.target_coords.clone() );
+        if gui_button_enabled(d, self.rect_btn_send, rstr!("SEND"), cnc.allows(ECommand::EJog)) {
+            if let Err(e) = cnc.set_target_coords(self.target_coords.clone() ) {
+                self.program_status = e;
+            }
         }
+        self.draw_machine_controls(d, cnc);
+        self.draw_macro_controls(d, font, cnc);
 
-        self.set_current_coords(cnc.current_coords.x, cnc.current_coords.y, cnc.current_coords.z);
+        self.current_coords = cnc.current_coords.clone();
 
         self.cnc_target_coords = cnc.get_target_coords();
         
-        self.current_pos_display.set_coords(self.current_coords.clone());
-        self.cnc_target_display.set_coords(self.cnc_target_coords.clone());
-        self.target_display.set_coords(self.target_coords.clone());
+        self.current_pos_display.set_coords(self.current_coords.clone(), cnc);
+        self.cnc_target_display.set_coords(self.cnc_target_coords.clone(), cnc);
+        self.target_display.set_coords(self.target_coords.clone(), cnc);
 
 
+        if d.gui_button(self.rect_btn_fit_machine, Some(rstr!("FIT MACHINE"))) {
+            self.cnc_area_xy.fit_machine();
+        }
+        if d.gui_button(self.rect_btn_fit_job, Some(rstr!("FIT JOB"))) {
+            if let Some(ref toolpath) = cnc.toolpath {
+                self.cnc_area_xy.fit_toolpath(toolpath);
+            }
+        }
+        if d.gui_button(self.rect_btn_clear_trail, Some(rstr!("CLEAR TRAIL"))) {
+            self.trail.clear();
+        }
+        let zoom_label = format!("ZOOM {:.0}%", self.cnc_area_xy.zoom() * 100f32);
+        d.draw_text_ex(font, zoom_label.as_str(), Vector2::new(self.rect_zoom.x, self.rect_zoom.y + self.font_size * 0.25f32),
+            self.font_size, 0f32, Color::DARKGRAY);
+
         self.cnc_area_xy.draw(d, font);
-        self.cnc_area_z.draw(d, font);
-        self.current_indicator.draw(d);
-        self.cnc_target_indicator.draw(d);
-        self.target_indicator.draw(d);
-        self.ind_z_current.draw(d);
-        self.ind_z_cnc_target.draw(d);
-        self.ind_z_target.draw(d);
+        let simulation = cnc.simulation.as_ref().filter(|_| !cnc.job.is_active());
+        let running = cnc.job.is_active() || simulation.is_some();
+        let completed = match (simulation, &cnc.toolpath) {
+            (Some(simulation), _) => simulation.completed_segments(),
+            (None, Some(toolpath)) => cnc.job.completed_segments(toolpath),
+            (None, None) => 0,
+        };
+        let simulated_position = match (simulation, &cnc.toolpath) {
+            (Some(simulation), Some(toolpath)) => simulation.position(toolpath),
+            _ => None,
+        };
+        let marker_size = self.font_size * 0.4f32;
+        {
+            let area = self.cnc_area_xy.rect;
+            let mut d = d.begin_scissor_mode(area.x as i32, area.y as i32, area.width as i32, area.height as i32);
+            self.cnc_area_xy.draw_grid(&mut d);
+            if let Some(ref toolpath) = cnc.toolpath {
+                self.cnc_area_xy.draw_toolpath(&mut d, toolpath, completed, running);
+            }
+            self.cnc_area_xy.draw_trail(&mut d, &self.trail);
+            if let Some(ref position) = simulated_position {
+                let marker = self.cnc_area_xy.map_to_screen(&Vector2::new(position[0], position.get(1).copied().unwrap_or(0f32)));
+                d.draw_circle_v(marker, marker_size, Color::ORANGE);
+            }
+            self.current_indicator.draw(&mut d);
+            self.cnc_target_indicator.draw(&mut d);
+            self.target_indicator.draw(&mut d);
+        }
+        self.cnc_area_xy.draw_rulers(d, font);
+        if let Some(ref mut cnc_area_z) = self.cnc_area_z {
+            cnc_area_z.draw(d, font);
+            if let Some(ref toolpath) = cnc.toolpath {
+                cnc_area_z.draw_z_profile(d, toolpath, completed, running);
+            }
+            if let Some(z) = simulated_position.as_ref().and_then(|position| position.get(2)) {
+                let marker = cnc_area_z.map_to_screen(&Vector2::new((cnc_area_z.x_min + cnc_area_z.x_max) / 2f32, *z));
+                d.draw_circle_v(marker, marker_size, Color::ORANGE);
+            }
+            self.ind_z_current.draw(d);
+            self.ind_z_cnc_target.draw(d);
+            self.ind_z_target.draw(d);
+        }
 
         self.current_pos_display.draw(d, font);
         self.cnc_target_display.draw(d, font);
         self.target_display.draw(d, font);
+        self.following_error_ui.draw(d, font, cnc);
     }
 
-    pub fn set_current_coords(&mut self, x: f32, y: f32, z: f32) {
-        self.current_coords.x = x;
-        self.current_coords.y = y;
-        self.current_coords.z = z;
+    fn draw_machine_controls(&mut self, d: &mut RaylibDrawHandle, cnc: &mut CncCtrl) {
+        let mut result = Ok(());
+        if gui_button_enabled(d, self.rect_btn_home, rstr!("HOME"), cnc.allows(ECommand::EHome)) {
+            result = cnc.home();
+        }
+        if gui_button_enabled(d, self.rect_btn_reset, rstr!("RESET"), cnc.allows(ECommand::EReset)) {
+            result = cnc.reset();
+        }
+        if gui_button_enabled(d, self.rect_btn_estop, rstr!("E-STOP"), cnc.allows(ECommand::EEStop)) {
+            result = cnc.emergency_stop();
+        }
+        if cnc.allows(ECommand::EEStop) {
+            d.draw_rectangle_lines_ex(self.rect_btn_estop, (self.font_size * 0.2f32).max(2f32) as i32, Color::RED);
+        }
+        if let Err(e) = result {
+            self.program_status = e;
+        }
     }
-}
\ No newline at end of file
+
+    fn draw_macro_controls(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        let mut result = Ok(());
+        let can_run = self.macro_dry_run || (cnc.allows(ECommand::ERunMacro) && !cnc.is_macro_running());
+        let names: Vec<String> = cnc.profile.macros.iter().map(|m| m.name.clone()).collect();
+        for (name, rect) in names.iter().zip(self.rect_macro_buttons.iter()) {
+            let label = CString::new(name.to_uppercase()).unwrap_or_default();
+            if !gui_button_enabled(d, *rect, &label, can_run) {
+                continue;
+            }
+            if self.macro_dry_run {
+                self.show_macro_run = false;
+                self.macro_status = match cnc.dry_run_macro(name) {
+                    Ok(report) => report.summary(),
+                    Err(e) => e,
+                };
+            } else {
+                self.show_macro_run = true;
+                result = cnc.run_macro(name);
+            }
+        }
+
+        self.macro_input.update(d);
+        if d.gui_button(self.rect_btn_import_macro, Some(rstr!("IMPORT"))) {
+            result = MacroDef::import(&self.macro_input.text()).and_then(|definition| {
+                let name = definition.name.clone();
+                cnc.save_macro(definition)?;
+                self.show_macro_run = false;
+                self.macro_status = format!("Imported macro {}", name);
+                Ok(())
+            });
+        }
+        if d.gui_button(self.rect_btn_remove_macro, Some(rstr!("REMOVE"))) {
+            match MacroDef::import(&self.macro_input.text()) {
+                Ok(definition) if names.contains(&definition.name) => cnc.remove_macro(&definition.name),
+                _ => result = Err(String::from("The file doesn't name a stored macro")),
+            }
+        }
+        self.macro_dry_run = d.gui_toggle(self.rect_macro_dry_run, Some(rstr!("DRY RUN")), self.macro_dry_run);
+        if gui_button_enabled(d, self.rect_btn_stop_macro, rstr!("STOP MACRO"), cnc.is_macro_running()) {
+            cnc.stop_macro();
+        }
+        if let Err(e) = result {
+            self.show_macro_run = false;
+            self.macro_status = e;
+        }
+
+        let status = match cnc.macro_run {
+            Some(ref run) if self.show_macro_run => match run.state {
+                EMacroState::ERunning => format!("Macro {} at line {}", run.name(), run.line()),
+                EMacroState::EFinished => format!("Macro {} finished", run.name()),
+                EMacroState::EStopped => format!("Macro {} stopped at line {}", run.name(), run.line()),
+                EMacroState::EFailed(ref e) => format!("Macro {} failed at {}", run.name(), e),
+            },
+            _ => self.macro_status.clone(),
+        };
+        d.draw_text_ex(font, status.as_str(), Vector2::new(self.rect_macro_status.x, self.rect_macro_status.y),
+            self.font_size, 0f32, Color::DARKGRAY);
+    }
+
+    fn draw_resume_controls(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        if cnc.interrupted_line != self.resume_line_from {
+            self.resume_line_from = cnc.interrupted_line;
+            if let Some(line) = cnc.interrupted_line {
+                self.resume_line.set_value(line);
+            }
+        }
+        self.resume_line.update(d);
+        let mut result = Ok(());
+        let can_start = cnc.allows(ECommand::EStartJob) && cnc.toolpath.is_some();
+        if gui_button_enabled(d, self.rect_btn_prepare_resume, rstr!("START FROM LINE"), can_start) {
+            result = cnc.prepare_resume(self.resume_line.value);
+            self.resume_spindle_started = false;
+        }
+        let needs_spindle = cnc.pending_resume.as_ref().is_some_and(|plan| plan.needs_spindle());
+        if needs_spindle {
+            self.resume_spindle_started = d.gui_toggle(self.rect_resume_spindle, Some(rstr!("SPINDLE IS ON")), self.resume_spindle_started);
+        }
+        let acknowledged = !needs_spindle || self.resume_spindle_started;
+        if gui_button_enabled(d, self.rect_btn_confirm_resume, rstr!("CONFIRM RESUME"), can_start && cnc.pending_resume.is_some() && acknowledged) {
+            result = cnc.confirm_resume(self.resume_spindle_started);
+        }
+        if gui_button_enabled(d, self.rect_btn_cancel_resume, rstr!("CANCEL"), cnc.pending_resume.is_some()) {
+            cnc.cancel_resume();
+        }
+        if let Err(e) = result {
+            self.program_status = e;
+        }
+
+        let lines = match cnc.pending_resume {
+            Some(ref plan) => {
+                let block = cnc.toolpath.as_ref().and_then(|toolpath| toolpath.segments.get(plan.first_segment))
+                    .and_then(|segment| cnc.program.as_ref().and_then(|program| program.lines.get(segment.block_index)))
+                    .map_or("", |line| line.text.as_str());
+                let approach: Vec<String> = plan.approach.iter().map(|(kind, target)| {
+                    let coords: Vec<String> = target.iter().enumerate()
+                        .map(|(axis, value)| format!("{} {}", cnc.axis_name(axis), cnc.format_axis_value(axis, *value))).collect();
+                    format!("{} to {}", kind.name(), coords.join(" "))
+                }).collect();
+                let spindle_note = match plan.needs_spindle() {
+                    true => ", start the spindle by hand first",
+                    false => "",
+                };
+                vec![
+                    format!("Line {}: {} with {}", plan.line_number, block, plan.modal.describe()),
+                    format!("{}{}", approach.join(", "), spindle_note),
+                ]
+            },
+            None => match cnc.interrupted_line {
+                Some(line) => vec![format!("Job interrupted at line {}", line)],
+                None => Vec::new(),
+            },
+        };
+        for (i, line) in lines.iter().enumerate() {
+            d.draw_text_ex(font, line.as_str(), Vector2::new(self.rect_resume_plan.x, self.rect_resume_plan.y + i as f32 * self.font_size),
+                self.font_size, 0f32, if cnc.pending_resume.is_some() { Color::MAROON } else { Color::DARKGRAY });
+        }
+    }
+
+    fn draw_job_info(&self, d: &mut RaylibDrawHandle, font: &Font, cnc: &CncCtrl) {
+        let (stats, toolpath) = match (&cnc.job_stats, &cnc.toolpath) {
+            (Some(stats), Some(toolpath)) => (stats, toolpath),
+            _ => return,
+        };
+        let distance = |value: f32| cnc.display_units.format(EAxisKind::ELinear, value);
+        let bounds: Vec<String> = stats.min.iter().zip(stats.max.iter()).enumerate()
+            .map(|(axis, (min, max))| format!("{} {} to {}", cnc.axis_name(axis), cnc.format_axis_value(axis, *min), cnc.format_axis_value(axis, *max)))
+            .collect();
+        let timing = if cnc.job.state == EJobState::EIdle {
+            format!("Estimated run time {}", format_duration(stats.estimated_duration))
+        } else {
+            let elapsed = cnc.job.elapsed().as_secs_f32();
+            let expected = stats.estimated_until(cnc.job.completed_segments(toolpath));
+            let remaining = stats.estimated_duration - expected;
+            format!("Elapsed {}, estimated {} for the same progress ({:+.0}%), about {} left of {}", format_duration(elapsed),
+                format_duration(expected), if expected > 0f32 { (elapsed / expected - 1f32) * 100f32 } else { 0f32 },
+                format_duration(remaining), format_duration(stats.estimated_duration))
+        };
+        let lines = [
+            format!("{} moves, {} tool changes, cut {}, rapid {}", stats.moves, stats.tool_changes, distance(stats.cut_length),
+                distance(stats.rapid_length)),
+            bounds.join(", "),
+            timing,
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            d.draw_text_ex(font, line.as_str(), Vector2::new(self.rect_job_info.x, self.rect_job_info.y + i as f32 * self.font_size),
+                self.font_size, 0f32, Color::DARKGRAY);
+        }
+    }
+
+    fn draw_simulation_controls(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        let mut result = Ok(());
+        let can_simulate = cnc.allows(ECommand::ESimulateJob) && cnc.toolpath.is_some();
+        if gui_button_enabled(d, self.rect_btn_simulate, rstr!("SIMULATE x"), can_simulate) {
+            result = cnc.simulate_job(Some(self.simulation_speed.value.max(0.1f32)));
+        }
+        self.simulation_speed.update(d);
+        if gui_button_enabled(d, self.rect_btn_simulate_instant, rstr!("INSTANT"), can_simulate) {
+            result = cnc.simulate_job(None);
+        }
+        if gui_button_enabled(d, self.rect_btn_stop_simulation, rstr!("CLEAR SIM"), cnc.simulation.is_some()) {
+            cnc.stop_simulation();
+        }
+        if let Err(e) = result {
+            self.program_status = e;
+        }
+
+        let simulation = match cnc.simulation {
+            Some(ref simulation) => simulation,
+            None => return,
+        };
+        let report = &simulation.report;
+        let extents: Vec<String> = report.stats.min.iter().zip(report.stats.max.iter()).enumerate()
+            .map(|(axis, (min, max))| format!("{} {} to {}", cnc.axis_name(axis), cnc.format_axis_value(axis, *min), cnc.format_axis_value(axis, *max)))
+            .collect();
+        let progress = if simulation.is_finished() {
+            format!("Simulated {}", format_duration(simulation.duration()))
+        } else {
+            format!("Simulating {} of {}", format_duration(simulation.elapsed), format_duration(simulation.duration()))
+        };
+        let violations = match report.violations.first() {
+            Some(first) => format!("{} soft-limit violations, first at line {}: {} {}", report.violations.len(), first.line_number,
+                cnc.axis_name(first.axis), cnc.format_axis_value(first.axis, first.value)),
+            None => String::from("within the soft limits"),
+        };
+        let distance = |value: f32| cnc.display_units.format(EAxisKind::ELinear, value);
+        let lines = [
+            format!("{}, {}", progress, extents.join(", ")),
+            format!("Rapid {}, feed {}, {}", distance(report.stats.rapid_length), distance(report.stats.cut_length), violations),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let color = if i == 1 && !report.violations.is_empty() { Color::RED } else { Color::DARKGRAY };
+            d.draw_text_ex(font, line.as_str(), Vector2::new(self.rect_simulation_report.x, self.rect_simulation_report.y + i as f32 * self.font_size),
+                self.font_size, 0f32, color);
+        }
+    }
+
+    fn draw_job_controls(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        let mut result = Ok(());
+        if gui_button_enabled(d, self.rect_btn_start, rstr!("START JOB"), cnc.allows(ECommand::EStartJob) && cnc.toolpath.is_some()) {
+            result = cnc.start_job();
+        }
+        let paused = cnc.job.state == EJobState::EPaused;
+        let pause_command = if paused { ECommand::EResumeJob } else { ECommand::EPauseJob };
+        if gui_button_enabled(d, self.rect_btn_pause, if paused { rstr!("RESUME") } else { rstr!("PAUSE") }, cnc.allows(pause_command)) {
+            result = if paused { cnc.resume_job() } else { cnc.pause_job() };
+        }
+        if gui_button_enabled(d, self.rect_btn_stop, rstr!("STOP"), cnc.allows(ECommand::EStopJob)) {
+            result = cnc.stop_job();
+        }
+        if let Err(e) = result {
+            self.program_status = e;
+        }
+
+        let job_status = match (&cnc.toolpath, &cnc.program) {
+            (Some(toolpath), Some(program)) => {
+                let completed = cnc.job.completed_segments(toolpath);
+                let line = match toolpath.segments.get(completed) {
+                    Some(segment) if cnc.job.is_active() => {
+                        let text = program.lines.get(segment.block_index).map(|line| line.text.as_str()).unwrap_or("");
+                        format!("line {}: {} (F{:.0})", segment.line_number, text, segment.feed)
+                    },
+                    _ => String::new(),
+                };
+                format!("{:?} {}/{} {}", cnc.job.state, completed, toolpath.segments.len(), line)
+            },
+            _ => String::new(),
+        };
+        d.draw_text_ex(font, job_status.as_str(), Vector2::new(self.rect_job_status.x, self.rect_job_status.y),
+            self.font_size, 0f32, Color::DARKGRAY);
+    }
+}
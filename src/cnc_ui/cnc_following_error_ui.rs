@@ -0,0 +1,133 @@
+use raylib::prelude::*;
+
+use cnc_desktop::cnc_ctrl::CncCtrl;
+use cnc_desktop::cnc_following_error::{AxisFollowingError, EFollowingErrorLevel, FollowingErrorLimits};
+
+use super::cnc_config_ui::ValueInput;
+use super::cnc_layout::{ESize, Layout};
+
+fn level_color(level: EFollowingErrorLevel) -> Color {
+    match level {
+        EFollowingErrorLevel::EOk => Color::LIME,
+        EFollowingErrorLevel::EWarning => Color::ORANGE,
+        EFollowingErrorLevel::EFault => Color::RED,
+    }
+}
+
+/// Per axis following error gauges with the warning and fault thresholds of the profile.
+pub struct CncFollowingErrorUi {
+    rect_title          : Rectangle,
+    rect_gauges         : Vec<Rectangle>,
+    rect_names          : Vec<Rectangle>,
+    warning_inputs      : Vec<ValueInput<f32>>,
+    fault_inputs        : Vec<ValueInput<f32>>,
+    rect_btn_apply      : Rectangle,
+    rect_btn_clear_peaks: Rectangle,
+    error               : String,
+    /// Whether the inputs hold the profile thresholds yet.
+    loaded              : bool,
+    font_size           : f32,
+}
+
+impl CncFollowingErrorUi {
+    pub fn new(axis_count: usize) -> Self {
+        CncFollowingErrorUi{
+            rect_title          : Rectangle::default(),
+            rect_gauges         : vec![Rectangle::default(); axis_count],
+            rect_names          : vec![Rectangle::default(); axis_count],
+            warning_inputs      : (0..axis_count).map(|_| ValueInput::new(0f32, 0f32, 0f32, 0f32, 0f32)).collect(),
+            fault_inputs        : (0..axis_count).map(|_| ValueInput::new(0f32, 0f32, 0f32, 0f32, 0f32)).collect(),
+            rect_btn_apply      : Rectangle::default(),
+            rect_btn_clear_peaks: Rectangle::default(),
+            error               : String::new(),
+            loaded              : false,
+            font_size           : 20f32,
+        }
+    }
+
+    pub fn layout(&mut self, layout: &Layout, area: Rectangle) {
+        let axis_count = self.rect_gauges.len();
+        let mut sizes = vec![ESize::EFixed(26f32)];
+        sizes.extend(vec![ESize::EFixed(34f32); axis_count]);
+        sizes.push(ESize::EFixed(36f32));
+        let rows = layout.rows(area, &sizes, 4f32);
+        self.rect_title = rows[0];
+        for axis in 0..axis_count {
+            let columns = layout.columns(rows[axis + 1], &[ESize::EFixed(40f32), ESize::EWeight(1f32), ESize::EFixed(90f32), ESize::EFixed(90f32)], 8f32);
+            self.rect_names[axis] = columns[0];
+            self.rect_gauges[axis] = columns[1];
+            self.warning_inputs[axis].rect = columns[2];
+            self.fault_inputs[axis].rect = columns[3];
+        }
+        let buttons = layout.columns(rows[axis_count + 1], &[ESize::EWeight(1f32), ESize::EFixed(160f32), ESize::EFixed(188f32)], 8f32);
+        self.rect_btn_clear_peaks = buttons[1];
+        self.rect_btn_apply = buttons[2];
+        self.font_size = layout.px(18f32);
+    }
+
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &mut CncCtrl) {
+        let limits = cnc.following_error_limits();
+        if !self.loaded {
+            for ((warning, fault), axis_limits) in self.warning_inputs.iter_mut().zip(self.fault_inputs.iter_mut()).zip(limits.iter()) {
+                warning.set_value(axis_limits.warning);
+                fault.set_value(axis_limits.fault);
+            }
+            self.loaded = true;
+        }
+
+        let title_color = match cnc.following_error.level() {
+            EFollowingErrorLevel::EOk => Color::DARKGRAY,
+            level => level_color(level),
+        };
+        d.draw_text_ex(font, "FOLLOWING ERROR", Vector2::new(self.rect_title.x, self.rect_title.y), self.font_size, 0f32, title_color);
+        let warn_x = self.warning_inputs.first().map_or(self.rect_title.x, |input| input.rect.x);
+        let fault_x = self.fault_inputs.first().map_or(self.rect_title.x, |input| input.rect.x);
+        d.draw_text_ex(font, "WARN", Vector2::new(warn_x, self.rect_title.y), self.font_size, 0f32, Color::DARKGRAY);
+        d.draw_text_ex(font, "FAULT", Vector2::new(fault_x, self.rect_title.y), self.font_size, 0f32, Color::DARKGRAY);
+
+        for (axis, axis_limits) in limits.iter().enumerate() {
+            let name = cnc.axis_name(axis);
+            let rect_name = self.rect_names[axis];
+            d.draw_text_ex(font, name.as_str(), Vector2::new(rect_name.x, rect_name.y + (rect_name.height - self.font_size) / 2f32), self.font_size, 0f32, Color::BLACK);
+            if let Some(axis_error) = cnc.following_error.axes.get(axis) {
+                self.draw_gauge(d, font, self.rect_gauges[axis], axis_error, axis_limits);
+            }
+            self.warning_inputs[axis].update(d);
+            self.fault_inputs[axis].update(d);
+        }
+
+        if d.gui_button(self.rect_btn_clear_peaks, Some(rstr!("CLEAR PEAKS"))) {
+            cnc.following_error.reset();
+        }
+        if d.gui_button(self.rect_btn_apply, Some(rstr!("SET THRESHOLDS"))) {
+            let new_limits = self.warning_inputs.iter().zip(self.fault_inputs.iter())
+                .map(|(warning, fault)| FollowingErrorLimits{ warning: warning.value, fault: fault.value })
+                .collect();
+            self.error = match cnc.set_following_error_limits(new_limits) {
+                Ok(()) => String::new(),
+                Err(e) => e,
+            };
+        }
+        if !self.error.is_empty() {
+            d.draw_text_ex(font, self.error.as_str(), Vector2::new(self.rect_title.x, self.rect_btn_apply.y + self.rect_btn_apply.height + self.font_size * 0.3f32),
+                self.font_size, 0f32, Color::RED);
+        }
+    }
+
+    /// Bar of the current error scaled to a bit beyond the fault threshold, with a tick
+    /// for the recent maximum and lines at the thresholds.
+    fn draw_gauge(&self, d: &mut RaylibDrawHandle, font: &Font, rect: Rectangle, axis_error: &AxisFollowingError, limits: &FollowingErrorLimits) {
+        let current = axis_error.current;
+        let recent_max = axis_error.recent_max();
+        let full_scale = limits.fault * 1.25f32;
+        let x_of = |value: f32| rect.x + rect.width * (value / full_scale).clamp(0f32, 1f32);
+        d.draw_rectangle_rec(rect, Color::RAYWHITE);
+        d.draw_rectangle_rec(Rectangle::new(rect.x, rect.y, x_of(current) - rect.x, rect.height), level_color(axis_error.level));
+        d.draw_line_ex(Vector2::new(x_of(recent_max), rect.y), Vector2::new(x_of(recent_max), rect.y + rect.height), 2f32, Color::DARKGRAY);
+        d.draw_line_ex(Vector2::new(x_of(limits.warning), rect.y), Vector2::new(x_of(limits.warning), rect.y + rect.height), 2f32, Color::ORANGE);
+        d.draw_line_ex(Vector2::new(x_of(limits.fault), rect.y), Vector2::new(x_of(limits.fault), rect.y + rect.height), 2f32, Color::RED);
+        d.draw_rectangle_lines_ex(rect, 1, Color::GRAY);
+        let text = format!("{:.3}  peak {:.3}", current, axis_error.peak);
+        d.draw_text_ex(font, text.as_str(), Vector2::new(rect.x + self.font_size * 0.3f32, rect.y + (rect.height - self.font_size) / 2f32), self.font_size, 0f32, Color::BLACK);
+    }
+}
@@ -0,0 +1,127 @@
+use raylib::prelude::*;
+
+/// Size of one slot in a row or column layout.
+#[derive(Clone, Copy, Debug)]
+pub enum ESize {
+    /// Design pixels, multiplied by the layout scale.
+    EFixed(f32),
+    /// Share of the space left after fixed slots and spacing.
+    EWeight(f32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum EAnchor {
+    ETopLeft,
+    ETopRight,
+}
+
+/// Window size dependent layout. Widget sizes are given in design pixels for a
+/// `DESIGN_WIDTH` x `DESIGN_HEIGHT` window and scaled to the actual window each frame.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub scale       : f32,
+    pub screen      : Rectangle,
+}
+
+impl Layout {
+    pub const DESIGN_WIDTH: f32 = 1600f32;
+    pub const DESIGN_HEIGHT: f32 = 960f32;
+    const MIN_SCALE: f32 = 0.5f32;
+    const MAX_SCALE: f32 = 4f32;
+    /// The smallest design text (`SMALLEST_TEXT` pixels) is kept at least
+    /// `MIN_TEXT_POINTS` tall, so text stays readable on dense displays.
+    const MIN_TEXT_POINTS: f32 = 7f32;
+    const SMALLEST_TEXT: f32 = 14f32;
+
+    pub fn new(d: &RaylibDrawHandle) -> Self {
+        let width = d.get_screen_width() as f32;
+        let height = d.get_screen_height() as f32;
+        let scale = (width / Layout::DESIGN_WIDTH).min(height / Layout::DESIGN_HEIGHT)
+            .clamp(Layout::MIN_SCALE, Layout::MAX_SCALE);
+        Layout{
+            scale   : scale.max(Layout::MIN_TEXT_POINTS / 72f32 * monitor_dpi() / Layout::SMALLEST_TEXT),
+            screen  : Rectangle::new(0f32, 0f32, width, height),
+        }
+    }
+
+    /// Scales a length in design pixels.
+    pub fn px(&self, value: f32) -> f32 {
+        value * self.scale
+    }
+
+    pub fn rows(&self, area: Rectangle, sizes: &[ESize], spacing: f32) -> Vec<Rectangle> {
+        self.split(area.height, sizes, spacing).into_iter()
+            .map(|(offset, length)| Rectangle::new(area.x, area.y + offset, area.width, length))
+            .collect()
+    }
+
+    pub fn columns(&self, area: Rectangle, sizes: &[ESize], spacing: f32) -> Vec<Rectangle> {
+        self.split(area.width, sizes, spacing).into_iter()
+            .map(|(offset, length)| Rectangle::new(area.x + offset, area.y, length, area.height))
+            .collect()
+    }
+
+    /// Shrinks `area` by `margin` design pixels on every side.
+    pub fn inset(&self, area: Rectangle, margin: f32) -> Rectangle {
+        let margin = self.px(margin);
+        Rectangle::new(area.x + margin, area.y + margin, (area.width - margin * 2f32).max(0f32), (area.height - margin * 2f32).max(0f32))
+    }
+
+    /// Places a `width` x `height` (design pixels) rect at `anchor` inside `area`.
+    pub fn anchor(&self, area: Rectangle, anchor: EAnchor, width: f32, height: f32) -> Rectangle {
+        let width = self.px(width).min(area.width);
+        let height = self.px(height).min(area.height);
+        let (x, y) = match anchor {
+            EAnchor::ETopLeft => (area.x, area.y),
+            EAnchor::ETopRight => (area.x + area.width - width, area.y),
+        };
+        Rectangle::new(x, y, width, height)
+    }
+
+    /// Largest rect with the given aspect ratio (width / height) at the top left of `area`.
+    pub fn fit_aspect(&self, area: Rectangle, aspect: f32) -> Rectangle {
+        if area.width / area.height > aspect {
+            Rectangle::new(area.x, area.y, area.height * aspect, area.height)
+        } else {
+            Rectangle::new(area.x, area.y, area.width, area.width / aspect)
+        }
+    }
+
+    /// Offsets and lengths along one dimension. Fixed slots shrink proportionally when
+    /// they don't fit, so nothing overlaps on small windows.
+    fn split(&self, length: f32, sizes: &[ESize], spacing: f32) -> Vec<(f32, f32)> {
+        let spacing = self.px(spacing);
+        let available = (length - spacing * sizes.len().saturating_sub(1) as f32).max(0f32);
+        let fixed: f32 = sizes.iter().map(|size| match size {
+            ESize::EFixed(value) => self.px(*value),
+            ESize::EWeight(_) => 0f32,
+        }).sum();
+        let weights: f32 = sizes.iter().map(|size| match size {
+            ESize::EFixed(_) => 0f32,
+            ESize::EWeight(weight) => *weight,
+        }).sum();
+        let fixed_ratio = if fixed > available && fixed > 0f32 { available / fixed } else { 1f32 };
+        let flexible = (available - fixed * fixed_ratio).max(0f32);
+
+        let mut offset = 0f32;
+        sizes.iter().map(|size| {
+            let slot = match size {
+                ESize::EFixed(value) => self.px(*value) * fixed_ratio,
+                ESize::EWeight(weight) if weights > 0f32 => flexible * weight / weights,
+                ESize::EWeight(_) => 0f32,
+            };
+            let result = (offset, slot);
+            offset += slot + spacing;
+            result
+        }).collect()
+    }
+}
+
+/// Pixels per inch of the primary monitor, 96 if the monitor doesn't report its size.
+fn monitor_dpi() -> f32 {
+    let physical_width_mm = get_monitor_physical_width(0) as f32;
+    if physical_width_mm <= 0f32 {
+        return 96f32;
+    }
+    get_monitor_width(0) as f32 / (physical_width_mm / 25.4f32)
+}
@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use raylib::prelude::*;
+
+use cnc_desktop::cnc_bus::{EEventKind, Subscription};
+use cnc_desktop::cnc_ctrl::CncCtrl;
+use cnc_desktop::cnc_log::{self, format_timestamp, ELogLevel, LogEvent};
+
+use super::cnc_alarm_ui::draw_alarm_history;
+
+use super::cnc_config_ui::TextInput;
+use super::cnc_layout::{ESize, Layout};
+
+/// Controller events kept for the event list.
+const BUS_EVENTS: usize = 200;
+
+fn level_color(level: ELogLevel) -> Color {
+    match level {
+        ELogLevel::EError => Color::RED,
+        ELogLevel::EWarn => Color::ORANGE,
+        ELogLevel::EInfo => Color::BLACK,
+        ELogLevel::EDebug => Color::DARKGRAY,
+        ELogLevel::ETrace => Color::GRAY,
+    }
+}
+
+/// Scrollable view of the log with level and text filters, per module verbosity, controller events and the alarm history.
+pub struct CncLogUi {
+    events              : VecDeque<LogEvent>,
+    last_seen           : usize,
+    /// Most verbose level shown.
+    shown_level         : ELogLevel,
+    pub filter_input    : TextInput,
+    rect_levels         : Rectangle,
+    rect_btn_clear      : Rectangle,
+    rect_btn_follow     : Rectangle,
+    rect_lines          : Rectangle,
+    rect_modules        : Rectangle,
+    rect_alarms         : Rectangle,
+    rect_bus_events     : Rectangle,
+    /// Everything but positions and status samples, as seen on the status bus.
+    bus_events          : VecDeque<(SystemTime, String)>,
+    bus_subscription    : Option<Subscription>,
+    /// Lines scrolled up from the end.
+    scroll              : usize,
+    follow              : bool,
+    font_size           : f32,
+}
+
+impl CncLogUi {
+    pub fn new() -> Self {
+        CncLogUi{
+            events          : VecDeque::with_capacity(cnc_log::CAPACITY),
+            last_seen       : 0,
+            shown_level     : ELogLevel::ETrace,
+            filter_input    : TextInput::new(0f32, 0f32, 0f32, 0f32, "", 64),
+            rect_levels     : Rectangle::default(),
+            rect_btn_clear  : Rectangle::default(),
+            rect_btn_follow : Rectangle::default(),
+            rect_lines      : Rectangle::default(),
+            rect_modules    : Rectangle::default(),
+            rect_alarms     : Rectangle::default(),
+            rect_bus_events : Rectangle::default(),
+            bus_events      : VecDeque::with_capacity(BUS_EVENTS),
+            bus_subscription: None,
+            scroll          : 0,
+            follow          : true,
+            font_size       : 20f32,
+        }
+    }
+
+    pub fn layout(&mut self, layout: &Layout, area: Rectangle) {
+        let columns = layout.columns(area, &[ESize::EWeight(1f32), ESize::EFixed(480f32)], 20f32);
+        let rows = layout.rows(columns[0], &[ESize::EFixed(40f32), ESize::EWeight(1f32)], 10f32);
+        let tools = layout.columns(rows[0], &[ESize::EFixed(500f32), ESize::EFixed(300f32), ESize::EFixed(120f32), ESize::EFixed(120f32), ESize::EWeight(1f32)], 10f32);
+        // The toggle group lays out five toggles of this size side by side.
+        self.rect_levels = Rectangle::new(tools[0].x, tools[0].y, (tools[0].width - layout.px(8f32)) / 5f32, tools[0].height);
+        self.filter_input.rect = tools[1];
+        self.rect_btn_follow = tools[2];
+        self.rect_btn_clear = tools[3];
+        self.rect_lines = rows[1];
+        let side = layout.rows(columns[1], &[ESize::EWeight(1f32), ESize::EWeight(1f32), ESize::EWeight(1f32)], 20f32);
+        self.rect_modules = side[0];
+        self.rect_bus_events = side[1];
+        self.rect_alarms = side[2];
+        self.font_size = layout.px(18f32);
+    }
+
+    /// Copies events logged since the last frame.
+    fn poll(&mut self) {
+        let (events, last) = cnc_log::events_since(self.last_seen);
+        self.last_seen = last;
+        for event in events {
+            if self.events.len() == cnc_log::CAPACITY {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+    }
+
+    /// Takes the controller events published since the last frame.
+    fn poll_bus(&mut self, cnc: &CncCtrl) {
+        let subscription = self.bus_subscription.get_or_insert_with(|| cnc.bus.subscribe(&[EEventKind::EPIDParams, EEventKind::EAxisConfig,
+            EEventKind::EMachineInfo, EEventKind::EAlarm, EEventKind::EMachineState], BUS_EVENTS));
+        let now = SystemTime::now();
+        for event in subscription.drain() {
+            if self.bus_events.len() == BUS_EVENTS {
+                self.bus_events.pop_front();
+            }
+            self.bus_events.push_back((now, event.describe(|axis| cnc.axis_name(axis))));
+        }
+    }
+
+    fn matches(&self, event: &LogEvent, filter: &str) -> bool {
+        event.level <= self.shown_level
+            && (filter.is_empty() || event.message.to_lowercase().contains(filter) || event.module.to_lowercase().contains(filter))
+    }
+
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, cnc: &CncCtrl) {
+        self.poll();
+        self.poll_bus(cnc);
+
+        let shown = d.gui_toggle_group(self.rect_levels, Some(rstr!("ERROR;WARN;INFO;DEBUG;TRACE")), self.shown_level.index() as i32);
+        self.shown_level = ELogLevel::from_index(shown.max(0) as usize);
+        self.filter_input.update(d);
+        if self.filter_input.text().is_empty() && !self.filter_input.edit_mode {
+            d.draw_text_ex(font, "filter", Vector2::new(self.filter_input.rect.x + self.font_size * 0.5f32, self.filter_input.rect.y + self.font_size * 0.5f32),
+                self.font_size, 0f32, Color::GRAY);
+        }
+        self.follow = d.gui_toggle(self.rect_btn_follow, Some(rstr!("FOLLOW")), self.follow);
+        if d.gui_button(self.rect_btn_clear, Some(rstr!("CLEAR"))) {
+            self.events.clear();
+            self.scroll = 0;
+        }
+
+        self.draw_lines(d, font);
+        self.draw_modules(d, font);
+        self.draw_bus_events(d, font);
+        draw_alarm_history(d, font, self.rect_alarms, self.font_size, cnc);
+    }
+
+    fn draw_lines(&mut self, d: &mut RaylibDrawHandle, font: &Font) {
+        let filter = self.filter_input.text().to_lowercase();
+        let lines: Vec<&LogEvent> = self.events.iter().filter(|event| self.matches(event, &filter)).collect();
+        let line_height = self.font_size * 1.2f32;
+        let visible = ((self.rect_lines.height - self.font_size * 0.5f32) / line_height).floor().max(1f32) as usize;
+        let max_scroll = lines.len().saturating_sub(visible);
+
+        if self.rect_lines.check_collision_point_rec(d.get_mouse_position()) {
+            let wheel = d.get_mouse_wheel_move();
+            if wheel > 0 {
+                self.scroll = (self.scroll + wheel as usize * 3).min(max_scroll);
+                self.follow = false;
+            } else if wheel < 0 {
+                self.scroll = self.scroll.saturating_sub((-wheel) as usize * 3);
+            }
+        }
+        if self.follow {
+            self.scroll = 0;
+        }
+        self.scroll = self.scroll.min(max_scroll);
+
+        d.draw_rectangle_rec(self.rect_lines, Color::RAYWHITE);
+        d.draw_rectangle_lines_ex(self.rect_lines, 1, Color::GRAY);
+        let first = lines.len().saturating_sub(visible + self.scroll);
+        {
+            let area = self.rect_lines;
+            let mut d = d.begin_scissor_mode(area.x as i32, area.y as i32, area.width as i32, area.height as i32);
+            for (row, event) in lines.iter().skip(first).take(visible).enumerate() {
+                d.draw_text_ex(font, event.format().as_str(), Vector2::new(area.x + self.font_size * 0.3f32, area.y + self.font_size * 0.25f32 + row as f32 * line_height),
+                    self.font_size, 0f32, level_color(event.level));
+            }
+        }
+        if lines.len() > visible {
+            let position = format!("{}-{} of {}", first + 1, (first + visible).min(lines.len()), lines.len());
+            let size = measure_text_ex(font, position.as_str(), self.font_size, 0f32);
+            d.draw_text_ex(font, position.as_str(), Vector2::new(self.rect_lines.x + self.rect_lines.width - size.x - self.font_size * 0.5f32,
+                self.rect_lines.y - size.y - self.font_size * 0.3f32), self.font_size, 0f32, Color::DARKGRAY);
+        }
+    }
+
+    /// One row per module that has logged so far, with its verbosity.
+    fn draw_modules(&mut self, d: &mut RaylibDrawHandle, font: &Font) {
+        let area = self.rect_modules;
+        d.draw_text_ex(font, "Module verbosity", Vector2::new(area.x, area.y), self.font_size * 1.2f32, 0f32, Color::BLACK);
+        let row_height = self.font_size * 2.6f32;
+        let label_height = self.font_size * 1.1f32;
+        let toggle_width = area.width / 5f32 - 2f32;
+        let mut y = area.y + self.font_size * 2f32;
+        for (module, level) in cnc_log::module_levels() {
+            if y + row_height > area.y + area.height {
+                break;
+            }
+            d.draw_text_ex(font, module.as_str(), Vector2::new(area.x, y), self.font_size, 0f32, Color::DARKGRAY);
+            let rect = Rectangle::new(area.x, y + label_height, toggle_width, row_height - label_height - self.font_size * 0.3f32);
+            let selected = d.gui_toggle_group(rect, Some(rstr!("ERROR;WARN;INFO;DEBUG;TRACE")), level.index() as i32);
+            if selected >= 0 && selected as usize != level.index() {
+                cnc_log::set_module_level(&module, ELogLevel::from_index(selected as usize));
+            }
+            y += row_height;
+        }
+    }
+
+    /// Most recent controller events first.
+    fn draw_bus_events(&mut self, d: &mut RaylibDrawHandle, font: &Font) {
+        let area = self.rect_bus_events;
+        let title = match self.bus_subscription.as_ref().map_or(0, |subscription| subscription.dropped()) {
+            0 => String::from("Controller events"),
+            dropped => format!("Controller events ({} dropped)", dropped),
+        };
+        d.draw_text_ex(font, title.as_str(), Vector2::new(area.x, area.y), self.font_size * 1.2f32, 0f32, Color::BLACK);
+        let line_height = self.font_size * 1.2f32;
+        let mut y = area.y + self.font_size * 2f32;
+        if self.bus_events.is_empty() {
+            d.draw_text_ex(font, "No events", Vector2::new(area.x, y), self.font_size, 0f32, Color::GRAY);
+            return;
+        }
+        let mut d = d.begin_scissor_mode(area.x as i32, area.y as i32, area.width as i32, area.height as i32);
+        for (at, text) in self.bus_events.iter().rev() {
+            if y + line_height > area.y + area.height {
+                break;
+            }
+            // The date is the same for every recent event, only the time is shown.
+            let time = format_timestamp(*at);
+            let time = time.split(' ').nth(1).unwrap_or(time.as_str());
+            d.draw_text_ex(font, format!("{} {}", time, text).as_str(), Vector2::new(area.x, y), self.font_size, 0f32, Color::DARKGRAY);
+            y += line_height;
+        }
+    }
+}
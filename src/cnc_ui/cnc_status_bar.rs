@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use raylib::prelude::*;
+
+use cnc_desktop::cnc_ctrl::CncCtrl;
+use cnc_desktop::cnc_link::LinkStats;
+use cnc_desktop::cnc_machine_state::EMachineState;
+
+use super::cnc_layout::{ESize, Layout};
+
+/// Messages older than this are shown as stale.
+const STALE_AGE: Duration = Duration::from_secs(2);
+/// How long a rejected command stays visible.
+const REJECTION_TIME: Duration = Duration::from_secs(5);
+
+fn state_color(state: EMachineState) -> Color {
+    match state {
+        EMachineState::EAlarm | EMachineState::EEStop => Color::RED,
+        EMachineState::ERunning | EMachineState::EJogging | EMachineState::EHoming => Color::DARKBLUE,
+        EMachineState::EHolding | EMachineState::EConnecting | EMachineState::EHandshaking => Color::ORANGE,
+        EMachineState::EIdle | EMachineState::EDisconnected => Color::BLACK,
+    }
+}
+
+fn format_age(age: Option<Duration>) -> String {
+    match age {
+        Some(age) if age < Duration::from_secs(10) => format!("{} ms", age.as_millis()),
+        Some(age) => format!("{} s", age.as_secs()),
+        None => String::from("-"),
+    }
+}
+
+fn format_link(link: &LinkStats) -> (String, String, String) {
+    let rates = format!("RX {:.0}/s  TX {:.0}/s", link.rx_rate, link.tx_rate);
+    let round_trip = match link.round_trip {
+        Some(round_trip) => format!("RTT {:.1} ms", round_trip.as_secs_f32() * 1000f32),
+        None => String::from("RTT -"),
+    };
+    let cycle_time = match link.cycle_time {
+        Some(cycle_time) => format!("CYCLE {} us", cycle_time),
+        None => String::from("CYCLE -"),
+    };
+    (rates, round_trip, cycle_time)
+}
+
+/// Connection and controller health along the bottom of the window.
+pub fn draw_status_bar(d: &mut RaylibDrawHandle, font: &Font, layout: &Layout, area: Rectangle, cnc: &CncCtrl) {
+    d.draw_rectangle_rec(area, Color::LIGHTGRAY);
+    d.draw_line_ex(Vector2::new(area.x, area.y), Vector2::new(area.x + area.width, area.y), 1f32, Color::GRAY);
+
+    let connected = cnc.is_connected();
+    let link_color = if connected { Color::DARKGREEN } else { Color::MAROON };
+    let age = cnc.link.last_message_age();
+    let stale = age.is_none_or(|age| age > STALE_AGE);
+    let (rates, round_trip, cycle_time) = format_link(&cnc.link);
+    let address = cnc.remote_address.clone().unwrap_or_else(|| String::from("-"));
+    let rejection = match cnc.last_rejection {
+        Some((ref reason, at)) if at.elapsed() < REJECTION_TIME => reason.clone(),
+        _ => String::new(),
+    };
+    let fields: [(String, Color); 7] = [
+        (address, Color::BLACK),
+        (rates, Color::BLACK),
+        (format!("LAST {}", format_age(age)), if connected && stale { Color::RED } else { Color::BLACK }),
+        (round_trip, Color::BLACK),
+        (cycle_time, Color::BLACK),
+        (cnc.state().name().to_string(), state_color(cnc.state())),
+        (rejection, Color::RED),
+    ];
+
+    let area = layout.inset(area, 4f32);
+    let columns = layout.columns(area, &[ESize::EFixed(160f32), ESize::EFixed(260f32), ESize::EFixed(220f32), ESize::EFixed(150f32),
+        ESize::EFixed(150f32), ESize::EFixed(170f32), ESize::EFixed(160f32), ESize::EWeight(1f32)], 10f32);
+    let font_size = layout.px(18f32);
+    let text_y = area.y + (area.height - font_size) / 2f32;
+
+    let radius = font_size * 0.3f32;
+    d.draw_circle_v(Vector2::new(columns[0].x + radius, text_y + font_size / 2f32), radius, link_color);
+    d.draw_text_ex(font, if connected { "ONLINE" } else { "OFFLINE" }, Vector2::new(columns[0].x + radius * 3f32, text_y), font_size, 0f32, link_color);
+    for ((text, color), column) in fields.iter().zip(columns.iter().skip(1)) {
+        if column.width > 0f32 {
+            d.draw_text_ex(font, text.as_str(), Vector2::new(column.x, text_y), font_size, 0f32, *color);
+        }
+    }
+}
@@ -1,14 +1,17 @@
 use raylib::{prelude::*, text::{Font, FontLoadEx}, RaylibHandle};
 
-use crate::{cnc_ctrl::CncCtrl, cnc_connection::CncConnectionManager};
+use cnc_desktop::{cnc_ctrl::CncCtrl, cnc_connection::CncConnectionManager, cnc_msg::AxisInfo};
+use cnc_desktop::log_error;
 
-use super::{cnc_ctrl_ui::CncCtrlUi, cnc_config_ui::CncConfigUi, cnc_connection_ui::{configure_ip, GuiIpAddress}};
+use super::{cnc_ctrl_ui::CncCtrlUi, cnc_config_ui::CncConfigUi, cnc_connection_ui::{configure_host, CncAxesUi, GuiHostAddress}, cnc_layout::{ESize, Layout}, cnc_log_ui::CncLogUi, cnc_status_bar::draw_status_bar,
+    cnc_alarm_ui::{alarm_banner_height, draw_alarm_banner}};
 
 
 pub enum EAppState {
     EConfigureIpAddress,
     ECncControl,
     ECncConfig,
+    ELog,
 }
 
 
@@ -16,46 +19,58 @@ pub struct CncUi {
     pub title: String,
     pub font: Font,
     pub app_state: EAppState,
-    pub connection: bool,
-    pub btn_tabs: [Rectangle; 3],
-    pub ip_address: GuiIpAddress,
+    pub btn_tabs: [Rectangle; 4],
+    pub host_address: GuiHostAddress,
+    pub axes_ui: CncAxesUi,
     pub ctrl_ui: CncCtrlUi,
     pub config_ui: CncConfigUi,
+    pub log_ui: CncLogUi,
 }
 
 impl CncUi {
-    pub fn new(rl: &mut RaylibHandle, thread: &raylib::RaylibThread) -> Self {
+    pub fn new(rl: &mut RaylibHandle, thread: &raylib::RaylibThread, axes: &[AxisInfo]) -> Self {
         let font_char_set = FontLoadEx::Default(127) ;
-        let font_24 = rl.load_font_ex(&thread, "./data/fonts/iosevka-fixed-regular.ttf",
+        let font_24 = rl.load_font_ex(thread, "./data/fonts/iosevka-fixed-regular.ttf",
         24, font_char_set).expect("Failed to load the font");
         rl.gui_set_font(&font_24);
         
-        let x_pos = 600f32;
-        let y_pos = 30f32;
-        let btn_w = 150f32;
-        let btn_h = 50f32;
-        let btn_connection = Rectangle::new(x_pos, y_pos, btn_w, btn_h);
-        let btn_ctrl = Rectangle::new(x_pos + btn_w * 1f32, y_pos, btn_w, btn_h);
-        let btn_config = Rectangle::new(x_pos + btn_w * 2f32, y_pos, btn_w, btn_h);
-        
-        
+        let btn_tab = Rectangle::default();
+
         CncUi{
             title: String::from("CNC"),
             font : font_24,
             app_state: EAppState::EConfigureIpAddress,
-            connection: false,
-            btn_tabs: [btn_connection, btn_ctrl, btn_config],
-            ip_address: GuiIpAddress::new(),
-            ctrl_ui: CncCtrlUi::new(),
+            btn_tabs: [btn_tab; 4],
+            host_address: GuiHostAddress::new(),
+            axes_ui: CncAxesUi::new(axes),
+            ctrl_ui: CncCtrlUi::new(axes),
             config_ui: CncConfigUi::new(),
+            log_ui: CncLogUi::new(),
         }
     }
     
     pub fn update(&mut self, d: &mut raylib::prelude::RaylibDrawHandle, cnc: &mut CncCtrl, connection_manager: &mut CncConnectionManager) {
+        // Applied on every tab, so the status bar, alarms and handshake never go stale.
+        cnc.update_status();
+        self.ctrl_ui.poll(cnc);
+
+        let layout = Layout::new(d);
+        d.gui_set_style(GuiControl::DEFAULT, GuiDefaultProperty::TEXT_SIZE as i32, layout.px(20f32) as i32);
+
+        let regions = layout.rows(layout.screen, &[ESize::EFixed(80f32), ESize::EFixed(alarm_banner_height(cnc)), ESize::EWeight(1f32), ESize::EFixed(32f32)], 0f32);
+        let header = layout.inset(regions[0], 10f32);
+        let header_columns = layout.columns(header, &[ESize::EFixed(590f32), ESize::EFixed(150f32), ESize::EFixed(150f32),
+            ESize::EFixed(150f32), ESize::EFixed(150f32), ESize::EWeight(1f32)], 0f32);
+        self.btn_tabs = [header_columns[1], header_columns[2], header_columns[3], header_columns[4]];
+        for tab in self.btn_tabs.iter_mut() {
+            tab.y += layout.px(20f32);
+            tab.height = (tab.height - layout.px(20f32)).max(0f32);
+        }
+        let content = layout.inset(regions[2], 20f32);
 
         d.draw_text_ex(&self.font,
             &self.title, 
-            Vector2::new(12.0f32, 12.0f32), 50.0f32, 0f32, Color::BLACK);
+            Vector2::new(header.x, header.y), layout.px(50.0f32), 0f32, Color::BLACK);
             
         if d.gui_button(self.btn_tabs[0], Some(rstr!("Connection"))) {
             self.set_state(EAppState::EConfigureIpAddress);
@@ -66,32 +81,50 @@ impl CncUi {
         if d.gui_button(self.btn_tabs[2], Some(rstr!("Configuration"))) {
             self.set_state(EAppState::ECncConfig);
         }
+        if d.gui_button(self.btn_tabs[3], Some(rstr!("Log"))) {
+            self.set_state(EAppState::ELog);
+        }
             
         let accent_height = self.btn_tabs[0].height as i32 / 10;
         match self.app_state {
             EAppState::EConfigureIpAddress => {
                 d.draw_rectangle( self.btn_tabs[0].x as i32  , (self.btn_tabs[0].y + self.btn_tabs[0].height) as i32 - accent_height, self.btn_tabs[0].width as i32 , accent_height, Color::DARKGRAY);
-                if let Some(stream) = configure_ip(d, &self.font, &mut self.ip_address) {
+                let connection_area = layout.rows(content, &[ESize::EFixed(430f32), ESize::EWeight(1f32)], 20f32);
+                self.host_address.layout(&layout, connection_area[0]);
+                self.axes_ui.layout(&layout, connection_area[1]);
+                if let Some(stream) = configure_host(d, &self.font, &mut self.host_address, connection_manager) {
                     
-                    let connection = connection_manager.run(stream);
-                    cnc.set_connection(connection);
+                    let remote_address = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+                    let connection = connection_manager.run(stream, cnc.connection_axis_count());
+                    if let Err(e) = cnc.set_connection(connection, remote_address) {
+                        log_error!("{}", e);
+                    }
 
                     // self.app_state = EAppState::ECncControl;
                     self.set_state(EAppState::EConfigureIpAddress);
                 }
+                self.axes_ui.draw(d, &self.font, cnc);
                 
             },
             EAppState::ECncControl => {
                 d.draw_rectangle( self.btn_tabs[1].x as i32 , (self.btn_tabs[1].y + self.btn_tabs[0].height)as i32  - accent_height, self.btn_tabs[1].width as i32 , accent_height, Color::DARKGRAY);
-                cnc.update_status();
+                self.ctrl_ui.layout(&layout, content, cnc);
                 self.ctrl_ui.draw(d, &self.font, cnc);
             },
             EAppState::ECncConfig => {
                 d.draw_rectangle( self.btn_tabs[2].x as i32 , (self.btn_tabs[2].y  + self.btn_tabs[0].height)  as i32 - accent_height, self.btn_tabs[2].width as i32 , accent_height, Color::DARKGRAY);
-                cnc.update_status();
+                self.config_ui.layout(&layout, content, cnc);
                 self.config_ui.draw(d, &self.font, cnc);
             },
+            EAppState::ELog => {
+                d.draw_rectangle( self.btn_tabs[3].x as i32 , (self.btn_tabs[3].y  + self.btn_tabs[0].height)  as i32 - accent_height, self.btn_tabs[3].width as i32 , accent_height, Color::DARKGRAY);
+                self.log_ui.layout(&layout, content);
+                self.log_ui.draw(d, &self.font, cnc);
+            },
         }
+
+        draw_alarm_banner(d, &self.font, &layout, regions[1], cnc);
+        draw_status_bar(d, &self.font, &layout, regions[3], cnc);
     }
     
     pub fn set_state(&mut self, state: EAppState) {
@@ -99,13 +132,16 @@ impl CncUi {
         
         self.title = match self.app_state {
             EAppState::EConfigureIpAddress => {
-                String::from("Configure IP Address")
+                String::from("Configure Connection")
             },
             EAppState::ECncControl => {
                 String::from("CNC Control")
             },
             EAppState::ECncConfig => {
                 String::from("Config Parameters")
+            },
+            EAppState::ELog => {
+                String::from("Log")
             }
         }
     }
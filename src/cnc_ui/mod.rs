@@ -2,4 +2,10 @@
 pub mod cnc_ctrl_ui;
 pub mod cnc_connection_ui;
 pub mod cnc_config_ui;
-pub mod cnc_ui;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod cnc_ui;
+pub mod cnc_layout;
+pub mod cnc_log_ui;
+pub mod cnc_status_bar;
+pub mod cnc_alarm_ui;
+pub mod cnc_following_error_ui;
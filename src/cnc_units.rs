@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cnc_msg::EAxisKind;
+
+pub const MM_PER_INCH: f32 = 25.4f32;
+
+/// Display/input units. Everything inside `CncCtrl` and on the wire stays in mm
+/// (degrees for rotary axes), this only affects what the user sees and types.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EUnits {
+    EMillimeters,
+    EInches,
+}
+
+impl EUnits {
+    pub fn to_units(self, kind: EAxisKind, value: f32) -> f32 {
+        match (self, kind) {
+            (EUnits::EInches, EAxisKind::ELinear) => value / MM_PER_INCH,
+            _ => value,
+        }
+    }
+
+    pub fn to_mm(self, kind: EAxisKind, value: f32) -> f32 {
+        match (self, kind) {
+            (EUnits::EInches, EAxisKind::ELinear) => value * MM_PER_INCH,
+            _ => value,
+        }
+    }
+
+    /// Decimal places shown, chosen so both units resolve to about a micron.
+    pub fn decimals(self, kind: EAxisKind) -> usize {
+        match (self, kind) {
+            (_, EAxisKind::ERotary) => 3,
+            (EUnits::EMillimeters, EAxisKind::ELinear) => 3,
+            (EUnits::EInches, EAxisKind::ELinear) => 4,
+        }
+    }
+
+    pub fn suffix(self, kind: EAxisKind) -> &'static str {
+        match (self, kind) {
+            (_, EAxisKind::ERotary) => "deg",
+            (EUnits::EMillimeters, EAxisKind::ELinear) => "mm",
+            (EUnits::EInches, EAxisKind::ELinear) => "in",
+        }
+    }
+
+    /// Formats a machine value (mm or degrees) in these units with their precision.
+    pub fn format(self, kind: EAxisKind, value: f32) -> String {
+        format!("{:.*}", self.decimals(kind), self.to_units(kind, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_linear_axes_convert() {
+        let cases = [
+            (EUnits::EMillimeters, EAxisKind::ELinear, 25.4f32, 25.4f32, "25.400 mm"),
+            (EUnits::EInches, EAxisKind::ELinear, 25.4f32, 1f32, "1.0000 in"),
+            (EUnits::EInches, EAxisKind::ERotary, 90f32, 90f32, "90.000 deg"),
+            (EUnits::EMillimeters, EAxisKind::ERotary, 90f32, 90f32, "90.000 deg"),
+        ];
+        for (units, kind, mm, shown, text) in cases {
+            assert!((units.to_units(kind, mm) - shown).abs() < 1e-5, "{:?} {:?}", units, kind);
+            assert!((units.to_mm(kind, shown) - mm).abs() < 1e-4, "{:?} {:?}", units, kind);
+            assert_eq!(format!("{} {}", units.format(kind, mm), units.suffix(kind)), text);
+        }
+    }
+}
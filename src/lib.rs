@@ -0,0 +1,27 @@
+//! Everything but the raylib UI, shared by the desktop app and the command-line client.
+
+#![allow(clippy::enum_variant_names)]
+
+pub mod cnc_log;
+pub mod thread_pool;
+pub mod cnc_ctrl;
+pub mod cnc_connection;
+pub mod cnc_msg;
+pub mod cnc_pid_history;
+pub mod cnc_profile;
+pub mod cnc_units;
+pub mod cnc_gcode;
+pub mod cnc_toolpath;
+pub mod cnc_job;
+pub mod cnc_job_sim;
+pub mod cnc_job_stats;
+pub mod cnc_trail;
+pub mod cnc_recent;
+pub mod cnc_link;
+pub mod cnc_machine_state;
+pub mod cnc_sim;
+pub mod cnc_alarm;
+pub mod cnc_following_error;
+pub mod cnc_bus;
+pub mod cnc_api;
+pub mod cnc_macro;
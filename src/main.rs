@@ -1,17 +1,39 @@
+#![allow(clippy::enum_variant_names)]
 
-
-use cnc_connection::{CncConnection, CncConnectionManager};
-use cnc_ctrl::{CncCtrl};
+use cnc_desktop::{cnc_api, cnc_log, cnc_msg, cnc_sim, log_error};
+use cnc_desktop::cnc_connection::CncConnectionManager;
+use cnc_desktop::cnc_ctrl::CncCtrl;
 use raylib::prelude::*;
-use cnc_ui::{cnc_connection_ui::{GuiIpAddress, configure_ip}, cnc_ctrl_ui::CncCtrlUi, cnc_config_ui::CncConfigUi, cnc_ui::CncUi};
+use cnc_ui::cnc_ui::CncUi;
 
-mod thread_pool;
-mod cnc_ctrl;
 mod cnc_ui;
-mod cnc_connection;
-mod cnc_msg;
 
 fn main() {
+    cnc_log::init();
+
+    // `--sim` starts a simulated controller on loopback to test without hardware.
+    if std::env::args().any(|arg| arg == "--sim") {
+        if let Err(e) = cnc_sim::CncSimulator::new(cnc_msg::AxisInfo::default_axes()).spawn(cnc_sim::SIM_PORT) {
+            log_error!("{}", e);
+        }
+    }
+
+    // `--api` serves the local automation API, `--api-address` on another address than the default.
+    let args: Vec<String> = std::env::args().collect();
+    let api_address = match args.iter().position(|arg| arg == "--api-address") {
+        Some(index) => match args.get(index + 1).filter(|address| !address.starts_with("--")) {
+            Some(address) => Some(address.clone()),
+            None => exit_with_error("--api-address needs an address like 127.0.0.1:5560"),
+        },
+        None if args.iter().any(|arg| arg == "--api") => Some(String::from(cnc_api::API_DEFAULT_ADDRESS)),
+        None => None,
+    };
+    // Asked for an API that can't run, better not to start without it.
+    let mut api_server = match api_address.map(|address| cnc_api::ApiServer::start(&address)) {
+        Some(Ok(server)) => Some(server),
+        Some(Err(e)) => exit_with_error(&e),
+        None => None,
+    };
 
     let (mut rl, thread) = raylib::init()
         .size(1920/6*5, 1080/9*8)
@@ -25,7 +47,7 @@ fn main() {
 
     let mut connection_manager = CncConnectionManager::new();
 
-    let mut cnc_ui = CncUi::new(&mut rl, &thread);
+    let mut cnc_ui = CncUi::new(&mut rl, &thread, &cnc_ctrl.axes);
     
     while !rl.window_should_close() {
         let mut d = rl.begin_drawing(&thread);
@@ -33,6 +55,20 @@ fn main() {
         d.clear_background(Color::WHITE);
 
         cnc_ui.update(&mut d, &mut cnc_ctrl, &mut connection_manager);
+        if let Some(ref mut api_server) = api_server {
+            api_server.update(&mut cnc_ctrl);
+        }
+    }
+    if let Some(ref mut api_server) = api_server {
+        api_server.shutdown();
     }
     cnc_ctrl.quit();
+    connection_manager.shutdown();
+    cnc_log::flush();
+}
+
+fn exit_with_error(message: &str) -> ! {
+    log_error!("{}", message);
+    cnc_log::flush();
+    std::process::exit(2);
 }
@@ -1,11 +1,16 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
+use crate::{log_debug, log_error};
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: mpsc::Sender<EMessage>,
+    sender: Option<mpsc::Sender<EMessage>>,
+    /// Handles of jobs started with `spawn`, cancelled on shutdown.
+    handles: Vec<JobHandle>,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
@@ -15,6 +20,44 @@ enum EMessage {
     ETerminate,
 }
 
+/// Shared between a long running job and its owner. The job polls `is_cancelled`
+/// and returns, freeing its worker; the pool marks it finished however it ends.
+#[derive(Clone)]
+pub struct JobHandle {
+    cancelled: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+}
+
+impl JobHandle {
+    fn new() -> Self {
+        JobHandle {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            finished: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
+
+/// Marks a job finished when dropped, also while unwinding from a panic.
+struct FinishedOnDrop(Arc<AtomicBool>);
+
+impl Drop for FinishedOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -36,39 +79,80 @@ impl ThreadPool {
             workers.push(Worker::new(id, Arc::clone(&receiver)));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool { workers, sender: Some(sender), handles: Vec::new() }
     }
 
-    pub fn execute<F>(&self, f: F)
+    pub fn execute<F>(&self, f: F) -> Result<(), String>
     where
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
 
-        self.sender.send(EMessage::ENewJob(job)).unwrap();
+        match self.sender {
+            Some(ref sender) => sender.send(EMessage::ENewJob(job)).map_err(|_| String::from("Thread pool has no workers left")),
+            None => Err(String::from("Thread pool is shut down")),
+        }
     }
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
+    /// Queues a job that runs until it returns or is cancelled through the returned handle.
+    pub fn spawn<F>(&mut self, f: F) -> Result<JobHandle, String>
+    where
+        F: FnOnce(JobHandle) + Send + 'static,
+    {
+        let handle = JobHandle::new();
+        let job_handle = handle.clone();
+        self.execute(move || {
+            let _finished = FinishedOnDrop(Arc::clone(&job_handle.finished));
+            f(job_handle);
+        })?;
+        self.handles.retain(|handle| !handle.is_finished());
+        self.handles.push(handle.clone());
+        Ok(handle)
+    }
+
+    /// Jobs started with `spawn` that haven't finished yet.
+    pub fn active_jobs(&self) -> usize {
+        self.handles.iter().filter(|handle| !handle.is_finished()).count()
+    }
+
+    /// Cancels the spawned jobs, lets queued jobs run and joins all workers.
+    /// Later calls do nothing.
+    pub fn shutdown(&mut self) {
+        let sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => return,
+        };
+        for handle in self.handles.drain(..) {
+            handle.cancel();
+        }
+
+        log_debug!("Sending terminate message to all workers.");
 
         for _ in &self.workers {
-            self.sender.send(EMessage::ETerminate).unwrap();
+            // A worker that is gone has nothing left to terminate.
+            let _ = sender.send(EMessage::ETerminate);
         }
 
-        println!("Shutting down all workers.");
+        log_debug!("Shutting down all workers.");
 
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            log_debug!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if thread.join().is_err() {
+                    log_error!("Worker {} panicked", worker.id);
+                }
             }
         }
     }
 }
 
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
 struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
@@ -77,16 +161,25 @@ struct Worker {
 impl Worker {
     fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<EMessage>>>) -> Worker {
         let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+            // Only `recv` runs under the lock, so it can't be poisoned by a job.
+            let message = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv();
 
             match message {
-                EMessage::ENewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
+                Ok(EMessage::ENewJob(job)) => {
+                    log_debug!("Worker {} got a job; executing.", id);
+
+                    // A panicking job must not take the worker with it.
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log_error!("Job on worker {} panicked", id);
+                    }
+                }
+                Ok(EMessage::ETerminate) => {
+                    log_debug!("Worker {} was told to terminate.", id);
 
-                    job();
+                    break;
                 }
-                EMessage::ETerminate => {
-                    println!("Worker {} was told to terminate.", id);
+                Err(_) => {
+                    log_debug!("Worker {} lost its pool, terminating.", id);
 
                     break;
                 }
@@ -99,3 +192,74 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn drop_joins_idle_workers() {
+        let pool = ThreadPool::new(2);
+        drop(pool);
+    }
+
+    #[test]
+    fn drop_runs_queued_jobs() {
+        let (tx, rx) = mpsc::channel();
+        {
+            let pool = ThreadPool::new(1);
+            for i in 0..3 {
+                let tx = tx.clone();
+                pool.execute(move || tx.send(i).unwrap()).unwrap();
+            }
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<i32>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn cancelled_job_frees_its_worker() {
+        let mut pool = ThreadPool::new(1);
+        let handle = pool.spawn(|job| {
+            while !job.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }).unwrap();
+        assert_eq!(pool.active_jobs(), 1);
+
+        handle.cancel();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        assert!(rx.recv_timeout(TIMEOUT).is_ok());
+        assert!(handle.is_finished());
+        assert_eq!(pool.active_jobs(), 0);
+    }
+
+    #[test]
+    fn panicking_job_frees_its_worker() {
+        let mut pool = ThreadPool::new(1);
+        let handle = pool.spawn(|_| panic!("job failed")).unwrap();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        assert!(rx.recv_timeout(TIMEOUT).is_ok());
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn shutdown_cancels_running_jobs() {
+        let mut pool = ThreadPool::new(2);
+        let handles: Vec<JobHandle> = (0..2).map(|_| pool.spawn(|job| {
+            while !job.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }).unwrap()).collect();
+
+        pool.shutdown();
+        assert!(handles.iter().all(|handle| handle.is_cancelled() && handle.is_finished()));
+        assert!(pool.execute(|| {}).is_err());
+        // Dropping after an explicit shutdown does nothing.
+        drop(pool);
+    }
+}